@@ -0,0 +1,102 @@
+// With the default-book feature, turns books/default.txt into a Polyglot book in OUT_DIR for
+// src/polyglot.rs to include_bytes!. Every line is a variation in SAN, each time a move shows up
+// in a position its weight goes up by one. Lines starting with # are comments. Also works out the
+// version and build info for src/version.rs.
+use shakmaty::{
+    san::San,
+    zobrist::{Zobrist64, ZobristHash},
+    Chess, EnPassantMode, Move, Position, Role,
+};
+use std::{collections::BTreeMap, env, fs, path::Path, process::Command};
+
+fn main() {
+    println!("cargo:rerun-if-changed=books/default.txt");
+    build_info();
+    if env::var_os("CARGO_FEATURE_DEFAULT_BOOK").is_none() {
+        return;
+    }
+
+    let text = fs::read_to_string("books/default.txt").expect("Couldn't read books/default.txt");
+    let mut weights: BTreeMap<(u64, u16), u16> = BTreeMap::new();
+    for (line_no, line) in text.lines().enumerate() {
+        if line.trim_start().starts_with('#') {
+            continue;
+        }
+        let mut position = Chess::default();
+        for token in line.split_whitespace() {
+            let chess_move = token
+                .parse::<San>()
+                .ok()
+                .and_then(|san| san.to_move(&position).ok())
+                .unwrap_or_else(|| panic!("books/default.txt:{}: bad move {}", line_no + 1, token));
+            let key = position.zobrist_hash::<Zobrist64>(EnPassantMode::Legal).0;
+            *weights.entry((key, encode(&chess_move))).or_insert(0) += 1;
+            position.play_unchecked(&chess_move);
+        }
+    }
+
+    // 16 byte big endian entries sorted by key: key, move, weight, learn
+    let mut book = Vec::new();
+    for ((key, chess_move), weight) in weights {
+        book.extend_from_slice(&key.to_be_bytes());
+        book.extend_from_slice(&chess_move.to_be_bytes());
+        book.extend_from_slice(&weight.to_be_bytes());
+        book.extend_from_slice(&0u32.to_be_bytes());
+    }
+    let out = Path::new(&env::var("OUT_DIR").unwrap()).join("default-book.bin");
+    fs::write(out, book).expect("Couldn't write the default book");
+}
+
+// SHALLOW_RED_VERSION is the Cargo version plus the commit when there's a git checkout to ask,
+// SHALLOW_RED_BUILD the target, profile and features
+fn build_info() {
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+    let mut version = env::var("CARGO_PKG_VERSION").unwrap();
+    if let Some(hash) = git(&["rev-parse", "--short", "HEAD"]) {
+        version += &format!("+{}", hash);
+    }
+    println!("cargo:rustc-env=SHALLOW_RED_VERSION={}", version);
+
+    let mut features: Vec<String> = env::vars()
+        .filter_map(|(key, _)| {
+            key.strip_prefix("CARGO_FEATURE_")
+                .map(|feature| feature.to_lowercase().replace('_', "-"))
+        })
+        .collect();
+    features.sort();
+    let features = match features.is_empty() {
+        true => "none".to_string(),
+        false => features.join(" "),
+    };
+    println!(
+        "cargo:rustc-env=SHALLOW_RED_BUILD={} {} build, features: {}",
+        env::var("TARGET").unwrap(),
+        env::var("PROFILE").unwrap(),
+        features
+    );
+}
+
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let out = String::from_utf8(output.stdout).ok()?;
+    Some(out.trim().to_string()).filter(|out| !out.is_empty())
+}
+
+// Polyglot move bits: to file, to rank, from file, from rank, promotion. Castling comes out as the
+// king taking its own rook, which is how shakmaty's Move::to already reports it.
+fn encode(chess_move: &Move) -> u16 {
+    let from = u32::from(chess_move.from().expect("Book moves aren't drops")) as u16;
+    let to = u32::from(chess_move.to()) as u16;
+    let promotion = match chess_move.promotion() {
+        Some(Role::Knight) => 1,
+        Some(Role::Bishop) => 2,
+        Some(Role::Rook) => 3,
+        Some(Role::Queen) => 4,
+        _ => 0,
+    };
+    (to % 8) | (to / 8) << 3 | (from % 8) << 6 | (from / 8) << 9 | promotion << 12
+}
@@ -0,0 +1,47 @@
+// The engine behind a trait, so the UCI layer can be tested against a predictable stand-in
+use chess::{Board, ChessMove, MoveGen};
+use log::info;
+use shallow_red_engine::{engine::enter_engine, utils::engine_interface::EngineSettings};
+
+use crate::display::Score;
+
+pub trait SearchBackend: Send + Sync {
+    fn search(&self, board: Board, settings: EngineSettings) -> SearchOutcome;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SearchOutcome {
+    pub best_move: ChessMove,
+    pub score: Option<Score>, // From the side to move's point of view, None when the backend can't say
+    pub nodes: Option<u64>,   // Nodes searched, when the backend counts them
+}
+
+// The real thing
+pub struct ShallowRed;
+
+impl SearchBackend for ShallowRed {
+    fn search(&self, board: Board, settings: EngineSettings) -> SearchOutcome {
+        let (best_move, search_results) = enter_engine(board, settings);
+        if let Some(results) = search_results {
+            info!("Search finished with results: {:#?}", results)
+        }
+        SearchOutcome {
+            best_move,
+            score: None, // Results are only good for logging
+            nodes: None,
+        }
+    }
+}
+
+// Plays the first legal move straight away, for tests
+pub struct MockBackend;
+
+impl SearchBackend for MockBackend {
+    fn search(&self, board: Board, _settings: EngineSettings) -> SearchOutcome {
+        SearchOutcome {
+            best_move: MoveGen::new_legal(&board).next().unwrap_or_default(),
+            score: None,
+            nodes: None,
+        }
+    }
+}
@@ -0,0 +1,265 @@
+// bench: a fixed search over a set of positions, timed, and saved as a named baseline to compare
+// against later. The engine reports no node counts, so it's the time each position takes and
+// positions per second: a drop in speed past the tolerance is a regression, and a different
+// bestmove is shown but not counted, since searches on the clock aren't reproducible.
+use chess::Board;
+use serde::{Deserialize, Serialize};
+use shallow_red_engine::utils::engine_interface::EngineSettings;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use crate::backend::SearchBackend;
+
+const BENCH_TIME: Duration = Duration::from_millis(200); // Per position
+pub const DEFAULT_SPEED_TOLERANCE: f64 = 5.0; // Percent
+pub const DEFAULT_BASELINE_DIR: &str = "bench-baselines";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PositionResult {
+    pub fen: String,
+    pub bestmove: String,
+    pub time_ms: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BenchResult {
+    pub version: String,
+    pub position_set: String, // Hash of the positions' FENs, baselines from another set are stale
+    pub positions: Vec<PositionResult>,
+    pub total_ms: u64,
+    pub positions_per_second: f64,
+}
+
+impl BenchResult {
+    pub fn new(positions: Vec<PositionResult>, elapsed: Duration) -> BenchResult {
+        let fens: Vec<&str> = positions
+            .iter()
+            .map(|position| position.fen.as_str())
+            .collect();
+        BenchResult {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            position_set: position_set_hash(&fens),
+            positions_per_second: positions.len() as f64 / elapsed.as_secs_f64().max(1e-3),
+            positions,
+            total_ms: elapsed.as_millis() as u64,
+        }
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines: Vec<String> = self
+            .positions
+            .iter()
+            .enumerate()
+            .map(|(i, position)| {
+                format!(
+                    "position {} bestmove {} time {}ms",
+                    i + 1,
+                    position.bestmove,
+                    position.time_ms
+                )
+            })
+            .collect();
+        lines.push(format!(
+            "total time {}ms, {:.2} positions/s",
+            self.total_ms, self.positions_per_second
+        ));
+        lines
+    }
+}
+
+// FNV-1a, std's hasher isn't guaranteed to stay the same between releases
+fn position_set_hash(fens: &[&str]) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in fens.join("\n").bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}", hash)
+}
+
+pub fn run_bench(backend: &dyn SearchBackend, positions: &[Board]) -> BenchResult {
+    let start = Instant::now();
+    let mut results = Vec::new();
+    for board in positions {
+        let settings = EngineSettings {
+            verbose: false,
+            time_limit: BENCH_TIME,
+            ..Default::default()
+        };
+        let searched = Instant::now();
+        let bestmove = backend.search(*board, settings).best_move;
+        results.push(PositionResult {
+            fen: board.to_string(),
+            bestmove: bestmove.to_string(),
+            time_ms: searched.elapsed().as_millis() as u64,
+        });
+    }
+    BenchResult::new(results, start.elapsed())
+}
+
+fn baseline_path(dir: &Path, name: &str) -> PathBuf {
+    dir.join(format!("{}.json", name))
+}
+
+pub fn save_baseline(dir: &Path, name: &str, result: &BenchResult) -> Result<PathBuf, String> {
+    let path = baseline_path(dir, name);
+    let text = serde_json::to_string_pretty(result).expect("Bench result should serialize");
+    fs::create_dir_all(dir)
+        .and_then(|_| fs::write(path.with_extension("json.tmp"), text))
+        .and_then(|_| fs::rename(path.with_extension("json.tmp"), &path))
+        .map_err(|e| format!("can't save {}: {}", path.display(), e))?;
+    Ok(path)
+}
+
+pub fn load_baseline(dir: &Path, name: &str) -> Result<BenchResult, String> {
+    let path = baseline_path(dir, name);
+    let text =
+        fs::read_to_string(&path).map_err(|e| format!("can't read {}: {}", path.display(), e))?;
+    serde_json::from_str(&text).map_err(|e| format!("bad baseline {}: {}", path.display(), e))
+}
+
+#[derive(Debug, Default)]
+pub struct Comparison {
+    pub lines: Vec<String>,
+    pub failures: Vec<String>,
+}
+
+impl Comparison {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+// Err when the baseline can't be compared against at all
+pub fn compare(
+    baseline: &BenchResult,
+    current: &BenchResult,
+    speed_tolerance: f64,
+) -> Result<Comparison, String> {
+    if baseline.version != current.version {
+        return Err(format!(
+            "stale baseline, recorded by version {} not {}",
+            baseline.version, current.version
+        ));
+    }
+    if baseline.position_set != current.position_set {
+        return Err("stale baseline, recorded on a different position set".to_string());
+    }
+
+    let mut comparison = Comparison::default();
+    for (i, (before, after)) in baseline
+        .positions
+        .iter()
+        .zip(&current.positions)
+        .enumerate()
+    {
+        let mut line = format!(
+            "position {} time {}ms -> {}ms ({:+}ms)",
+            i + 1,
+            before.time_ms,
+            after.time_ms,
+            after.time_ms as i64 - before.time_ms as i64
+        );
+        if before.bestmove != after.bestmove {
+            line += &format!(", bestmove {} -> {}", before.bestmove, after.bestmove);
+        }
+        comparison.lines.push(line);
+    }
+    comparison.lines.push(format!(
+        "total time {}ms -> {}ms ({:+}ms)",
+        baseline.total_ms,
+        current.total_ms,
+        current.total_ms as i64 - baseline.total_ms as i64
+    ));
+    let speed_change = if baseline.positions_per_second == 0.0 {
+        0.0
+    } else {
+        (current.positions_per_second - baseline.positions_per_second)
+            / baseline.positions_per_second
+            * 100.0
+    };
+    comparison.lines.push(format!(
+        "positions/s {:.2} -> {:.2} ({:+.1}%)",
+        baseline.positions_per_second, current.positions_per_second, speed_change
+    ));
+
+    if speed_change < -speed_tolerance {
+        comparison.failures.push(format!(
+            "positions/s dropped {:.1}%, more than the {}% tolerance",
+            -speed_change, speed_tolerance
+        ));
+    }
+    Ok(comparison)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Each position taking `time_ms`, and the whole run `seconds`
+    fn fake(moves: &[&str], time_ms: u64, seconds: u64) -> BenchResult {
+        let positions = moves
+            .iter()
+            .enumerate()
+            .map(|(i, bestmove)| PositionResult {
+                fen: format!("position {}", i),
+                bestmove: bestmove.to_string(),
+                time_ms,
+            })
+            .collect();
+        BenchResult::new(positions, Duration::from_secs(seconds))
+    }
+
+    #[test]
+    fn test_identical_passes() {
+        let dir = std::env::temp_dir().join("shallow-red-bench-test");
+        let baseline = fake(&["e2e4", "d2d4", "g1f3"], 200, 1);
+        save_baseline(&dir, "identical", &baseline).unwrap();
+        let loaded = load_baseline(&dir, "identical").unwrap();
+        assert_eq!(loaded, baseline);
+
+        let current = fake(&["e2e4", "d2d4", "g1f3"], 200, 1);
+        let comparison = compare(&loaded, &current, 5.0).unwrap();
+        assert!(comparison.passed());
+        assert_eq!(comparison.lines[0], "position 1 time 200ms -> 200ms (+0ms)");
+        assert_eq!(
+            comparison.lines.last().unwrap(),
+            "positions/s 3.00 -> 3.00 (+0.0%)"
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_perturbed_fails() {
+        let baseline = fake(&["e2e4", "d2d4", "g1f3"], 200, 1);
+
+        // Another move is worth a look, but searches on the clock can differ
+        let comparison = compare(&baseline, &fake(&["e2e4", "c2c4", "g1f3"], 200, 1), 5.0).unwrap();
+        assert_eq!(
+            comparison.lines[1],
+            "position 2 time 200ms -> 200ms (+0ms), bestmove d2d4 -> c2c4"
+        );
+        assert!(comparison.passed());
+
+        // The same positions in twice the time
+        let slower = fake(&["e2e4", "d2d4", "g1f3"], 400, 2);
+        assert!(compare(&baseline, &slower, 60.0).unwrap().passed());
+        let comparison = compare(&baseline, &slower, 5.0).unwrap();
+        assert_eq!(
+            comparison.failures,
+            ["positions/s dropped 50.0%, more than the 5% tolerance"]
+        );
+    }
+
+    #[test]
+    fn test_stale_baseline_rejected() {
+        let baseline = fake(&["e2e4", "d2d4"], 200, 1);
+        let mut other_version = baseline.clone();
+        other_version.version = "0.0.0".to_string();
+        assert!(compare(&other_version, &baseline, 5.0).is_err());
+        assert!(compare(&fake(&["e2e4", "d2d4", "g1f3"], 200, 1), &baseline, 5.0).is_err());
+    }
+}
@@ -0,0 +1,176 @@
+// Opening book move selection. Candidates come from the book lookup, the policy picks one of
+// them (or nothing, in which case we fall back to searching).
+use chess::ChessMove;
+use log::info;
+use std::{fmt, str::FromStr};
+
+use crate::rng::SessionRng;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BookEntry {
+    pub chess_move: ChessMove,
+    pub weight: u16,
+}
+
+// Values of the `Book Selection` combo option
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SelectionPolicy {
+    Best, // Highest weight, always
+    #[default]
+    Weighted, // Probability proportional to weight
+    Uniform, // Ignore weights
+    MinWeight(u16), // Weighted, but moves below the weight are never played
+}
+
+impl SelectionPolicy {
+    pub fn select(&self, candidates: &[BookEntry], rng: &mut SessionRng) -> Option<ChessMove> {
+        let choice = match self {
+            SelectionPolicy::Best => candidates
+                .iter()
+                .fold(None, |best: Option<&BookEntry>, entry| match best {
+                    Some(best) if best.weight >= entry.weight => Some(best),
+                    _ => Some(entry),
+                })
+                .map(|entry| entry.chess_move),
+            SelectionPolicy::Weighted => weighted_pick(candidates.iter(), rng),
+            SelectionPolicy::Uniform => match candidates.len() {
+                0 => None,
+                n => Some(candidates[rng.below(n as u64) as usize].chess_move),
+            },
+            SelectionPolicy::MinWeight(min) => {
+                weighted_pick(candidates.iter().filter(|entry| entry.weight >= *min), rng)
+            }
+        };
+
+        info!(
+            "Book selection {} from {:?} chose {:?}",
+            self, candidates, choice
+        );
+        choice
+    }
+}
+
+fn weighted_pick<'a>(
+    candidates: impl Iterator<Item = &'a BookEntry> + Clone,
+    rng: &mut SessionRng,
+) -> Option<ChessMove> {
+    let total: u64 = candidates.clone().map(|entry| entry.weight as u64).sum();
+    if total == 0 {
+        return None; // Weight 0 means never play it
+    }
+
+    let mut roll = rng.below(total);
+    for entry in candidates {
+        if roll < entry.weight as u64 {
+            return Some(entry.chess_move);
+        }
+        roll -= entry.weight as u64;
+    }
+    None
+}
+
+impl FromStr for SelectionPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split_whitespace().collect();
+        match parts.as_slice() {
+            ["best"] => Ok(SelectionPolicy::Best),
+            ["weighted"] => Ok(SelectionPolicy::Weighted),
+            ["uniform"] => Ok(SelectionPolicy::Uniform),
+            ["min-weight", min] => min
+                .parse::<u16>()
+                .map(SelectionPolicy::MinWeight)
+                .map_err(|_| format!("bad min-weight value {}", min)),
+            _ => Err(format!("unknown book selection {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for SelectionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SelectionPolicy::Best => write!(f, "best"),
+            SelectionPolicy::Weighted => write!(f, "weighted"),
+            SelectionPolicy::Uniform => write!(f, "uniform"),
+            SelectionPolicy::MinWeight(min) => write!(f, "min-weight {}", min),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // e4 is the main line, d4 a sideline and a3 a rarely played oddity
+    fn book() -> Vec<BookEntry> {
+        [("e2e4", 60), ("d2d4", 30), ("a2a3", 10)]
+            .iter()
+            .map(|(m, weight)| BookEntry {
+                chess_move: ChessMove::from_str(m).unwrap(),
+                weight: *weight,
+            })
+            .collect()
+    }
+
+    fn counts(policy: SelectionPolicy, draws: usize) -> Vec<usize> {
+        let book = book();
+        let mut rng = SessionRng::seeded(7);
+        let mut counts = vec![0; book.len()];
+        for _ in 0..draws {
+            let choice = policy.select(&book, &mut rng).unwrap();
+            counts[book.iter().position(|e| e.chess_move == choice).unwrap()] += 1;
+        }
+        counts
+    }
+
+    #[test]
+    fn test_best_always_highest_weight() {
+        assert_eq!(counts(SelectionPolicy::Best, 100), vec![100, 0, 0]);
+    }
+
+    #[test]
+    fn test_weighted_follows_weights() {
+        let counts = counts(SelectionPolicy::Weighted, 10000);
+        assert!((5700..6300).contains(&counts[0]));
+        assert!((2700..3300).contains(&counts[1]));
+        assert!((800..1200).contains(&counts[2]));
+    }
+
+    #[test]
+    fn test_uniform_ignores_weights() {
+        for count in counts(SelectionPolicy::Uniform, 9000) {
+            assert!((2700..3300).contains(&count));
+        }
+    }
+
+    #[test]
+    fn test_min_weight_excludes_rare_moves() {
+        let counts = counts(SelectionPolicy::MinWeight(20), 1000);
+        assert_eq!(counts[2], 0);
+        assert!(counts[0] > counts[1]);
+        assert_eq!(
+            SelectionPolicy::MinWeight(100).select(&book(), &mut SessionRng::seeded(1)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_seed_reproduces_picks() {
+        let pick = |seed| {
+            let mut rng = SessionRng::seeded(seed);
+            (0..20)
+                .map(|_| SelectionPolicy::Weighted.select(&book(), &mut rng))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(pick(3), pick(3));
+    }
+
+    #[test]
+    fn test_parse_policy() {
+        assert_eq!("best".parse(), Ok(SelectionPolicy::Best));
+        assert_eq!("min-weight 5".parse(), Ok(SelectionPolicy::MinWeight(5)));
+        assert!("min-weight x".parse::<SelectionPolicy>().is_err());
+        assert!("random".parse::<SelectionPolicy>().is_err());
+    }
+}
@@ -0,0 +1,245 @@
+// Keeps positions worth a second look as EPD records: big eval drops, substituted moves and
+// `capture` snapshots. Records are appended one whole line at a time and synced, so a crash
+// loses at most the record being written.
+use chess::{Board, ChessMove};
+use log::{info, warn};
+use parking_lot::Mutex;
+use std::{fs::OpenOptions, io::Write, path::PathBuf};
+
+use crate::display::{san, Score};
+
+const SWING_CP: i32 = 200; // Drop in our own eval between two of our moves that counts as a swing
+const MAX_PER_GAME: u32 = 5; // Automatic captures, snapshots don't count
+const MATE_CP: i32 = 10000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+    Swing,
+    Fallback, // Engine move was rejected and substituted
+    Manual,
+}
+
+impl Trigger {
+    fn reason(&self) -> &'static str {
+        match self {
+            Trigger::Swing => "eval-swing",
+            Trigger::Fallback => "fallback",
+            Trigger::Manual => "manual",
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct CaptureState {
+    game: u32,
+    our_moves: u32,
+    captured: u32,           // Automatic captures this game
+    last_score: Option<i32>, // Our eval after our previous move
+}
+
+pub struct EpdCapture {
+    path: PathBuf,
+    state: Mutex<CaptureState>,
+}
+
+impl EpdCapture {
+    pub fn new(path: PathBuf) -> EpdCapture {
+        EpdCapture {
+            path,
+            state: Mutex::new(CaptureState {
+                game: 1,
+                ..Default::default()
+            }),
+        }
+    }
+
+    pub fn new_game(&self) {
+        let mut state = self.state.lock();
+        *state = CaptureState {
+            game: state.game + 1,
+            ..Default::default()
+        };
+    }
+
+    // Called after each of our searches with the move we're about to play
+    pub fn after_search(
+        &self,
+        board: &Board,
+        chess_move: ChessMove,
+        score: Option<Score>,
+        fallback: bool,
+    ) {
+        let mut state = self.state.lock();
+        state.our_moves += 1;
+        let before = state.last_score;
+        let after = score.map(centipawns);
+        state.last_score = after;
+
+        let trigger = match (before, after) {
+            _ if fallback => Trigger::Fallback,
+            (Some(before), Some(after)) if before - after >= SWING_CP => Trigger::Swing,
+            _ => return,
+        };
+        if state.captured >= MAX_PER_GAME {
+            return;
+        }
+        state.captured += 1;
+
+        let id = format!("game {} move {}", state.game, state.our_moves);
+        let record = epd_record(board, Some(chess_move), before, after, trigger, &id);
+        self.append(&record);
+    }
+
+    pub fn snapshot(&self, board: &Board) {
+        let state = self.state.lock();
+        let id = format!("game {} move {}", state.game, state.our_moves);
+        let record = epd_record(board, None, None, state.last_score, Trigger::Manual, &id);
+        self.append(&record);
+    }
+
+    fn append(&self, record: &str) {
+        let written = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| {
+                file.write_all(format!("{}\n", record).as_bytes())?;
+                file.sync_data()
+            });
+        match written {
+            Ok(()) => info!("Captured {}", record),
+            Err(e) => warn!("Couldn't capture to {}: {}", self.path.display(), e),
+        }
+    }
+}
+
+pub(crate) fn centipawns(score: Score) -> i32 {
+    match score {
+        Score::Centipawns(cp) => cp,
+        Score::Mate(moves) if moves > 0 => MATE_CP,
+        Score::Mate(_) => -MATE_CP,
+    }
+}
+
+// First four FEN fields, then sm (our move), ce (eval after), c0 reason, c1 eval before and id
+pub fn epd_record(
+    board: &Board,
+    chess_move: Option<ChessMove>,
+    before: Option<i32>,
+    after: Option<i32>,
+    trigger: Trigger,
+    id: &str,
+) -> String {
+    let fen = board.to_string();
+    let mut record = fen
+        .split_whitespace()
+        .take(4)
+        .collect::<Vec<&str>>()
+        .join(" ");
+    if let Some(chess_move) = chess_move {
+        record += &format!(" sm {};", san(board, chess_move));
+    }
+    if let Some(after) = after {
+        record += &format!(" ce {};", after);
+    }
+    record += &format!(" c0 \"{}\";", trigger.reason());
+    if let Some(before) = before {
+        record += &format!(" c1 \"before {}\";", before);
+    }
+    record += &format!(" id \"{}\";", id);
+    record
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        backend::{SearchBackend, SearchOutcome},
+        session::{Reply, UciSession},
+    };
+    use chess::MoveGen;
+    use shallow_red_engine::utils::engine_interface::EngineSettings;
+    use std::{fs, sync::Arc};
+
+    // First legal move, with scores handed out in order
+    struct ScriptedBackend(Mutex<Vec<i32>>);
+
+    impl SearchBackend for ScriptedBackend {
+        fn search(&self, board: Board, _settings: EngineSettings) -> SearchOutcome {
+            SearchOutcome {
+                best_move: MoveGen::new_legal(&board).next().unwrap(),
+                score: Some(Score::Centipawns(self.0.lock().remove(0))),
+                nodes: None,
+            }
+        }
+    }
+
+    fn capture_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("shallow-red-capture-{}.epd", name));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn play_go(session: &mut UciSession) {
+        match session.handle("go wtime 600000 btime 600000") {
+            Some(Reply::Search(request)) => request.run(|_| {}),
+            _ => panic!("go should hand back a search"),
+        }
+    }
+
+    #[test]
+    fn test_swing_appends_epd() {
+        let path = capture_path("swing");
+        let mut session = UciSession::new(None);
+        session.backend = Arc::new(ScriptedBackend(Mutex::new(vec![30, 10, -350])));
+        session.handle(&format!(
+            "setoption name Capture File value {}",
+            path.display()
+        ));
+
+        for moves in [
+            "e2e4 e7e5",
+            "e2e4 e7e5 g1f3 b8c6",
+            "e2e4 e7e5 g1f3 b8c6 f1c4 g8f6",
+        ] {
+            session.handle(&format!("position startpos moves {}", moves));
+            play_go(&mut session);
+        }
+
+        let captured = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = captured.lines().collect();
+        assert_eq!(lines.len(), 1); // 30 -> 10 isn't a swing, 10 -> -350 is
+        let played = san(
+            &session.board,
+            MoveGen::new_legal(&session.board).next().unwrap(),
+        );
+        assert_eq!(
+            lines[0],
+            format!(
+                "r1bqkb1r/pppp1ppp/2n2n2/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - \
+                 sm {}; ce -350; c0 \"eval-swing\"; c1 \"before 10\"; id \"game 1 move 3\";",
+                played
+            )
+        );
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_rate_limit_and_snapshot() {
+        let path = capture_path("limit");
+        let capture = EpdCapture::new(path.clone());
+        let board = Board::default();
+        let first = MoveGen::new_legal(&board).next().unwrap();
+        for _ in 0..MAX_PER_GAME + 3 {
+            capture.after_search(&board, first, None, true);
+        }
+        capture.snapshot(&board);
+
+        let captured = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = captured.lines().collect();
+        assert_eq!(lines.len(), MAX_PER_GAME as usize + 1);
+        assert!(lines[0].contains("c0 \"fallback\";"));
+        assert!(lines.last().unwrap().contains("c0 \"manual\";"));
+        let _ = fs::remove_file(&path);
+    }
+}
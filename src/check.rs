@@ -0,0 +1,231 @@
+// --check <file>: lints a GUI's command stream without running any of it. Every line goes
+// through the same parsing the session does, against a simulated board, so illegal moves and
+// commands sent out of order show up before a real game goes wrong.
+use chess::{Board, ChessMove};
+use std::{fmt, str::FromStr};
+
+use crate::{
+    command::{parse_command, parse_setoption, ParseError, UciCommand, GO_KEYWORDS},
+    options,
+    session::{parse_position, play_moves, PositionError},
+};
+
+const COMMANDS: [&str; 18] = [
+    "uci",
+    "isready",
+    "debug",
+    "setoption",
+    "ucinewgame",
+    "position",
+    "go",
+    "stop",
+    "ponderhit",
+    "quit",
+    "debuginternal",
+    "capture",
+    "tbprobe",
+    "symtest",
+    "reset",
+    "result",
+    "gameresult",
+    "stats",
+];
+
+// go arguments that take a value, infinite and ponder stand alone
+const GO_VALUES: [&str; 9] = [
+    "wtime",
+    "btime",
+    "winc",
+    "binc",
+    "movestogo",
+    "movetime",
+    "depth",
+    "nodes",
+    "mate",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    Ok,
+    UnknownCommand(String),
+    Malformed(String),
+    IllegalMove(String),
+    OutOfOrder(String),
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Verdict::Ok => write!(f, "ok"),
+            Verdict::UnknownCommand(command) => write!(f, "unknown command {}", command),
+            Verdict::Malformed(problem) => write!(f, "malformed arguments: {}", problem),
+            Verdict::IllegalMove(problem) => write!(f, "illegal move {}", problem),
+            Verdict::OutOfOrder(problem) => write!(f, "protocol order: {}", problem),
+        }
+    }
+}
+
+// What the session would know at this point of the stream
+#[derive(Debug, Default)]
+pub struct Checker {
+    board: Board,
+    uci_seen: bool,
+    position_seen: bool,
+    searching: bool, // A go hasn't been answered as far as we can tell
+    raw_next: bool,  // debuginternal reads the next line as a board
+    chess960: bool,  // UCI_Chess960 has been turned on
+}
+
+impl Checker {
+    pub fn check_line(&mut self, line: &str) -> Verdict {
+        if std::mem::take(&mut self.raw_next) {
+            return match Board::from_str(line.trim()) {
+                Ok(board) => {
+                    self.board = board;
+                    self.position_seen = true;
+                    Verdict::Ok
+                }
+                Err(_) => Verdict::Malformed(format!("debuginternal board {}", line.trim())),
+            };
+        }
+
+        let parsed: Vec<&str> = line.split_whitespace().collect();
+        let command = parsed[0];
+        if !COMMANDS.contains(&command) {
+            return Verdict::UnknownCommand(command.to_string());
+        }
+        if !self.uci_seen && !matches!(command, "uci" | "quit") {
+            return Verdict::OutOfOrder(format!("{} before uci", command));
+        }
+
+        match command {
+            "uci" => self.uci_seen = true,
+            "setoption" => match parse_setoption(&parsed) {
+                Some((name, value)) => match options::find(&name).map(|o| o.check_value(&value)) {
+                    None => return Verdict::Malformed(format!("no option named {}", name)),
+                    Some(Err(e)) => return Verdict::Malformed(e),
+                    Some(Ok(value)) if name.eq_ignore_ascii_case("UCI_Chess960") => {
+                        self.chess960 = value == "true"
+                    }
+                    Some(Ok(_)) => {}
+                },
+                None => return Verdict::Malformed("expected name <name> [value <value>]".into()),
+            },
+            "ucinewgame" => {
+                self.board = Board::default();
+                self.position_seen = false;
+            }
+            "position" => return self.check_position(line),
+            "go" => {
+                if !self.position_seen {
+                    return Verdict::OutOfOrder("go before position".into());
+                }
+                if let Err(problem) = check_go(&parsed) {
+                    return Verdict::Malformed(problem);
+                }
+                self.searching = true;
+            }
+            "stop" => self.searching = false,
+            "ponderhit" if !self.searching => {
+                return Verdict::OutOfOrder("ponderhit without a go".into())
+            }
+            "debuginternal" => self.raw_next = true,
+            _ => {}
+        }
+        Verdict::Ok
+    }
+
+    fn check_position(&mut self, line: &str) -> Verdict {
+        let (start, moves) = match parse_command(line) {
+            Ok(UciCommand::Position { start, moves }) => (start, moves),
+            Err(ParseError::Position(problem)) => return Verdict::Malformed(problem),
+            _ => unreachable!("only position lines get here"),
+        };
+        match parse_position(&start, &moves, self.chess960) {
+            Ok((start, moves)) => {
+                self.board = play_moves(start, &moves);
+                self.position_seen = true;
+                Verdict::Ok
+            }
+            Err(PositionError::Malformed(problem)) => Verdict::Malformed(problem),
+            Err(PositionError::IllegalMove(problem)) => Verdict::IllegalMove(problem),
+        }
+    }
+}
+
+fn check_go(parsed: &[&str]) -> Result<(), String> {
+    let mut args = parsed[1..].iter();
+    while let Some(arg) = args.next() {
+        match *arg {
+            "infinite" | "ponder" => {}
+            "searchmoves" | "avoidmoves" => {
+                while let Some(chess_move) =
+                    args.clone().next().filter(|m| !GO_KEYWORDS.contains(m))
+                {
+                    if ChessMove::from_str(chess_move).is_err() {
+                        return Err(format!("{} {} isn't a move", arg, chess_move));
+                    }
+                    args.next();
+                }
+            }
+            key if GO_VALUES.contains(&key) => match args.next() {
+                Some(value) if value.parse::<i64>().is_ok() => {}
+                Some(value) => return Err(format!("{} {} isn't a number", key, value)),
+                None => return Err(format!("{} needs a value", key)),
+            },
+            other => return Err(format!("unknown go argument {}", other)),
+        }
+    }
+    Ok(())
+}
+
+// Verdicts by line number (from 1), blank lines are skipped
+pub fn check_script(text: &str) -> Vec<(usize, Verdict)> {
+    let mut checker = Checker::default();
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| (i + 1, checker.check_line(line)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixture_errors() {
+        let errors: Vec<(usize, Verdict)> =
+            check_script(include_str!("../fixtures/check-errors.uci"))
+                .into_iter()
+                .filter(|(_, verdict)| *verdict != Verdict::Ok)
+                .collect();
+        assert_eq!(
+            errors,
+            [
+                (1, Verdict::OutOfOrder("isready before uci".into())),
+                (2, Verdict::OutOfOrder("go before uci".into())),
+                (5, Verdict::UnknownCommand("frobnicate".into())),
+                (
+                    6,
+                    Verdict::Malformed("Hash is a number from 1 to 65536".into())
+                ),
+                (7, Verdict::OutOfOrder("go before position".into())),
+                (8, Verdict::IllegalMove("e2e5 at ply 5".into())),
+                (10, Verdict::Malformed("btime lots isn't a number".into())),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_go_arguments() {
+        assert!(check_go(&["go", "movetime", "100"]).is_ok());
+        assert!(check_go(&["go", "wtime", "1", "btime", "1", "winc", "0", "binc", "0"]).is_ok());
+        assert!(check_go(&["go", "btime", "1", "wtime", "1"]).is_ok());
+        assert!(check_go(&["go", "infinite"]).is_ok());
+        assert!(check_go(&["go", "wtime"]).is_err());
+        assert!(check_go(&["go", "forever"]).is_err());
+        assert!(check_go(&["go", "avoidmoves", "e2e4", "d2d4", "movetime", "10"]).is_ok());
+        assert!(check_go(&["go", "searchmoves", "e2e4", "castle"]).is_err());
+    }
+}
@@ -0,0 +1,160 @@
+// UCI_Chess960. The chess crate only castles with the king on the e-file and the rooks in the
+// corners, so that's the part of Fischer Random we can play: those rights are kept whichever way
+// the FEN writes them (KQkq, or Shredder's HAha) and anything else is dropped. With the option on,
+// castling goes both ways as king takes rook.
+use chess::{Board, ChessMove, Color, File, Piece, Rank, Square};
+use log::warn;
+use std::str::FromStr;
+
+// A FEN with X-FEN or Shredder castling rights, keeping the ones the chess crate can castle
+pub fn parse_fen(fen: &str) -> Result<Board, chess::Error> {
+    let mut fields: Vec<&str> = fen.split_whitespace().collect();
+    let Some(castling) = fields.get(2).copied() else {
+        return Board::from_str(fen);
+    };
+    fields[2] = "-";
+    let uncastled = Board::from_str(&fields.join(" "))?;
+
+    let mut rights = String::new();
+    for right in castling.chars().filter(|right| *right != '-') {
+        let color = match right.is_ascii_uppercase() {
+            true => Color::White,
+            false => Color::Black,
+        };
+        let (rook_file, standard) = match right.to_ascii_uppercase() {
+            'K' | 'H' => (File::H, 'K'),
+            'Q' | 'A' => (File::A, 'Q'),
+            _ => {
+                warn!("Castling right {} isn't supported, dropped", right);
+                continue;
+            }
+        };
+        let back_rank = color.to_my_backrank();
+        let king = Square::make_square(back_rank, File::E);
+        let rook = Square::make_square(back_rank, rook_file);
+        if piece_of(&uncastled, king, Piece::King, color)
+            && piece_of(&uncastled, rook, Piece::Rook, color)
+        {
+            rights.push(match color {
+                Color::White => standard,
+                Color::Black => standard.to_ascii_lowercase(),
+            });
+        } else {
+            warn!("Castling right {} isn't supported, dropped", right);
+        }
+    }
+    if rights.is_empty() {
+        return Ok(uncastled);
+    }
+    fields[2] = &rights;
+    Board::from_str(&fields.join(" "))
+}
+
+// King takes rook from the GUI, as the chess crate's king move
+pub fn castling_in(board: &Board, chess_move: ChessMove) -> ChessMove {
+    let (source, dest) = (chess_move.get_source(), chess_move.get_dest());
+    let color = board.side_to_move();
+    if source.get_file() != File::E
+        || !piece_of(board, source, Piece::King, color)
+        || !piece_of(board, dest, Piece::Rook, color)
+    {
+        return chess_move;
+    }
+    let king_to = match dest.get_file() {
+        File::H => File::G,
+        File::A => File::C,
+        _ => return chess_move,
+    };
+    ChessMove::new(
+        source,
+        Square::make_square(source.get_rank(), king_to),
+        None,
+    )
+}
+
+// The other way, for our own moves
+pub fn castling_out(board: &Board, chess_move: ChessMove) -> ChessMove {
+    let (source, dest) = (chess_move.get_source(), chess_move.get_dest());
+    let rank = source.get_rank();
+    if source.get_file() != File::E
+        || board.piece_on(source) != Some(Piece::King)
+        || !matches!(rank, Rank::First | Rank::Eighth)
+    {
+        return chess_move;
+    }
+    match dest.get_file() {
+        File::G => ChessMove::new(source, Square::make_square(rank, File::H), None),
+        File::C => ChessMove::new(source, Square::make_square(rank, File::A), None),
+        _ => chess_move,
+    }
+}
+
+// Rewrites the moves of any bestmove line in `out`, searched from `board`, ponder move included
+pub fn bestmove_out(board: &Board, out: &str) -> String {
+    out.lines()
+        .map(|line| {
+            let mut words = line
+                .strip_prefix("bestmove ")
+                .unwrap_or("")
+                .split_whitespace();
+            let Some(played) = words.next().and_then(|word| ChessMove::from_str(word).ok()) else {
+                return line.to_string();
+            };
+            let mut rewritten = format!("bestmove {}", castling_out(board, played));
+            if let (Some("ponder"), Some(reply)) = (
+                words.next(),
+                words.next().and_then(|word| ChessMove::from_str(word).ok()),
+            ) {
+                let after = board.make_move_new(played);
+                rewritten += &format!(" ponder {}", castling_out(&after, reply));
+            }
+            rewritten
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+fn piece_of(board: &Board, square: Square, piece: Piece, color: Color) -> bool {
+    board.piece_on(square) == Some(piece) && board.color_on(square) == Some(color)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shredder_castling_rights() {
+        let fen = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w HAha - 0 1";
+        assert_eq!(parse_fen(fen).unwrap(), Board::default());
+
+        // Rooks on b and g: nothing the chess crate can castle
+        let frc = parse_fen("qrbkbnrn/pppppppp/8/8/8/8/PPPPPPPP/QRBKBNRN w GBgb - 0 1").unwrap();
+        assert_eq!(
+            frc.castle_rights(Color::White),
+            chess::CastleRights::NoRights
+        );
+    }
+
+    #[test]
+    fn test_king_takes_rook() {
+        let board = Board::from_str("r3k2r/pppppppp/8/8/8/8/PPPPPPPP/R3K2R w KQkq - 0 1").unwrap();
+        let short = ChessMove::from_str("e1g1").unwrap();
+        assert_eq!(
+            castling_in(&board, ChessMove::from_str("e1h1").unwrap()),
+            short
+        );
+        assert_eq!(
+            castling_out(&board, short),
+            ChessMove::from_str("e1h1").unwrap()
+        );
+        assert_eq!(
+            bestmove_out(&board, "info string x\nbestmove e1c1"),
+            "info string x\nbestmove e1a1"
+        );
+        assert_eq!(bestmove_out(&board, "bestmove a2a3"), "bestmove a2a3");
+        assert_eq!(
+            bestmove_out(&board, "bestmove e1g1 ponder e8c8"),
+            "bestmove e1h1 ponder e8a8"
+        );
+    }
+}
@@ -0,0 +1,306 @@
+// A line from the GUI as a UciCommand. Only the shape of a command is checked here, whether a FEN
+// or a move makes sense on the board is up to the session. Go is lenient like the GUIs it has to
+// put up with: a bad or missing value is warned about and left out, never the whole command.
+use log::warn;
+use std::{fmt, time::Duration};
+
+pub(crate) const GO_KEYWORDS: [&str; 13] = [
+    "wtime",
+    "btime",
+    "winc",
+    "binc",
+    "movestogo",
+    "movetime",
+    "depth",
+    "nodes",
+    "mate",
+    "infinite",
+    "ponder",
+    "searchmoves",
+    "avoidmoves",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UciCommand {
+    Uci,
+    Debug(bool),
+    IsReady,
+    SetOption {
+        name: String,
+        value: String,
+    },
+    UciNewGame,
+    Position {
+        start: PositionStart,
+        moves: Vec<String>,
+    },
+    Go(GoParams),
+    Stop,
+    PonderHit,
+    Quit,
+    // Ours, for debugging and the tools around the engine
+    Capture,
+    TbProbe,
+    SymTest(Option<String>), // Positions file, the built in set without one
+    Reset,
+    Result(Vec<String>), // result or gameresult, everything after it
+    Stats,
+    #[cfg(test)]
+    DebugPanic,
+    Unknown(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PositionStart {
+    StartPos,
+    Fen(String), // As sent, the move counters might be missing
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GoParams {
+    pub wtime: Option<Duration>, // A clock gone negative is no time left
+    pub btime: Option<Duration>,
+    pub winc: Option<Duration>,
+    pub binc: Option<Duration>,
+    pub movestogo: Option<u32>,
+    pub movetime: Option<Duration>,
+    pub depth: Option<u8>,
+    pub nodes: Option<u64>,
+    pub mate: Option<u8>,
+    pub infinite: bool,
+    pub ponder: bool,
+    pub searchmoves: Option<Vec<String>>, // Move tokens as sent, checked against the board later
+    pub avoidmoves: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    Empty, // Blank lines happen, GUIs send them between commands
+    SetOption,
+    Position(String),
+    Debug(String), // What came instead of on or off
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::Empty => write!(f, "empty command"),
+            ParseError::SetOption => write!(f, "setoption takes name <name> [value <value>]"),
+            ParseError::Position(problem) => write!(f, "{}", problem),
+            ParseError::Debug(got) => write!(f, "debug takes on or off, got {}", got),
+        }
+    }
+}
+
+pub fn parse_command(line: &str) -> Result<UciCommand, ParseError> {
+    let tokens: Vec<&str> = line.split_whitespace().collect();
+    let Some(&command) = tokens.first() else {
+        return Err(ParseError::Empty);
+    };
+    Ok(match command {
+        "uci" => UciCommand::Uci,
+        "debug" => match tokens.get(1) {
+            Some(&"on") => UciCommand::Debug(true),
+            Some(&"off") => UciCommand::Debug(false),
+            _ => return Err(ParseError::Debug(tokens[1..].join(" "))),
+        },
+        "isready" => UciCommand::IsReady,
+        "setoption" => {
+            let (name, value) = parse_setoption(&tokens).ok_or(ParseError::SetOption)?;
+            UciCommand::SetOption { name, value }
+        }
+        "ucinewgame" => UciCommand::UciNewGame,
+        "position" => {
+            let (start, moves) = parse_position(&tokens)?;
+            UciCommand::Position { start, moves }
+        }
+        "go" => UciCommand::Go(parse_go(&tokens)),
+        "stop" => UciCommand::Stop,
+        "ponderhit" => UciCommand::PonderHit,
+        "quit" => UciCommand::Quit,
+        "capture" => UciCommand::Capture,
+        "tbprobe" => UciCommand::TbProbe,
+        "symtest" => UciCommand::SymTest(tokens.get(1).map(|file| file.to_string())),
+        "reset" => UciCommand::Reset,
+        "result" | "gameresult" => {
+            UciCommand::Result(tokens[1..].iter().map(|token| token.to_string()).collect())
+        }
+        "stats" => UciCommand::Stats,
+        #[cfg(test)]
+        "debugpanic" => UciCommand::DebugPanic,
+        command => UciCommand::Unknown(command.to_string()),
+    })
+}
+
+// setoption name <name> value <value>, option names may contain spaces
+pub(crate) fn parse_setoption(tokens: &[&str]) -> Option<(String, String)> {
+    let value_at = tokens.iter().position(|token| *token == "value");
+    let name_end = value_at.unwrap_or(tokens.len());
+    if tokens.get(1) != Some(&"name") || name_end <= 2 {
+        return None;
+    }
+    let name = tokens[2..name_end].join(" ");
+    let value = match value_at {
+        Some(at) => tokens[at + 1..].join(" "),
+        None => String::new(),
+    };
+    Some((name, value))
+}
+
+// position <startpos | fen <fen>> [moves ...]
+fn parse_position(tokens: &[&str]) -> Result<(PositionStart, Vec<String>), ParseError> {
+    let moves_at = tokens
+        .iter()
+        .position(|token| *token == "moves")
+        .unwrap_or(tokens.len());
+    let start = match tokens.get(1) {
+        Some(&"startpos") if moves_at == 2 => PositionStart::StartPos,
+        Some(&"startpos") => {
+            return Err(ParseError::Position(format!(
+                "expected moves, got {}",
+                tokens[2]
+            )))
+        }
+        Some(&"fen") => PositionStart::Fen(tokens[2..moves_at].join(" ")),
+        _ => return Err(ParseError::Position("expected startpos or fen".to_string())),
+    };
+    let moves = tokens.get(moves_at + 1..).unwrap_or(&[]);
+    Ok((start, moves.iter().map(|token| token.to_string()).collect()))
+}
+
+// Fields come in any order, and a keyword straight after a key means its value is missing
+pub fn parse_go(tokens: &[&str]) -> GoParams {
+    let value = |key: &str| {
+        let at = tokens.iter().position(|token| *token == key)?;
+        let value = tokens
+            .get(at + 1)
+            .filter(|value| !GO_KEYWORDS.contains(value));
+        match value.map(|value| (value, value.parse::<i64>())) {
+            Some((_, Ok(number))) => Some(number.max(0) as u64),
+            Some((value, Err(_))) => {
+                warn!("go {} {} isn't a number, ignored", key, value);
+                None
+            }
+            None => {
+                warn!("go {} without a value, ignored", key);
+                None
+            }
+        }
+    };
+    let ms = |key: &str| value(key).map(Duration::from_millis);
+    let moves = |key: &str| {
+        let at = tokens.iter().position(|token| *token == key)?;
+        Some(
+            tokens[at + 1..]
+                .iter()
+                .take_while(|token| !GO_KEYWORDS.contains(token))
+                .map(|token| token.to_string())
+                .collect(),
+        )
+    };
+    GoParams {
+        wtime: ms("wtime"),
+        btime: ms("btime"),
+        winc: ms("winc"),
+        binc: ms("binc"),
+        movestogo: value("movestogo").map(|moves| moves.min(u32::MAX as u64) as u32),
+        movetime: ms("movetime"),
+        depth: value("depth").map(|depth| depth.min(u8::MAX as u64) as u8),
+        nodes: value("nodes"),
+        mate: value("mate").map(|moves| moves.min(u8::MAX as u64) as u8),
+        infinite: tokens.contains(&"infinite"),
+        ponder: tokens.contains(&"ponder"),
+        searchmoves: moves("searchmoves"),
+        avoidmoves: moves("avoidmoves"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(parse_command("  "), Err(ParseError::Empty));
+        assert_eq!(parse_command("isready"), Ok(UciCommand::IsReady));
+        assert_eq!(parse_command("debug on"), Ok(UciCommand::Debug(true)));
+        assert_eq!(
+            parse_command("debug maybe"),
+            Err(ParseError::Debug("maybe".to_string()))
+        );
+        assert_eq!(
+            parse_command("setoption name Bullet Fast Path value true"),
+            Ok(UciCommand::SetOption {
+                name: "Bullet Fast Path".to_string(),
+                value: "true".to_string()
+            })
+        );
+        assert_eq!(parse_command("setoption name"), Err(ParseError::SetOption));
+        assert_eq!(
+            parse_command("gameresult 1-0 time"),
+            Ok(UciCommand::Result(vec![
+                "1-0".to_string(),
+                "time".to_string()
+            ]))
+        );
+        assert_eq!(
+            parse_command("xyzzy 3"),
+            Ok(UciCommand::Unknown("xyzzy".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_parse_position() {
+        assert_eq!(
+            parse_command("position startpos moves e2e4 e7e5"),
+            Ok(UciCommand::Position {
+                start: PositionStart::StartPos,
+                moves: vec!["e2e4".to_string(), "e7e5".to_string()]
+            })
+        );
+        assert_eq!(
+            parse_command("position fen 8/8/8/8/8/8/6k1/4K2R w K -"),
+            Ok(UciCommand::Position {
+                start: PositionStart::Fen("8/8/8/8/8/8/6k1/4K2R w K -".to_string()),
+                moves: Vec::new()
+            })
+        );
+        assert_eq!(
+            parse_command("position startpos e2e4"),
+            Err(ParseError::Position("expected moves, got e2e4".to_string()))
+        );
+        assert_eq!(
+            parse_command("position"),
+            Err(ParseError::Position("expected startpos or fen".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_go_fields_any_order() {
+        let go = |line: &str| parse_go(&line.split_whitespace().collect::<Vec<&str>>());
+        let params = go("go btime 5000 wtime 7000 winc 100 binc 100 movestogo 30");
+        assert_eq!(params.wtime, Some(Duration::from_millis(7000)));
+        assert_eq!(params.btime, Some(Duration::from_millis(5000)));
+        assert_eq!(params.winc, Some(Duration::from_millis(100)));
+        assert_eq!(params.movestogo, Some(30));
+        assert_eq!(
+            params,
+            go("go movestogo 30 binc 100 winc 100 wtime 7000 btime 5000")
+        );
+
+        let flagged = go("go wtime -20 btime 3000");
+        assert_eq!(flagged.wtime, Some(Duration::ZERO));
+        let missing = go("go wtime movestogo 10 btime x");
+        assert_eq!((missing.wtime, missing.btime), (None, None));
+        assert_eq!(missing.movestogo, Some(10));
+
+        let restricted = go("go ponder searchmoves e2e4 d2d4 depth 5 avoidmoves");
+        assert!(restricted.ponder && !restricted.infinite);
+        assert_eq!(
+            restricted.searchmoves,
+            Some(vec!["e2e4".to_string(), "d2d4".to_string()])
+        );
+        assert_eq!(restricted.avoidmoves, Some(Vec::new()));
+        assert_eq!(restricted.depth, Some(5));
+    }
+}
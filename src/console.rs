@@ -0,0 +1,157 @@
+// --play: a game against the engine in the terminal, moves typed as SAN or UCI. After each
+// engine move the board, eval and line are drawn by the display module instead of info lines.
+use chess::{Board, BoardStatus, ChessMove, Color};
+use log::warn;
+use shallow_red_engine::utils::engine_interface::EngineSettings;
+use std::{
+    io::{self, BufRead, Write},
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    backend::SearchBackend,
+    display::{render_analysis, render_board, Style},
+    search::checked_move,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConsoleSettings {
+    pub human: Color,
+    pub movetime: Duration,
+    pub style: Style,
+}
+
+// Until the game ends, `quit` or the input runs out. Returns the moves played.
+pub fn play_console(
+    backend: &dyn SearchBackend,
+    settings: &ConsoleSettings,
+    input: impl BufRead,
+    output: &mut impl Write,
+) -> io::Result<Vec<ChessMove>> {
+    let mut board = Board::default();
+    let mut moves = Vec::new();
+    let mut lines = input.lines();
+    if settings.human == Color::White {
+        write!(output, "{}", render_board(&board, None, settings.style))?;
+    }
+
+    loop {
+        match board.status() {
+            BoardStatus::Checkmate => {
+                let winner = match board.side_to_move() {
+                    Color::White => "black",
+                    Color::Black => "white",
+                };
+                writeln!(output, "checkmate, {} wins", winner)?;
+                break;
+            }
+            BoardStatus::Stalemate => {
+                writeln!(output, "stalemate")?;
+                break;
+            }
+            BoardStatus::Ongoing => {}
+        }
+
+        if board.side_to_move() == settings.human {
+            write!(output, "your move: ")?;
+            output.flush()?;
+            let Some(line) = lines.next().transpose()? else {
+                break;
+            };
+            let text = line.trim();
+            if text == "quit" {
+                break;
+            }
+            match parse_move(&board, text) {
+                Some(chess_move) => {
+                    board = board.make_move_new(chess_move);
+                    moves.push(chess_move);
+                }
+                None => writeln!(output, "{} isn't a legal move", text)?,
+            }
+            continue;
+        }
+
+        let started = Instant::now();
+        let outcome = backend.search(
+            board,
+            EngineSettings {
+                verbose: false,
+                time_limit: settings.movetime,
+                ..Default::default()
+            },
+        );
+        let chess_move = match checked_move(&board, outcome.best_move, None) {
+            Ok(chess_move) => chess_move,
+            Err((fallback, problem)) => {
+                warn!(
+                    "Engine returned {} on {}: {}, playing {} instead",
+                    outcome.best_move, board, problem, fallback
+                );
+                fallback
+            }
+        };
+        // The display wants white's point of view, a search gives the side to move's
+        let score = outcome.score.map(|score| match board.side_to_move() {
+            Color::White => score,
+            Color::Black => score.negated(),
+        });
+        let move_number = moves.len() as u32 / 2 + 1;
+        let analysis = render_analysis(
+            &board,
+            &[chess_move],
+            move_number,
+            score,
+            None,
+            started.elapsed(),
+            settings.style,
+        );
+        write!(output, "{}", analysis)?;
+        board = board.make_move_new(chess_move);
+        moves.push(chess_move);
+    }
+    Ok(moves)
+}
+
+fn parse_move(board: &Board, text: &str) -> Option<ChessMove> {
+    ChessMove::from_str(text)
+        .ok()
+        .or_else(|| ChessMove::from_san(board, text).ok())
+        .filter(|chess_move| board.legal(*chess_move))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MockBackend;
+
+    fn play(human: Color, input: &str) -> (Vec<ChessMove>, String) {
+        let settings = ConsoleSettings {
+            human,
+            movetime: Duration::from_millis(1),
+            style: Style { color: false },
+        };
+        let mut output = Vec::new();
+        let moves = play_console(&MockBackend, &settings, input.as_bytes(), &mut output).unwrap();
+        (moves, String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn test_engine_answers_with_analysis() {
+        let (moves, output) = play(Color::White, "e4\nbogus\nquit\n");
+        assert_eq!(moves.len(), 2);
+        assert_eq!(moves[0], ChessMove::from_str("e2e4").unwrap());
+        assert!(output.contains("pv 1... "));
+        assert!(output.contains("time "));
+        assert!(output.contains("bogus isn't a legal move"));
+    }
+
+    #[test]
+    fn test_engine_moves_first_as_white() {
+        let (moves, output) = play(Color::Black, "");
+        assert_eq!(moves.len(), 1);
+        assert!(output.starts_with("8 "));
+        assert!(output.contains("pv 1. "));
+    }
+}
@@ -0,0 +1,236 @@
+// Crash reports. The last commands in and out are kept in a ring buffer, and the session leaves
+// a snapshot of the game and options after every command. On a panic the hook writes both to the
+// report file along with the panic and a backtrace.
+use chess::{Board, MoveGen};
+use parking_lot::Mutex;
+use std::{
+    backtrace::Backtrace,
+    collections::VecDeque,
+    fmt::Write,
+    fs,
+    panic::{self, AssertUnwindSafe},
+    path::PathBuf,
+    str::FromStr,
+    thread,
+    time::Duration,
+};
+use web_time::{SystemTime, UNIX_EPOCH};
+
+use crate::version::{BUILD, VERSION};
+
+const HISTORY_LEN: usize = 200;
+const LOCK_WAIT: Duration = Duration::from_millis(100); // The hook gives up on a lock after this
+pub const DEFAULT_REPORT_PATH: &str = "shallow-red-crash.txt";
+
+struct Entry {
+    at: Duration, // Since the epoch
+    received: bool,
+    line: String,
+}
+
+// What the session looked like after the last command it handled
+#[derive(Debug, Clone, Default)]
+pub struct CrashContext {
+    pub fen: String,
+    pub moves: Vec<String>,
+    pub options: Vec<(String, String)>,
+}
+
+static HISTORY: Mutex<VecDeque<Entry>> = Mutex::new(VecDeque::new());
+static CONTEXT: Mutex<Option<CrashContext>> = Mutex::new(None);
+static REPORT_PATH: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+fn record(received: bool, line: &str) {
+    let at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let mut history = HISTORY.lock();
+    if history.len() == HISTORY_LEN {
+        history.pop_front();
+    }
+    history.push_back(Entry {
+        at,
+        received,
+        line: line.trim_end().to_string(),
+    });
+}
+
+pub fn record_received(line: &str) {
+    record(true, line);
+}
+
+pub fn record_sent(line: &str) {
+    record(false, line);
+}
+
+pub fn set_context(context: CrashContext) {
+    *CONTEXT.lock() = Some(context);
+}
+
+pub fn set_report_path(path: PathBuf) {
+    *REPORT_PATH.lock() = Some(path);
+}
+
+// After the report is written the previous hook runs (the usual message on stderr), then `shutdown`
+pub fn install(shutdown: fn()) {
+    let previous = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        // Nothing in here may panic again, a failed report just means no report
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| write_report(&info.to_string())));
+        previous(info);
+        let _ = panic::catch_unwind(shutdown);
+    }));
+}
+
+// For a go still waiting on its answer when we crash, the first legal move of the last position,
+// so the GUI gets a move instead of a time loss. None when nothing's waiting.
+pub fn fallback_bestmove() -> Option<String> {
+    let history = HISTORY.try_lock_for(LOCK_WAIT)?;
+    let context = CONTEXT.try_lock_for(LOCK_WAIT)?;
+    fallback(&history, context.as_ref()?)
+}
+
+fn fallback(history: &VecDeque<Entry>, context: &CrashContext) -> Option<String> {
+    let waiting = history
+        .iter()
+        .rev()
+        .find_map(|entry| match entry.received {
+            true => (entry.line.split_whitespace().next() == Some("go")).then_some(true),
+            false => entry
+                .line
+                .lines()
+                .any(|line| line.starts_with("bestmove"))
+                .then_some(false),
+        })?;
+    if !waiting {
+        return None;
+    }
+    let board = Board::from_str(&context.fen).ok()?;
+    Some(match MoveGen::new_legal(&board).next() {
+        Some(chess_move) => format!("bestmove {}", chess_move),
+        None => "bestmove 0000".to_string(),
+    })
+}
+
+fn write_report(panic_message: &str) {
+    let path = REPORT_PATH
+        .try_lock_for(LOCK_WAIT)
+        .and_then(|path| path.clone())
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_REPORT_PATH));
+    let _ = fs::write(path, report(panic_message));
+}
+
+fn report(panic_message: &str) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "shallow-red {} crash report ({})", VERSION, BUILD);
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let _ = writeln!(out, "time: {}", now.as_secs());
+    let _ = writeln!(
+        out,
+        "thread: {}",
+        thread::current().name().unwrap_or("unnamed")
+    );
+    let _ = writeln!(out, "panic: {}", panic_message);
+
+    match CONTEXT.try_lock_for(LOCK_WAIT).as_deref() {
+        Some(Some(context)) => {
+            let _ = writeln!(out, "fen: {}", context.fen);
+            let _ = writeln!(out, "moves: {}", context.moves.join(" "));
+            let _ = writeln!(out, "options:");
+            for (name, value) in &context.options {
+                let _ = writeln!(out, "  {} = {}", name, value);
+            }
+        }
+        Some(None) => out.push_str("no command handled yet\n"),
+        None => out.push_str("session snapshot unavailable\n"),
+    }
+
+    let _ = writeln!(out, "recent commands:");
+    match HISTORY.try_lock_for(LOCK_WAIT) {
+        Some(history) => {
+            for entry in history.iter() {
+                let _ = writeln!(
+                    out,
+                    "  {}.{:03} {} {}",
+                    entry.at.as_secs(),
+                    entry.at.subsec_millis(),
+                    if entry.received { "<<" } else { ">>" },
+                    entry.line
+                );
+            }
+        }
+        None => out.push_str("  unavailable\n"),
+    }
+
+    let _ = writeln!(out, "backtrace:\n{}", Backtrace::force_capture());
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::UciSession;
+
+    #[test]
+    fn test_panic_writes_report() {
+        let path = std::env::temp_dir().join("shallow-red-crash-test.txt");
+        let _ = fs::remove_file(&path);
+        set_report_path(path.clone());
+        install(|| {});
+
+        let mut session = UciSession::new(None);
+        session.handle("position startpos moves e2e4");
+        session.handle("setoption name OwnBook value true");
+        let crashed = panic::catch_unwind(AssertUnwindSafe(|| session.handle("debugpanic")));
+        let _ = panic::take_hook();
+        assert!(crashed.is_err());
+
+        let report = fs::read_to_string(&path).unwrap();
+        assert!(report.starts_with("shallow-red "));
+        assert!(report.contains("debugpanic requested"));
+        assert!(report.contains("fen: rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq"));
+        assert!(report.contains("moves: e2e4\n"));
+        assert!(report.contains("  OwnBook = true\n"));
+        assert!(report.contains(" << position startpos moves e2e4\n"));
+        assert!(report.contains(" << debugpanic\n"));
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_fallback_only_for_a_waiting_go() {
+        let entry = |received, line: &str| Entry {
+            at: Duration::ZERO,
+            received,
+            line: line.to_string(),
+        };
+        let board = Board::from_str("4k3/8/8/8/8/8/8/4K2R w K - 0 1").unwrap();
+        let context = CrashContext {
+            fen: board.to_string(),
+            ..Default::default()
+        };
+        let first = MoveGen::new_legal(&board).next().unwrap();
+
+        let mut history =
+            VecDeque::from([entry(true, "position fen 4k3/8/8/8/8/8/8/4K2R w K - 0 1")]);
+        assert_eq!(fallback(&history, &context), None);
+        history.push_back(entry(true, "go wtime 1000 btime 1000"));
+        assert_eq!(
+            fallback(&history, &context),
+            Some(format!("bestmove {}", first))
+        );
+        history.push_back(entry(false, "info string book move\nbestmove e1f1"));
+        assert_eq!(fallback(&history, &context), None);
+
+        history.push_back(entry(true, "go infinite"));
+        let mated = CrashContext {
+            fen: "rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            fallback(&history, &mated),
+            Some("bestmove 0000".to_string())
+        );
+    }
+}
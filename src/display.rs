@@ -0,0 +1,387 @@
+// Human readable rendering of search results for console use: a unicode board with the
+// last move highlighted, a text eval bar, the PV in SAN and a depth/time summary.
+use chess::{Board, BoardStatus, ChessMove, Color, File, MoveGen, Piece, Rank, Square};
+use std::{io::IsTerminal, time::Duration};
+
+const EVAL_BAR_WIDTH: usize = 20;
+const PV_MAX_MOVES: usize = 10;
+
+const HIGHLIGHT: &str = "\x1b[43m";
+const BRIGHT: &str = "\x1b[97m";
+const DIM: &str = "\x1b[90m";
+const RESET: &str = "\x1b[0m";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Score {
+    Centipawns(i32),
+    Mate(i32), // Moves to mate, negative when we are getting mated
+}
+
+const WDL_DRAW_MARGIN: f64 = 150.0; // Eval where a win is as likely as not
+const WDL_SCALE: f64 = 100.0;
+const MATE_CP: i32 = 100_000; // Centipawns a backend gives for mate on the board, less a point per ply
+const MAX_MATE_PLIES: i32 = 1000;
+
+impl Score {
+    // Win/draw/loss per mille for the side the score belongs to. A rough logistic model rather
+    // than one fitted to the engine's games: level is mostly drawn, 1.5 pawns up wins half the time.
+    pub fn wdl(&self) -> (u32, u32, u32) {
+        let cp = match self {
+            Score::Centipawns(cp) => *cp as f64,
+            Score::Mate(moves) if *moves > 0 => return (1000, 0, 0),
+            Score::Mate(_) => return (0, 0, 1000),
+        };
+        let wins = |cp: f64| (1000.0 / (1.0 + ((WDL_DRAW_MARGIN - cp) / WDL_SCALE).exp())).round();
+        let (win, loss) = (wins(cp) as u32, wins(-cp) as u32);
+        (win, 1000u32.saturating_sub(win + loss), loss)
+    }
+
+    // Backends that give a mate as MATE_CP less the plies to it, as a mate in moves. Winning in
+    // 3 plies is mate 2, getting mated in 4 is mate -2.
+    pub fn normalised(self) -> Score {
+        let Score::Centipawns(cp) = self else {
+            return self;
+        };
+        let plies = MATE_CP - cp.saturating_abs();
+        match plies {
+            0..=MAX_MATE_PLIES if cp > 0 => Score::Mate((plies + 1) / 2),
+            0..=MAX_MATE_PLIES => Score::Mate(-(plies + 1) / 2),
+            _ if plies < 0 => Score::Mate(cp.signum()), // Past MATE_CP, mate in some count
+            _ => self,
+        }
+    }
+
+    // The same score for the other side
+    pub fn negated(self) -> Score {
+        match self {
+            Score::Centipawns(cp) => Score::Centipawns(cp.saturating_neg()),
+            Score::Mate(moves) => Score::Mate(-moves),
+        }
+    }
+
+    // As it goes after `score` in an info line
+    pub fn uci(&self) -> String {
+        match self {
+            Score::Centipawns(cp) => format!("cp {}", cp),
+            Score::Mate(moves) => format!("mate {}", moves),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Style {
+    pub color: bool,
+}
+
+impl Style {
+    // Colors only when asked for and stdout is a terminal that hasn't opted out via NO_COLOR
+    pub fn detect(no_color: bool) -> Style {
+        Style {
+            color: !no_color
+                && std::env::var_os("NO_COLOR").is_none()
+                && std::io::stdout().is_terminal(),
+        }
+    }
+}
+
+pub fn render_analysis(
+    before: &Board,
+    pv: &[ChessMove],
+    move_number: u32,
+    score: Option<Score>,
+    depth: Option<u8>,
+    elapsed: Duration,
+    style: Style,
+) -> String {
+    let (board, last_move) = match pv.first() {
+        Some(played) => (before.make_move_new(*played), Some(*played)),
+        None => (*before, None),
+    };
+
+    let mut out = render_board(&board, last_move, style);
+    if let Some(score) = score {
+        out += &render_eval_bar(score, EVAL_BAR_WIDTH, style);
+        out += "\n";
+    }
+    if !pv.is_empty() {
+        out += &format!("pv {}\n", render_pv(before, pv, move_number, PV_MAX_MOVES));
+    }
+    out += &render_summary(depth, elapsed);
+    out
+}
+
+pub fn render_board(board: &Board, last_move: Option<ChessMove>, style: Style) -> String {
+    let highlighted = |square: Square| {
+        last_move.is_some_and(|m| m.get_source() == square || m.get_dest() == square)
+    };
+
+    let mut out = String::new();
+    for rank in (0..8).rev() {
+        let mut row = format!("{} ", rank + 1);
+        for file in 0..8 {
+            let square = Square::make_square(Rank::from_index(rank), File::from_index(file));
+            let glyph = glyph(board.piece_on(square), board.color_on(square));
+            row += &match (highlighted(square), style.color) {
+                (true, true) => format!("{HIGHLIGHT} {glyph} {RESET}"),
+                (true, false) => format!("[{glyph}]"),
+                (false, _) => format!(" {glyph} "),
+            };
+        }
+        out += row.trim_end();
+        out += "\n";
+    }
+    out += "   a  b  c  d  e  f  g  h\n";
+    out
+}
+
+fn glyph(piece: Option<Piece>, color: Option<Color>) -> char {
+    match (piece, color) {
+        (Some(Piece::King), Some(Color::White)) => '♔',
+        (Some(Piece::Queen), Some(Color::White)) => '♕',
+        (Some(Piece::Rook), Some(Color::White)) => '♖',
+        (Some(Piece::Bishop), Some(Color::White)) => '♗',
+        (Some(Piece::Knight), Some(Color::White)) => '♘',
+        (Some(Piece::Pawn), Some(Color::White)) => '♙',
+        (Some(Piece::King), Some(Color::Black)) => '♚',
+        (Some(Piece::Queen), Some(Color::Black)) => '♛',
+        (Some(Piece::Rook), Some(Color::Black)) => '♜',
+        (Some(Piece::Bishop), Some(Color::Black)) => '♝',
+        (Some(Piece::Knight), Some(Color::Black)) => '♞',
+        (Some(Piece::Pawn), Some(Color::Black)) => '♟',
+        _ => '·',
+    }
+}
+
+// White's share of the gauge, scores are from white's point of view
+pub fn render_eval_bar(score: Score, width: usize, style: Style) -> String {
+    let (share, label) = match score {
+        Score::Centipawns(cp) => (
+            1.0 / (1.0 + 10f64.powf(-(cp as f64) / 400.0)),
+            format!("{:+.2}", cp as f64 / 100.0),
+        ),
+        Score::Mate(moves) => (if moves > 0 { 1.0 } else { 0.0 }, format!("#{}", moves)),
+    };
+    let filled = ((share * width as f64).round() as usize).min(width);
+
+    let white = "█".repeat(filled);
+    let black = "░".repeat(width - filled);
+    if style.color {
+        format!("[{BRIGHT}{white}{DIM}{black}{RESET}] {label}")
+    } else {
+        format!("[{white}{black}] {label}")
+    }
+}
+
+// Numbered SAN line starting at `move_number`, cut off after `max_moves` plies
+pub fn render_pv(board: &Board, pv: &[ChessMove], move_number: u32, max_moves: usize) -> String {
+    let mut board = *board;
+    let mut move_number = move_number;
+    let mut parts: Vec<String> = Vec::new();
+
+    for (i, chess_move) in pv.iter().take(max_moves).enumerate() {
+        match board.side_to_move() {
+            Color::White => parts.push(format!("{}.", move_number)),
+            Color::Black if i == 0 => parts.push(format!("{}...", move_number)),
+            Color::Black => {}
+        }
+        parts.push(san(&board, *chess_move));
+        if board.side_to_move() == Color::Black {
+            move_number += 1;
+        }
+        board = board.make_move_new(*chess_move);
+    }
+
+    if pv.len() > max_moves {
+        parts.push(format!("… (+{})", pv.len() - max_moves));
+    }
+    parts.join(" ")
+}
+
+pub fn san(board: &Board, chess_move: ChessMove) -> String {
+    let source = chess_move.get_source();
+    let dest = chess_move.get_dest();
+    let piece = board.piece_on(source).unwrap_or(Piece::Pawn);
+
+    let mut out = if piece == Piece::King
+        && source
+            .get_file()
+            .to_index()
+            .abs_diff(dest.get_file().to_index())
+            == 2
+    {
+        if dest.get_file() == File::G {
+            "O-O".to_string()
+        } else {
+            "O-O-O".to_string()
+        }
+    } else {
+        let capture = board.piece_on(dest).is_some()
+            || (piece == Piece::Pawn && source.get_file() != dest.get_file());
+        let mut out = String::new();
+        if piece == Piece::Pawn {
+            if capture {
+                out.push(file_char(source));
+            }
+        } else {
+            out.push(piece_char(piece));
+            out += &disambiguation(board, chess_move, piece);
+        }
+        if capture {
+            out.push('x');
+        }
+        out += &dest.to_string();
+        if let Some(promotion) = chess_move.get_promotion() {
+            out.push('=');
+            out.push(piece_char(promotion));
+        }
+        out
+    };
+
+    let after = board.make_move_new(chess_move);
+    if after.status() == BoardStatus::Checkmate {
+        out.push('#');
+    } else if after.checkers().popcnt() > 0 {
+        out.push('+');
+    }
+    out
+}
+
+fn disambiguation(board: &Board, chess_move: ChessMove, piece: Piece) -> String {
+    let source = chess_move.get_source();
+    let rivals: Vec<Square> = MoveGen::new_legal(board)
+        .filter(|m| {
+            m.get_dest() == chess_move.get_dest()
+                && m.get_source() != source
+                && board.piece_on(m.get_source()) == Some(piece)
+        })
+        .map(|m| m.get_source())
+        .collect();
+
+    if rivals.is_empty() {
+        String::new()
+    } else if rivals.iter().all(|s| s.get_file() != source.get_file()) {
+        file_char(source).to_string()
+    } else if rivals.iter().all(|s| s.get_rank() != source.get_rank()) {
+        (source.get_rank().to_index() + 1).to_string()
+    } else {
+        source.to_string()
+    }
+}
+
+fn file_char(square: Square) -> char {
+    (b'a' + square.get_file().to_index() as u8) as char
+}
+
+fn piece_char(piece: Piece) -> char {
+    match piece {
+        Piece::Pawn => 'P',
+        Piece::Knight => 'N',
+        Piece::Bishop => 'B',
+        Piece::Rook => 'R',
+        Piece::Queen => 'Q',
+        Piece::King => 'K',
+    }
+}
+
+pub fn render_summary(depth: Option<u8>, elapsed: Duration) -> String {
+    match depth {
+        Some(depth) => format!("depth {}  time {:.2}s\n", depth, elapsed.as_secs_f64()),
+        None => format!("time {:.2}s\n", elapsed.as_secs_f64()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    const PLAIN: Style = Style { color: false };
+
+    fn moves(line: &str) -> Vec<ChessMove> {
+        line.split_whitespace()
+            .map(|m| ChessMove::from_str(m).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_wdl() {
+        assert_eq!(Score::Centipawns(0).wdl(), (182, 636, 182));
+        assert_eq!(Score::Centipawns(150).wdl(), (500, 453, 47));
+        assert_eq!(Score::Mate(-2).wdl(), (0, 0, 1000));
+        let (win, draw, loss) = Score::Centipawns(5000).wdl();
+        assert_eq!(win + draw + loss, 1000);
+    }
+
+    #[test]
+    fn test_mate_scores_normalised() {
+        assert_eq!(Score::Centipawns(MATE_CP - 3).normalised(), Score::Mate(2));
+        assert_eq!(
+            Score::Centipawns(-(MATE_CP - 4)).normalised(),
+            Score::Mate(-2)
+        );
+        assert_eq!(Score::Centipawns(i32::MIN).normalised(), Score::Mate(-1));
+        assert_eq!(
+            Score::Centipawns(-250).normalised(),
+            Score::Centipawns(-250)
+        );
+        assert_eq!(Score::Mate(3).normalised(), Score::Mate(3));
+        assert_eq!(Score::Centipawns(MATE_CP - 1).normalised().uci(), "mate 1");
+    }
+
+    #[test]
+    fn test_board_highlights_last_move() {
+        let e2e4 = moves("e2e4")[0];
+        let board = Board::default().make_move_new(e2e4);
+        let expected = "\
+8  ♜  ♞  ♝  ♛  ♚  ♝  ♞  ♜
+7  ♟  ♟  ♟  ♟  ♟  ♟  ♟  ♟
+6  ·  ·  ·  ·  ·  ·  ·  ·
+5  ·  ·  ·  ·  ·  ·  ·  ·
+4  ·  ·  ·  · [♙] ·  ·  ·
+3  ·  ·  ·  ·  ·  ·  ·  ·
+2  ♙  ♙  ♙  ♙ [·] ♙  ♙  ♙
+1  ♖  ♘  ♗  ♕  ♔  ♗  ♘  ♖
+   a  b  c  d  e  f  g  h
+";
+        assert_eq!(render_board(&board, Some(e2e4), PLAIN), expected);
+    }
+
+    #[test]
+    fn test_eval_bar_extremes() {
+        assert_eq!(
+            render_eval_bar(Score::Mate(3), 20, PLAIN),
+            "[████████████████████] #3"
+        );
+        assert_eq!(
+            render_eval_bar(Score::Mate(-2), 20, PLAIN),
+            "[░░░░░░░░░░░░░░░░░░░░] #-2"
+        );
+        assert_eq!(
+            render_eval_bar(Score::Centipawns(-5000), 20, PLAIN),
+            "[░░░░░░░░░░░░░░░░░░░░] -50.00"
+        );
+        assert_eq!(
+            render_eval_bar(Score::Centipawns(0), 20, PLAIN),
+            "[██████████░░░░░░░░░░] +0.00"
+        );
+    }
+
+    #[test]
+    fn test_pv_san_and_truncation() {
+        let pv = moves("e2e4 e7e5 g1f3 b8c6 f1b5 a7a6");
+        assert_eq!(
+            render_pv(&Board::default(), &pv, 1, 10),
+            "1. e4 e5 2. Nf3 Nc6 3. Bb5 a6"
+        );
+        assert_eq!(
+            render_pv(&Board::default(), &pv, 1, 4),
+            "1. e4 e5 2. Nf3 Nc6 … (+2)"
+        );
+
+        let black_to_move = Board::default().make_move_new(pv[0]);
+        assert_eq!(
+            render_pv(&black_to_move, &pv[1..3], 1, 10),
+            "1... e5 2. Nf3"
+        );
+    }
+}
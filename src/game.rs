@@ -0,0 +1,319 @@
+// The game in progress as the GUI describes it with `position`, plus how it ended once a
+// result comes in. Finished games go to the PGN File and the Career File statistics.
+use chess::{Board, ChessMove, Color, Piece};
+use std::{fmt, fs, io, io::Write, path::Path, str::FromStr};
+use toml::{Table, Value};
+
+use crate::{display::render_pv, ponder::PonderStats};
+
+pub const ENGINE_NAME: &str = "shallow-red";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameResult {
+    WhiteWins,
+    BlackWins,
+    Draw,
+    Unfinished, // "*"
+}
+
+impl FromStr for GameResult {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "1-0" => Ok(GameResult::WhiteWins),
+            "0-1" => Ok(GameResult::BlackWins),
+            "1/2-1/2" => Ok(GameResult::Draw),
+            "*" => Ok(GameResult::Unfinished),
+            _ => Err(format!("unknown result {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for GameResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GameResult::WhiteWins => write!(f, "1-0"),
+            GameResult::BlackWins => write!(f, "0-1"),
+            GameResult::Draw => write!(f, "1/2-1/2"),
+            GameResult::Unfinished => write!(f, "*"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct GameRecord {
+    pub start: Board,
+    pub start_halfmoves: u32, // Halfmove clock of the start FEN, 0 for startpos
+    pub start_fullmove: u32,  // Fullmove number of the start FEN, 1 for startpos
+    pub moves: Vec<ChessMove>,
+    pub our_color: Option<Color>, // Side we were last asked to move for
+    pub opponent: Option<String>,
+    pub result: Option<GameResult>,
+    pub comment: Option<String>, // e.g. "White mates" from `result 1-0 {White mates}`
+    pub tags: Vec<(String, String)>, // Replace the ? in Event/Site/Round, anything else goes after Result
+}
+
+// The clock after `chess_move`, en passant counts as it's a pawn move
+pub fn next_halfmove_clock(board: &Board, chess_move: ChessMove, clock: u32) -> u32 {
+    let pawn = board.piece_on(chess_move.get_source()) == Some(Piece::Pawn);
+    match pawn || board.piece_on(chess_move.get_dest()).is_some() {
+        true => 0,
+        false => clock + 1,
+    }
+}
+
+impl GameRecord {
+    // Mirrors `position ... moves ...`
+    pub fn record_position(&mut self, start: Board, moves: &[ChessMove]) {
+        self.start = start;
+        self.moves = moves.to_vec();
+    }
+
+    // Hashes of the start and of every position after it, for spotting repetitions
+    pub fn position_hashes(&self) -> Vec<u64> {
+        let mut board = self.start;
+        let mut hashes = vec![board.get_hash()];
+        for chess_move in &self.moves {
+            board = board.make_move_new(*chess_move);
+            hashes.push(board.get_hash());
+        }
+        hashes
+    }
+
+    // Plies since the start of the game, those before a start FEN included
+    pub fn ply_count(&self) -> u32 {
+        let before_start = self.start_fullmove.max(1) - 1;
+        let black_first = (self.start.side_to_move() == Color::Black) as u32;
+        before_start
+            .saturating_mul(2)
+            .saturating_add(black_first)
+            .saturating_add(self.moves.len() as u32)
+    }
+
+    pub fn side_to_move(&self) -> Color {
+        match self.moves.len() % 2 {
+            0 => self.start.side_to_move(),
+            _ => !self.start.side_to_move(),
+        }
+    }
+
+    // Moves the side to move has already made this game, what the time managers go by
+    pub fn moves_made(&self) -> u32 {
+        self.ply_count() / 2
+    }
+
+    // Plies since the last capture or pawn move, 100 and the game can be claimed drawn
+    pub fn halfmove_clock(&self) -> u32 {
+        let mut board = self.start;
+        let mut clock = self.start_halfmoves;
+        for chess_move in &self.moves {
+            clock = next_halfmove_clock(&board, *chess_move, clock);
+            board = board.make_move_new(*chess_move);
+        }
+        clock
+    }
+
+    pub fn to_pgn(&self) -> String {
+        let result = self.result.unwrap_or(GameResult::Unfinished);
+        let opponent = self.opponent.as_deref().unwrap_or("?");
+        let (white, black) = match self.our_color {
+            Some(Color::White) => (ENGINE_NAME, opponent),
+            Some(Color::Black) => (opponent, ENGINE_NAME),
+            None => ("?", "?"),
+        };
+
+        let tag = |name: &str| {
+            self.tags
+                .iter()
+                .find(|(tag, _)| tag == name)
+                .map_or("?", |(_, value)| value.as_str())
+        };
+
+        let mut pgn = format!(
+            "[Event \"{}\"]\n[Site \"{}\"]\n[Date \"????.??.??\"]\n[Round \"{}\"]\n\
+             [White \"{}\"]\n[Black \"{}\"]\n[Result \"{}\"]\n",
+            tag("Event"),
+            tag("Site"),
+            tag("Round"),
+            white,
+            black,
+            result
+        );
+        for (name, value) in &self.tags {
+            if !["Event", "Site", "Round"].contains(&name.as_str()) {
+                pgn += &format!("[{} \"{}\"]\n", name, value);
+            }
+        }
+        if self.start != Board::default() {
+            pgn += &format!("[SetUp \"1\"]\n[FEN \"{}\"]\n", self.start);
+        }
+        pgn += "\n";
+        if !self.moves.is_empty() {
+            pgn += &render_pv(&self.start, &self.moves, 1, self.moves.len());
+            pgn += " ";
+        }
+        if let Some(comment) = &self.comment {
+            pgn += &format!("{{{}}} ", comment);
+        }
+        pgn += &format!("{}\n", result);
+        pgn
+    }
+
+    pub fn append_pgn(&self, path: &Path) -> io::Result<()> {
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        writeln!(file, "{}", self.to_pgn())
+    }
+}
+
+// Running totals from our side of the board, kept in the Career File
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CareerStats {
+    pub games: u64,
+    pub wins: u64,
+    pub draws: u64,
+    pub losses: u64,
+    pub ponder: PonderStats,
+}
+
+impl CareerStats {
+    pub fn load(path: &Path) -> CareerStats {
+        let Some(table) = fs::read_to_string(path)
+            .ok()
+            .and_then(|text| text.parse::<Table>().ok())
+        else {
+            return CareerStats::default();
+        };
+        let count = |key| {
+            table
+                .get(key)
+                .and_then(Value::as_integer)
+                .map_or(0, |count| count.max(0) as u64)
+        };
+        CareerStats {
+            games: count("games"),
+            wins: count("wins"),
+            draws: count("draws"),
+            losses: count("losses"),
+            ponder: table
+                .get("ponder")
+                .and_then(Value::as_table)
+                .map(PonderStats::from_toml)
+                .unwrap_or_default(),
+        }
+    }
+
+    // Unfinished games and games where we never moved don't count
+    pub fn record(&mut self, result: GameResult, our_color: Option<Color>) {
+        let won = match (result, our_color) {
+            (GameResult::Unfinished, _) | (_, None) => return,
+            (GameResult::Draw, _) => None,
+            (GameResult::WhiteWins, Some(color)) => Some(color == Color::White),
+            (GameResult::BlackWins, Some(color)) => Some(color == Color::Black),
+        };
+        self.games += 1;
+        match won {
+            Some(true) => self.wins += 1,
+            Some(false) => self.losses += 1,
+            None => self.draws += 1,
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut table = Table::new();
+        for (key, count) in [
+            ("games", self.games),
+            ("wins", self.wins),
+            ("draws", self.draws),
+            ("losses", self.losses),
+        ] {
+            table.insert(key.to_string(), Value::Integer(count as i64));
+        }
+        table.insert("ponder".to_string(), Value::Table(self.ponder.to_toml()));
+        let text =
+            toml::to_string(&table).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let temp = path.with_extension("tmp");
+        fs::write(&temp, text)?;
+        fs::rename(&temp, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{command::PositionStart, session::parse_position};
+
+    fn record(moves: &str) -> GameRecord {
+        let tokens: Vec<String> = moves.split_whitespace().map(str::to_string).collect();
+        let (start, moves) = parse_position(&PositionStart::StartPos, &tokens, false).unwrap();
+        let mut game = GameRecord::default();
+        game.record_position(start, &moves);
+        game
+    }
+
+    #[test]
+    fn test_pgn_export() {
+        let mut game = record("f2f3 e7e5 g2g4 d8h4");
+        game.our_color = Some(Color::Black);
+        game.opponent = Some("Patzer".to_string());
+        game.result = Some("0-1".parse().unwrap());
+        game.comment = Some("Black mates".to_string());
+        assert_eq!(
+            game.to_pgn(),
+            "[Event \"?\"]\n[Site \"?\"]\n[Date \"????.??.??\"]\n[Round \"?\"]\n\
+             [White \"Patzer\"]\n[Black \"shallow-red\"]\n[Result \"0-1\"]\n\n\
+             1. f3 e5 2. g4 Qh4# {Black mates} 0-1\n"
+        );
+    }
+
+    #[test]
+    fn test_ply_count() {
+        let game = record("e2e4 e7e5 g1f3");
+        assert_eq!(game.ply_count(), 3);
+        assert_eq!(game.side_to_move(), Color::Black);
+        assert_eq!(game.moves_made(), 1);
+
+        let fen = "r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 2 30";
+        let mut game = GameRecord {
+            start_fullmove: 30,
+            ..Default::default()
+        };
+        game.record_position(Board::from_str(fen).unwrap(), &[]);
+        assert_eq!(game.ply_count(), 59);
+        assert_eq!(game.side_to_move(), Color::Black);
+        assert_eq!(game.moves_made(), 29);
+
+        game.start_fullmove = u32::MAX; // A FEN can say anything
+        assert_eq!(game.ply_count(), u32::MAX);
+    }
+
+    #[test]
+    fn test_result_tokens() {
+        assert_eq!("1/2-1/2".parse(), Ok(GameResult::Draw));
+        assert!("2-0".parse::<GameResult>().is_err());
+        assert!("1-0 ".parse::<GameResult>().is_err());
+    }
+
+    #[test]
+    fn test_career_stats() {
+        let mut stats = CareerStats::default();
+        stats.record(GameResult::WhiteWins, Some(Color::White));
+        stats.record(GameResult::WhiteWins, Some(Color::Black));
+        stats.record(GameResult::Draw, Some(Color::Black));
+        stats.record(GameResult::Unfinished, Some(Color::Black));
+        stats.record(GameResult::BlackWins, None);
+        assert_eq!(
+            stats,
+            CareerStats {
+                games: 3,
+                wins: 1,
+                draws: 1,
+                losses: 1,
+                ..Default::default()
+            }
+        );
+    }
+}
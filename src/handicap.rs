@@ -0,0 +1,77 @@
+// UCI_LimitStrength / UCI_Elo. The engine has no strength knob and doesn't score its root moves,
+// so a weaker Shallow Red gets less time to think and now and then plays a random legal move
+// instead of searching. Both scale linearly between MIN_ELO and MAX_ELO.
+use chess::{Board, ChessMove, MoveGen};
+use std::time::Duration;
+
+use crate::rng::SessionRng;
+
+pub const MIN_ELO: i64 = 800;
+pub const MAX_ELO: i64 = 2200; // Roughly full strength, no handicap at all here
+pub const DEFAULT_ELO: i64 = 1500;
+
+const MIN_TIME_SHARE: f64 = 0.1; // Of the normal budget, at MIN_ELO
+const MAX_RANDOM_PER_MILLE: u64 = 300; // Chance of a random move at MIN_ELO
+
+// 0 at MIN_ELO up to 1 at MAX_ELO
+fn strength(elo: i64) -> f64 {
+    (elo.clamp(MIN_ELO, MAX_ELO) - MIN_ELO) as f64 / (MAX_ELO - MIN_ELO) as f64
+}
+
+pub fn time_limit(elo: i64, full: Duration) -> Duration {
+    full.mul_f64(MIN_TIME_SHARE + (1.0 - MIN_TIME_SHARE) * strength(elo))
+        .max(Duration::from_millis(1))
+}
+
+// Some move when this one gets thrown away, only ever one of `allowed` if it's given
+pub fn random_move(
+    elo: i64,
+    board: &Board,
+    allowed: Option<&[ChessMove]>,
+    rng: &mut SessionRng,
+) -> Option<ChessMove> {
+    let per_mille = (MAX_RANDOM_PER_MILLE as f64 * (1.0 - strength(elo))).round() as u64;
+    if rng.below(1000) >= per_mille {
+        return None;
+    }
+    let moves: Vec<ChessMove> = MoveGen::new_legal(board)
+        .filter(|chess_move| allowed.is_none_or(|allowed| allowed.contains(chess_move)))
+        .collect();
+    match moves.len() {
+        0 => None,
+        n => Some(moves[rng.below(n as u64) as usize]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_time_scales_with_elo() {
+        let full = Duration::from_secs(10);
+        assert_eq!(time_limit(MAX_ELO, full), full);
+        assert_eq!(time_limit(MIN_ELO, full), Duration::from_secs(1));
+        assert_eq!(time_limit(400, full), Duration::from_secs(1)); // Clamped
+        assert_eq!(time_limit(1500, full), Duration::from_millis(5500));
+    }
+
+    #[test]
+    fn test_random_moves() {
+        let board = Board::default();
+        let mut rng = SessionRng::seeded(7);
+        assert!((0..200).all(|_| random_move(MAX_ELO, &board, None, &mut rng).is_none()));
+
+        let thrown: Vec<ChessMove> = (0..1000)
+            .filter_map(|_| random_move(MIN_ELO, &board, None, &mut rng))
+            .collect();
+        assert!((200..400).contains(&thrown.len())); // About 30%
+        assert!(thrown.iter().all(|chess_move| board.legal(*chess_move)));
+
+        let only = [ChessMove::from_str("a2a3").unwrap()];
+        assert!((0..200)
+            .filter_map(|_| random_move(MIN_ELO, &board, Some(&only), &mut rng))
+            .all(|chess_move| chess_move == only[0]));
+    }
+}
@@ -0,0 +1,251 @@
+// Small HTTP frontend for tools: POST /analyse and GET /health. Connections are handled one at
+// a time, so concurrent requests simply queue up in the listener backlog.
+use chess::Board;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use shallow_red_engine::utils::engine_interface::EngineSettings;
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    str::FromStr,
+    time::Duration,
+};
+
+use crate::session::UciSession;
+
+pub const DEFAULT_TIME_CAP: Duration = Duration::from_secs(10);
+const DEFAULT_MOVETIME_MS: u64 = 1000;
+
+#[derive(Debug, Deserialize)]
+struct AnalyseRequest {
+    fen: String,
+    #[serde(default = "default_movetime")]
+    movetime_ms: u64,
+    #[serde(default)]
+    multipv: Option<u32>,
+}
+
+fn default_movetime() -> u64 {
+    DEFAULT_MOVETIME_MS
+}
+
+#[derive(Debug, Serialize)]
+struct AnalyseResponse {
+    bestmove: String,
+    score_cp: Option<i32>,
+    pv: Vec<String>,
+    depth: Option<u8>,
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorResponse {
+    error: String,
+}
+
+pub struct HttpServer {
+    listener: TcpListener,
+    session: UciSession,
+    pub time_cap: Duration, // Longest search a single request can ask for
+}
+
+impl HttpServer {
+    pub fn bind(addr: &str, session: UciSession) -> io::Result<HttpServer> {
+        Ok(HttpServer {
+            listener: TcpListener::bind(addr)?,
+            session,
+            time_cap: DEFAULT_TIME_CAP,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn serve(mut self) {
+        info!("Serving HTTP on {:?}", self.local_addr());
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    if let Err(e) = self.handle_connection(stream) {
+                        warn!("HTTP connection failed: {}", e);
+                    }
+                }
+                Err(e) => warn!("HTTP accept failed: {}", e),
+            }
+        }
+    }
+
+    fn handle_connection(&mut self, stream: TcpStream) -> io::Result<()> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+
+        // Only the body length matters out of the headers
+        let mut content_length = 0;
+        loop {
+            let mut header = String::new();
+            if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse().unwrap_or(0);
+                }
+            }
+        }
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body)?;
+
+        info!("HTTP << {}", request_line.trim());
+        let mut parts = request_line.split_whitespace();
+        let (status, response) = match (parts.next(), parts.next()) {
+            (Some("GET"), Some("/health")) => (200, "{\"status\":\"ok\"}".to_string()),
+            (Some("POST"), Some("/analyse")) => self.analyse(&body),
+            (_, Some("/health" | "/analyse")) => (405, error_json("method not allowed")),
+            _ => (404, error_json("not found")),
+        };
+        write_response(stream, status, &response)
+    }
+
+    fn analyse(&mut self, body: &[u8]) -> (u16, String) {
+        let request: AnalyseRequest = match serde_json::from_slice(body) {
+            Ok(request) => request,
+            Err(e) => return (400, error_json(&format!("bad request body: {}", e))),
+        };
+        let board = match Board::from_str(&request.fen) {
+            Ok(board) => board,
+            Err(_) => return (400, error_json(&format!("bad fen: {}", request.fen))),
+        };
+        if request.multipv.is_some_and(|lines| lines > 1) {
+            info!(
+                "multipv {:?} requested, only one line is searched",
+                request.multipv
+            );
+        }
+
+        let settings = EngineSettings {
+            verbose: false,
+            cache_settings: self.session.cache_settings(),
+            time_limit: Duration::from_millis(request.movetime_ms).min(self.time_cap),
+            ..Default::default()
+        };
+        let mut output = String::new();
+        self.session
+            .search_request(board, settings)
+            .run(|out| output = out);
+
+        // bestmove and, with Ponder on, the reply we expect
+        let pv: Vec<String> = output
+            .lines()
+            .find_map(|line| line.strip_prefix("bestmove "))
+            .unwrap_or_default()
+            .split_whitespace()
+            .filter(|word| *word != "ponder")
+            .map(str::to_string)
+            .collect();
+        let response = AnalyseResponse {
+            bestmove: pv.first().cloned().unwrap_or_default(),
+            pv,
+            score_cp: None, // The engine doesn't report scores or depth
+            depth: None,
+        };
+        (
+            200,
+            serde_json::to_string(&response).expect("Response should serialize"),
+        )
+    }
+}
+
+fn error_json(error: &str) -> String {
+    serde_json::to_string(&ErrorResponse {
+        error: error.to_string(),
+    })
+    .expect("Error should serialize")
+}
+
+fn write_response(mut stream: TcpStream, status: u16, body: &str) -> io::Result<()> {
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        _ => "Method Not Allowed",
+    };
+    info!("HTTP >> {} {}", status, body);
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MockBackend;
+    use std::{sync::Arc, thread};
+
+    fn start_server() -> SocketAddr {
+        let mut session = UciSession::new(None);
+        session.backend = Arc::new(MockBackend);
+        let server = HttpServer::bind("127.0.0.1:0", session).unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.serve());
+        addr
+    }
+
+    fn request(addr: SocketAddr, method: &str, path: &str, body: &str) -> (String, String) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{}",
+            method,
+            path,
+            body.len(),
+            body
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        (head.lines().next().unwrap().to_string(), body.to_string())
+    }
+
+    #[test]
+    fn test_analyse_round_trip() {
+        let addr = start_server();
+        let (status, body) = request(
+            addr,
+            "POST",
+            "/analyse",
+            r#"{ "fen": "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1", "movetime_ms": 50, "multipv": 2 }"#,
+        );
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        let response: serde_json::Value = serde_json::from_str(&body).unwrap();
+        let bestmove = response["bestmove"].as_str().unwrap();
+        assert_eq!(bestmove.len(), 4);
+        assert_eq!(response["pv"][0].as_str(), Some(bestmove));
+
+        let (status, _) = request(addr, "GET", "/health", "");
+        assert_eq!(status, "HTTP/1.1 200 OK");
+    }
+
+    #[test]
+    fn test_bad_requests() {
+        let addr = start_server();
+        let (status, body) = request(addr, "POST", "/analyse", r#"{ "fen": "not a fen" }"#);
+        assert_eq!(status, "HTTP/1.1 400 Bad Request");
+        assert!(body.contains("bad fen"));
+
+        let (status, _) = request(addr, "POST", "/analyse", "{");
+        assert_eq!(status, "HTTP/1.1 400 Bad Request");
+
+        let (status, _) = request(addr, "GET", "/analyse", "");
+        assert_eq!(status, "HTTP/1.1 405 Method Not Allowed");
+        let (status, _) = request(addr, "GET", "/", "");
+        assert_eq!(status, "HTTP/1.1 404 Not Found");
+    }
+}
@@ -0,0 +1,197 @@
+// --log-format json: one JSON object per log line for shipping to ELK or Loki. The main loop's
+// "Received << ..." and "Sent >> ..." and the worker's "Search summary: ..." become received,
+// sent and search events with their fields pulled out, everything else is a log event carrying
+// the message as it is.
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use parking_lot::Mutex;
+use std::{
+    io::Write,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+struct JsonLogger {
+    sink: Mutex<Box<dyn Write + Send>>,
+    level: LevelFilter,
+}
+
+impl Log for JsonLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let line = json_line(at.as_millis(), record.level(), &record.args().to_string());
+        // One write so the log file only ever rotates between lines
+        let _ = self.sink.lock().write_all(format!("{}\n", line).as_bytes());
+    }
+
+    fn flush(&self) {
+        let _ = self.sink.lock().flush();
+    }
+}
+
+pub fn install(sink: Box<dyn Write + Send>, level: LevelFilter) {
+    let logger = Box::leak(Box::new(JsonLogger {
+        sink: Mutex::new(sink),
+        level,
+    }));
+    if log::set_logger(logger).is_ok() {
+        log::set_max_level(level);
+    }
+}
+
+fn json_line(at_ms: u128, level: Level, message: &str) -> String {
+    let mut fields = vec![("ts", at_ms.to_string()), ("level", quoted(level.as_str()))];
+    fields.extend(event_fields(message));
+    let fields: Vec<String> = fields
+        .iter()
+        .map(|(key, value)| format!("\"{}\":{}", key, value))
+        .collect();
+    format!("{{{}}}", fields.join(","))
+}
+
+fn event_fields(message: &str) -> Vec<(&'static str, String)> {
+    if let Some(command) = message.strip_prefix("Received << ") {
+        return vec![("event", quoted("received")), ("command", quoted(command))];
+    }
+    if let Some(out) = message.strip_prefix("Sent >> ") {
+        let response = sent_text(out).map_or("null".to_string(), |text| quoted(&text));
+        return vec![("event", quoted("sent")), ("response", response)];
+    }
+    if let Some(fields) = message
+        .strip_prefix("Search summary: ")
+        .and_then(summary_fields)
+    {
+        return fields;
+    }
+    vec![("event", quoted("log")), ("message", quoted(message))]
+}
+
+// The main loop logs its reply with {:#?}, so it's None or a pretty printed Some("...")
+fn sent_text(out: &str) -> Option<String> {
+    if out == "None" {
+        return None;
+    }
+    let Some(inner) = out
+        .strip_prefix("Some(")
+        .and_then(|out| out.strip_suffix(')'))
+    else {
+        return Some(out.to_string());
+    };
+    let literal = inner.trim().trim_end_matches(',');
+    let literal = literal
+        .strip_prefix('"')
+        .and_then(|literal| literal.strip_suffix('"'))
+        .unwrap_or(literal);
+    Some(unescape_debug(literal))
+}
+
+fn unescape_debug(literal: &str) -> String {
+    let mut text = String::new();
+    let mut chars = literal.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            text.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => text.push('\n'),
+            Some('r') => text.push('\r'),
+            Some('t') => text.push('\t'),
+            Some('0') => text.push('\0'),
+            Some('u') => {
+                let code: String = chars.by_ref().skip(1).take_while(|c| *c != '}').collect();
+                if let Some(c) = u32::from_str_radix(&code, 16).ok().and_then(char::from_u32) {
+                    text.push(c);
+                }
+            }
+            Some(escaped) => text.push(escaped), // \" \' \\
+            None => text.push('\\'),
+        }
+    }
+    text
+}
+
+// "bestmove e2e4, score cp 30, nodes 1234, time 812ms, fen <fen>", none where there's nothing
+fn summary_fields(summary: &str) -> Option<Vec<(&'static str, String)>> {
+    let mut fields = vec![("event", quoted("search"))];
+    for part in summary.split(", ") {
+        let (key, value) = part.split_once(' ')?;
+        let field = match (key, value) {
+            (_, "none") => "null".to_string(),
+            ("nodes", nodes) => nodes.parse::<u64>().ok()?.to_string(),
+            ("time", time) => time.strip_suffix("ms")?.parse::<u64>().ok()?.to_string(),
+            (_, value) => quoted(value),
+        };
+        let key = match key {
+            "bestmove" => "bestmove",
+            "score" => "score",
+            "nodes" => "nodes",
+            "time" => "time_ms",
+            "fen" => "fen",
+            _ => return None,
+        };
+        fields.push((key, field));
+    }
+    Some(fields)
+}
+
+fn quoted(text: &str) -> String {
+    let mut out = "\"".to_string();
+    for c in text.chars() {
+        match c {
+            '"' => out += "\\\"",
+            '\\' => out += "\\\\",
+            '\n' => out += "\\n",
+            '\r' => out += "\\r",
+            '\t' => out += "\\t",
+            c if (c as u32) < 0x20 => out += &format!("\\u{:04x}", c as u32),
+            c => out.push(c),
+        }
+    }
+    out + "\""
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_events() {
+        assert_eq!(
+            json_line(5, Level::Info, "Received << go wtime 1000"),
+            r#"{"ts":5,"level":"INFO","event":"received","command":"go wtime 1000"}"#
+        );
+        assert_eq!(
+            json_line(
+                5,
+                Level::Info,
+                "Sent >> Some(\n    \"info string \\\"x\\\"\\nbestmove e2e4\",\n)"
+            ),
+            r#"{"ts":5,"level":"INFO","event":"sent","response":"info string \"x\"\nbestmove e2e4"}"#
+        );
+        assert_eq!(
+            json_line(5, Level::Info, "Sent >> None"),
+            r#"{"ts":5,"level":"INFO","event":"sent","response":null}"#
+        );
+        assert_eq!(
+            json_line(
+                5,
+                Level::Info,
+                "Search summary: bestmove e2e4, score cp 30, nodes none, time 812ms, \
+                 fen rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"
+            ),
+            r#"{"ts":5,"level":"INFO","event":"search","bestmove":"e2e4","score":"cp 30","nodes":null,"time_ms":812,"fen":"rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"}"#
+        );
+        assert_eq!(
+            json_line(5, Level::Warn, "Search summary: something else"),
+            r#"{"ts":5,"level":"WARN","event":"log","message":"Search summary: something else"}"#
+        );
+    }
+}
@@ -0,0 +1,41 @@
+pub mod backend;
+#[cfg(feature = "bench")]
+pub mod bench;
+pub mod book;
+pub mod capture;
+pub mod check;
+pub mod chess960;
+pub mod command;
+pub mod console;
+pub mod crash;
+pub mod display;
+pub mod game;
+pub mod handicap;
+#[cfg(feature = "http")]
+pub mod http;
+pub mod jsonlog;
+pub mod logfile;
+pub mod logreport;
+pub mod mate;
+pub mod options;
+pub mod polyglot;
+pub mod ponder;
+pub mod profiles;
+pub mod repertoire;
+pub mod response;
+pub mod rng;
+pub mod search;
+pub mod selfcheck;
+pub mod selfplay;
+pub mod session;
+pub mod symtest;
+pub mod tablebase;
+pub mod timecontrol;
+pub mod timeusage;
+pub mod training;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod uci;
+pub mod version;
+
+#[cfg(feature = "wasm")]
+pub mod wasm;
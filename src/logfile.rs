@@ -0,0 +1,112 @@
+// Size based rotation for the log file, so a long lichess-bot session can't fill the disk. Once
+// the log passes max_bytes it moves to .1, .1 moves to .2 and so on, and whatever was at .keep is
+// gone. It's only ever rotated at the end of a line, so no log line is split between two files.
+use std::{
+    ffi::OsString,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+pub const DEFAULT_MAX_MB: u64 = 10;
+pub const DEFAULT_KEEP: u32 = 3;
+
+pub struct RotatingLog {
+    path: PathBuf,
+    file: File,
+    written: u64,   // Since the last rotation, what's already there included
+    max_bytes: u64, // 0 never rotates
+    keep: u32,
+}
+
+impl RotatingLog {
+    // Appends to what's there, a restart doesn't lose the last game's log
+    pub fn open(path: &Path, max_bytes: u64, keep: u32) -> io::Result<RotatingLog> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(RotatingLog {
+            path: path.to_path_buf(),
+            written: file.metadata()?.len(),
+            file,
+            max_bytes,
+            keep,
+        })
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.keep == 0 {
+            self.file = File::create(&self.path)?;
+        } else {
+            for n in (1..self.keep).rev() {
+                let _ = fs::rename(rotated(&self.path, n), rotated(&self.path, n + 1));
+            }
+            fs::rename(&self.path, rotated(&self.path, 1))?;
+            self.file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+        }
+        self.written = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingLog {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.file.write(buf)?;
+        self.written += n as u64;
+        if self.max_bytes > 0 && self.written >= self.max_bytes && buf[..n].ends_with(b"\n") {
+            self.rotate()?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+// shallow-red.log.2 for n = 2
+fn rotated(path: &Path, n: u32) -> PathBuf {
+    let mut name = OsString::from(path);
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotation_keeps_the_newest() {
+        let dir = std::env::temp_dir().join("shallow-red-logfile-test");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("test.log");
+
+        let mut log = RotatingLog::open(&path, 10, 2).unwrap();
+        for line in [
+            "first line\n",
+            "second",
+            " line\n",
+            "third line\n",
+            "fourth\n",
+        ] {
+            log.write_all(line.as_bytes()).unwrap();
+        }
+        log.flush().unwrap();
+
+        // The second line went over in two writes but stayed in one file
+        assert_eq!(fs::read_to_string(&path).unwrap(), "fourth\n");
+        assert_eq!(
+            fs::read_to_string(rotated(&path, 1)).unwrap(),
+            "third line\n"
+        );
+        assert_eq!(
+            fs::read_to_string(rotated(&path, 2)).unwrap(),
+            "second line\n"
+        );
+        assert!(!rotated(&path, 3).exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,323 @@
+// --logreport <file>: reads back a shallow-red.log and summarises it per game and per search.
+// Log lines are matched on the messages main and the session write ("Received << ...",
+// "Sent >> ...", "Game over ..."), after whatever timestamp prefix the logger put in front.
+// Debug output spans several lines and lines from the worker thread interleave with the main
+// loop's, so anything unexpected is tallied and skipped rather than trusted.
+use chess::{Board, ChessMove};
+use std::{collections::BTreeMap, str::FromStr};
+
+const SLOWEST: usize = 10;
+const DAY_MS: i64 = 24 * 60 * 60 * 1000;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Latency {
+    pub ms: i64,
+    pub fen: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TimeControlStats {
+    pub searches: u32,
+    pub depth_total: u64,
+    pub depth_samples: u32,
+    pub nps_total: u64,
+    pub nps_samples: u32,
+}
+
+impl TimeControlStats {
+    fn average(total: u64, samples: u32) -> String {
+        match samples {
+            0 => "n/a".to_string(),
+            samples => (total / samples as u64).to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LogReport {
+    pub games: u32,
+    pub results: BTreeMap<String, u32>, // Result token to count
+    pub unrecorded: u32,                // Games that ended without a result
+    pub time_losses: u32,
+    pub fallbacks: u32, // Engine moves that were rejected and substituted
+    pub watchdog: u32,
+    pub time_controls: BTreeMap<String, TimeControlStats>,
+    pub slowest: Vec<Latency>, // go to bestmove, slowest first
+    pub malformed: u32,
+}
+
+#[derive(Debug, Default)]
+struct Game {
+    active: bool, // Had a search or a result
+    result: Option<String>,
+    time_control: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct Parser {
+    report: LogReport,
+    game: Game,
+    board: Board,
+    pending_go: Option<(Option<i64>, String)>, // When the go came in, and the FEN it was for
+    last_sent: Option<Option<i64>>, // Timestamp of the last Sent line, for its continuation
+    latencies: Vec<Latency>,
+}
+
+impl Parser {
+    fn line(&mut self, line: &str) {
+        if line.trim().is_empty() {
+            return;
+        }
+        // Continuation of multi-line debug output, only Sent >> Some(...) is worth looking at
+        if line.starts_with(char::is_whitespace) || line.starts_with([')', '}', ']']) {
+            if let Some(at) = self.last_sent {
+                self.sent(at, line.trim().trim_matches(|c| c == '"' || c == ','));
+            }
+            return;
+        }
+        self.last_sent = None;
+
+        let at = timestamp(line);
+        if let Some(command) = message(line, "Received << ") {
+            self.received(at, command);
+        } else if let Some(out) = message(line, "Sent >> ") {
+            self.last_sent = Some(at);
+            self.sent(at, out);
+        } else if line.contains("Engine returned") {
+            self.report.fallbacks += 1;
+        } else if line.to_lowercase().contains("watchdog") {
+            self.report.watchdog += 1;
+        } else if at.is_none() {
+            self.report.malformed += 1; // Torn or partial line
+        }
+    }
+
+    fn received(&mut self, at: Option<i64>, command: &str) {
+        let parsed: Vec<&str> = command.split_whitespace().collect();
+        match parsed.first().copied() {
+            Some("ucinewgame") => self.end_game(),
+            Some("position") => match position(&parsed) {
+                Some(board) => self.board = board,
+                None => self.report.malformed += 1,
+            },
+            Some("go") => {
+                self.game.active = true;
+                if self.game.time_control.is_none() {
+                    self.game.time_control = Some(time_control(&parsed, &self.board));
+                }
+                let time_control = self.game.time_control.clone().unwrap_or_default();
+                self.report
+                    .time_controls
+                    .entry(time_control)
+                    .or_default()
+                    .searches += 1;
+                self.pending_go = Some((at, self.board.to_string()));
+            }
+            Some("result") | Some("gameresult") => match parsed.get(1) {
+                Some(token) if ["1-0", "0-1", "1/2-1/2", "*"].contains(token) => {
+                    self.game.active = true;
+                    self.game.result = Some(token.to_string());
+                    if command.to_lowercase().contains("time") {
+                        self.report.time_losses += 1;
+                    }
+                }
+                _ => self.report.malformed += 1,
+            },
+            Some(_) => {}
+            None => self.report.malformed += 1,
+        }
+    }
+
+    fn sent(&mut self, at: Option<i64>, out: &str) {
+        let stats = match &self.game.time_control {
+            Some(time_control) => self.report.time_controls.get_mut(time_control),
+            None => None,
+        };
+        if let Some(stats) = stats {
+            if let Some(depth) = token_after(out, "depth") {
+                stats.depth_total += depth;
+                stats.depth_samples += 1;
+            }
+            if let Some(nps) = token_after(out, "nps") {
+                stats.nps_total += nps;
+                stats.nps_samples += 1;
+            }
+        }
+        if !out.contains("bestmove ") {
+            return;
+        }
+        if let Some((go_at, fen)) = self.pending_go.take() {
+            if let (Some(go_at), Some(at)) = (go_at, at) {
+                self.latencies.push(Latency {
+                    ms: (at - go_at).rem_euclid(DAY_MS), // The clock may have passed midnight
+                    fen,
+                });
+            }
+        }
+    }
+
+    fn end_game(&mut self) {
+        let game = std::mem::take(&mut self.game);
+        self.board = Board::default();
+        self.pending_go = None;
+        if !game.active {
+            return;
+        }
+        self.report.games += 1;
+        match game.result {
+            Some(result) => *self.report.results.entry(result).or_default() += 1,
+            None => self.report.unrecorded += 1,
+        }
+    }
+
+    fn finish(mut self) -> LogReport {
+        self.end_game();
+        self.latencies
+            .sort_by_key(|latency| std::cmp::Reverse(latency.ms));
+        self.latencies.truncate(SLOWEST);
+        self.report.slowest = self.latencies;
+        self.report
+    }
+}
+
+// The message after `marker`, wherever the logger's prefix ends
+fn message<'a>(line: &'a str, marker: &str) -> Option<&'a str> {
+    line.find(marker).map(|at| line[at + marker.len()..].trim())
+}
+
+// First HH:MM:SS(.fff) before the message, as ms since midnight
+fn timestamp(line: &str) -> Option<i64> {
+    let prefix = &line[..line
+        .find("<<")
+        .or_else(|| line.find(">>"))
+        .unwrap_or(line.len())];
+    prefix
+        .split(|c: char| c.is_whitespace() || c == '[' || c == ']' || c == 'T')
+        .find_map(|token| {
+            let (time, fraction) = token.split_once('.').unwrap_or((token, "0"));
+            let parts: Vec<i64> = time
+                .split(':')
+                .map(|part| part.parse().ok())
+                .collect::<Option<_>>()?;
+            let [hours, minutes, seconds] = parts[..] else {
+                return None;
+            };
+            let millis: i64 = format!("{:0<3}", fraction).get(..3)?.parse().ok()?;
+            Some(((hours * 60 + minutes) * 60 + seconds) * 1000 + millis)
+        })
+}
+
+fn position(parsed: &[&str]) -> Option<Board> {
+    if parsed.get(1) != Some(&"startpos") {
+        return None;
+    }
+    let mut board = Board::default();
+    for token in parsed.iter().skip(3) {
+        let chess_move = ChessMove::from_str(token)
+            .ok()
+            .filter(|m| board.legal(*m))?;
+        board = board.make_move_new(chess_move);
+    }
+    Some(board)
+}
+
+// "60+1" from our clock and increment in seconds, or "movetime 100ms"
+fn time_control(parsed: &[&str], board: &Board) -> String {
+    let (clock, increment) = match board.side_to_move() {
+        chess::Color::White => ("wtime", "winc"),
+        chess::Color::Black => ("btime", "binc"),
+    };
+    let value = |key: &str| token_after(&parsed.join(" "), key);
+    match (value("movetime"), value(clock)) {
+        (Some(movetime), _) => format!("movetime {}ms", movetime),
+        (None, Some(clock)) => format!("{}+{}", clock / 1000, value(increment).unwrap_or(0) / 1000),
+        (None, None) => "untimed".to_string(),
+    }
+}
+
+fn token_after(text: &str, key: &str) -> Option<u64> {
+    let mut tokens = text.split_whitespace();
+    tokens.find(|token| *token == key)?;
+    tokens.next()?.parse().ok()
+}
+
+pub fn parse_log(text: &str) -> LogReport {
+    let mut parser = Parser::default();
+    for line in text.lines() {
+        parser.line(line);
+    }
+    parser.finish()
+}
+
+impl LogReport {
+    pub fn lines(&self) -> Vec<String> {
+        let results: Vec<String> = self
+            .results
+            .iter()
+            .map(|(result, count)| format!("{} x{}", result, count))
+            .collect();
+        let mut lines = vec![
+            format!("games {}", self.games),
+            format!(
+                "results {}{}unrecorded {}",
+                results.join(", "),
+                if results.is_empty() { "" } else { ", " },
+                self.unrecorded
+            ),
+            format!("time losses {}", self.time_losses),
+            format!("fallbacks {} watchdog {}", self.fallbacks, self.watchdog),
+        ];
+        for (time_control, stats) in &self.time_controls {
+            lines.push(format!(
+                "time control {}: searches {} avg depth {} avg nps {}",
+                time_control,
+                stats.searches,
+                TimeControlStats::average(stats.depth_total, stats.depth_samples),
+                TimeControlStats::average(stats.nps_total, stats.nps_samples)
+            ));
+        }
+        lines.push("slowest go to bestmove:".to_string());
+        for latency in &self.slowest {
+            lines.push(format!("  {}ms {}", latency.ms, latency.fen));
+        }
+        lines.push(format!("malformed lines skipped {}", self.malformed));
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixture_report() {
+        let report = parse_log(include_str!("../fixtures/logreport.log"));
+        assert_eq!(report.games, 3);
+        assert_eq!(report.results.get("1-0"), Some(&1));
+        assert_eq!(report.results.get("0-1"), Some(&1));
+        assert_eq!(report.unrecorded, 1);
+        assert_eq!(report.time_losses, 1);
+        assert_eq!(report.fallbacks, 1);
+        assert_eq!(report.malformed, 3);
+
+        assert_eq!(report.time_controls["60+1"].searches, 3);
+        assert_eq!(report.time_controls["movetime 500ms"].searches, 1);
+        assert_eq!(report.time_controls["60+1"].depth_samples, 1);
+        assert_eq!(report.slowest[0].ms, 2500);
+        assert_eq!(
+            report.slowest[0].fen,
+            "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 1"
+        );
+        assert_eq!(report.slowest.len(), 4);
+    }
+
+    #[test]
+    fn test_timestamps() {
+        assert_eq!(
+            timestamp("[12:00:01.250] (1) INFO Received << uci"),
+            Some(43_201_250)
+        );
+        assert_eq!(timestamp("12:00:01 INFO Received << uci"), Some(43_201_000));
+        assert_eq!(timestamp("INFO Received << 12:00:01"), None);
+    }
+}
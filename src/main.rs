@@ -1,255 +1,662 @@
-use ::text_io::read;
-use chess::{Board, ChessMove};
-use log::{info, LevelFilter};
-use shallow_red_engine::{
-    engine::enter_engine,
-    managers::cache_manager::{Cache, CacheInputGrouping},
-    utils::engine_interface::EngineSettings,
-};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use log::{error, info, warn, LevelFilter};
+use shallow_red_engine::managers::cache_manager::CacheInputGrouping;
 use std::{
+    env,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process,
     str::FromStr,
-    sync::{
-        mpsc::{self, Receiver, Sender},
-        Arc,
-    },
-    thread,
-    time::Duration,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{io::BufReader, sync::watch};
+
+use uci_shallow_red::{
+    backend::{SearchBackend, ShallowRed},
+    check::{check_script, Verdict},
+    console::{play_console, ConsoleSettings},
+    crash,
+    display::Style,
+    jsonlog,
+    logfile::{RotatingLog, DEFAULT_KEEP, DEFAULT_MAX_MB},
+    logreport::parse_log,
+    options::{env_name, identity_settings, log_settings, OptionKind, OPTIONS},
+    rng::SessionRng,
+    search::spawn_cache_manager,
+    selfcheck::selfcheck,
+    selfplay::{run_match, MatchSettings},
+    session::UciSession,
+    symtest::{load_positions, symtest},
+    training::TrainingExport,
+    uci,
+    version::{BUILD, VERSION},
 };
 
-use parking_lot::RwLock;
-use timecontrol::thinking_time;
-use tokio::{task, time::timeout};
+// Normal play is UCI on stdin, the modes do something else and exit. The flags go anywhere on the
+// line, after a mode too, so lichess-bot or a container can set the engine up without editing
+// anything. Most of them can come from the environment instead.
+#[derive(Parser)]
+#[command(name = "shallow-red", about = "UCI chess engine built on Shallow Red")]
+struct Cli {
+    #[command(subcommand)]
+    mode: Option<Mode>,
+    #[arg(long, global = true, help = "Print the version and build, then exit")]
+    version: bool,
+    #[arg(long, global = true, env = "SHALLOWRED_CONFIG", value_name = "FILE")]
+    config: Option<PathBuf>,
+    #[arg(
+        long,
+        global = true,
+        env = "SHALLOWRED_LOG",
+        value_name = "FILE|stderr|none"
+    )]
+    log: Option<String>,
+    #[arg(
+        long,
+        global = true,
+        env = "SHALLOWRED_LOG_LEVEL",
+        value_name = "LEVEL"
+    )]
+    log_level: Option<String>,
+    #[arg(long, global = true, env = "SHALLOWRED_LOG_FORMAT", value_enum)]
+    log_format: Option<LogFormat>,
+    #[arg(
+        long,
+        global = true,
+        env = "SHALLOWRED_LOG_MAX_SIZE",
+        value_name = "MB"
+    )]
+    log_max_size: Option<u64>,
+    #[arg(long, global = true, env = "SHALLOWRED_LOG_KEEP", value_name = "FILES")]
+    log_keep: Option<u32>,
+    #[arg(long, global = true, env = "SHALLOWRED_NAME", help = "id name to send")]
+    name: Option<String>,
+    #[arg(
+        long,
+        global = true,
+        env = "SHALLOWRED_AUTHOR",
+        help = "id author to send"
+    )]
+    author: Option<String>,
+    // Checked like a setoption, so these stay text until then
+    #[arg(long, global = true, value_name = "MB")]
+    hash: Option<String>,
+    #[arg(long, global = true)]
+    threads: Option<String>,
+    #[arg(long, global = true, value_name = "FILE")]
+    crash_report: Option<PathBuf>,
+    #[arg(long, global = true, value_name = "FILE")]
+    export_training: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum LogFormat {
+    Text,
+    Json,
+}
+
+// Each one works as a word or a flag, `shallow-red check script.txt` or `--check script.txt`
+#[derive(Subcommand)]
+enum Mode {
+    #[command(
+        long_flag = "symtest",
+        about = "Check every position scores like its mirror"
+    )]
+    Symtest { file: Option<PathBuf> },
+    #[cfg(feature = "bench")]
+    #[command(about = "Time a search of the bench positions")]
+    Bench(BenchArgs),
+    #[command(long_flag = "check", about = "Check a UCI script line by line")]
+    Check { file: PathBuf },
+    #[command(long_flag = "logreport", about = "Summarise a shallow-red.log")]
+    Logreport { file: PathBuf },
+    #[command(
+        long_flag = "selfcheck",
+        about = "Make sure the engine plays, without stdin"
+    )]
+    Selfcheck,
+    #[command(long_flag = "match", about = "Play the engine against itself")]
+    Match(MatchArgs),
+    #[command(long_flag = "play", about = "Play against the engine in the terminal")]
+    Play(PlayArgs),
+    #[cfg(feature = "http")]
+    #[command(long_flag = "serve-http", about = "Answer HTTP instead of reading UCI")]
+    ServeHttp(HttpArgs),
+}
+
+#[derive(Args)]
+struct MatchArgs {
+    #[arg(long)]
+    games: Option<u32>,
+    #[arg(long)]
+    random_plies: Option<u32>,
+    #[arg(long, value_name = "CP")]
+    max_opening_cp: Option<i32>,
+    #[arg(long, value_name = "MS")]
+    movetime: Option<u64>,
+    #[arg(long)]
+    seed: Option<u64>,
+    #[arg(long, value_name = "FILE")]
+    pgn: Option<PathBuf>,
+}
 
-mod timecontrol;
+#[derive(Args)]
+struct PlayArgs {
+    #[arg(value_enum, default_value_t = Side::White)]
+    side: Side,
+    #[arg(long, value_name = "MS", default_value_t = CONSOLE_MOVETIME_MS)]
+    movetime: u64,
+    #[arg(long)]
+    no_color: bool,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Side {
+    White,
+    Black,
+}
+
+#[cfg(feature = "bench")]
+#[derive(Args)]
+struct BenchArgs {
+    #[arg(long, value_name = "NAME")]
+    save_baseline: Option<String>,
+    #[arg(long, value_name = "NAME")]
+    compare: Option<String>,
+    #[arg(long, value_name = "PERCENT", default_value_t = uci_shallow_red::bench::DEFAULT_SPEED_TOLERANCE)]
+    speed_tolerance: f64,
+    #[arg(long, value_name = "DIR", default_value = uci_shallow_red::bench::DEFAULT_BASELINE_DIR)]
+    baseline_dir: PathBuf,
+}
+
+#[cfg(feature = "http")]
+#[derive(Args)]
+struct HttpArgs {
+    #[arg(value_name = "ADDR")]
+    addr: String,
+    #[arg(long, value_name = "MS")]
+    http_time_cap: Option<u64>,
+}
 
 #[tokio::main]
 async fn main() {
+    let cli = Cli::parse();
+    if cli.version {
+        println!("shallow-red {}\n{}", VERSION, BUILD);
+        return;
+    }
+    match &cli.mode {
+        Some(Mode::Symtest { file }) => process::exit(run_symtest(file.as_deref())),
+        #[cfg(feature = "bench")]
+        Some(Mode::Bench(args)) => process::exit(run_bench(args)),
+        Some(Mode::Check { file }) => process::exit(run_check(file)),
+        Some(Mode::Logreport { file }) => process::exit(run_logreport(file)),
+        _ => {}
+    }
+
+    let config = cli.config.clone().or_else(|| {
+        env::current_exe()
+            .ok()
+            .and_then(|exe| exe.parent().map(|dir| dir.join("shallowred.toml")))
+    });
+
+    // Setup logging, the command line or environment over the config file's [log] table
+    let file_log = config.as_deref().map(log_settings).unwrap_or_default();
+    let log_level = match &cli.log_level {
+        Some(level) => LevelFilter::from_str(level).unwrap_or_else(|_| {
+            cli_error("--log-level takes off, error, warn, info, debug or trace")
+        }),
+        None => file_log
+            .level
+            .as_deref()
+            .and_then(|level| LevelFilter::from_str(level).ok())
+            .unwrap_or(LevelFilter::Info),
+    };
+    let log_path = cli
+        .log
+        .clone()
+        .or(file_log.path.clone())
+        .unwrap_or(DEFAULT_LOG.to_string());
+    let max_mb = cli
+        .log_max_size
+        .unwrap_or(file_log.max_size.unwrap_or(DEFAULT_MAX_MB));
+    let rotation = Rotation {
+        max_bytes: max_mb.saturating_mul(1024 * 1024),
+        keep: cli
+            .log_keep
+            .unwrap_or(file_log.keep.unwrap_or(DEFAULT_KEEP)),
+    };
+    let json = match (cli.log_format, file_log.format.as_deref()) {
+        (Some(format), _) => matches!(format, LogFormat::Json),
+        (None, None | Some("text")) => false,
+        (None, Some("json")) => true,
+        (None, Some(_)) => cli_error("[log] format takes text or json"),
+    };
+    start_logging(&log_path, log_level, rotation, json);
+
+    // A fork's name and author, the command line over the environment over [identity]
+    let identity = config.as_deref().map(identity_settings).unwrap_or_default();
+    let engine_name = cli.name.clone().or(identity.name);
+    let engine_author = cli.author.clone().or(identity.author);
+    info!(
+        "{} starting",
+        engine_name.as_deref().unwrap_or("Shallow Red")
+    );
+    if let Some(level) = file_log
+        .level
+        .filter(|level| LevelFilter::from_str(level).is_err())
+    {
+        warn!(
+            "Config log level {} isn't one, logging at {}",
+            level, log_level
+        );
+    }
+    if let Some(path) = &cli.crash_report {
+        crash::set_report_path(path.clone());
+    }
+    crash::install(shutdown_after_panic);
+
+    let training = cli.export_training.as_deref().map(training_export);
+    match &cli.mode {
+        Some(Mode::Selfcheck) => process::exit(run_selfcheck(
+            Arc::new(ShallowRed),
+            Some(spawn_cache_manager()),
+            SELFCHECK_SEARCH,
+        )),
+        Some(Mode::Match(args)) => process::exit(run_self_play(args, training)),
+        Some(Mode::Play(args)) => process::exit(run_console(args)),
+        #[cfg(feature = "http")]
+        Some(Mode::ServeHttp(args)) => {
+            serve_http(args, UciSession::with_lazy_cache());
+            return;
+        }
+        _ => {}
+    }
+
     // Initialize values used throughout play
-    let mut board: Board = Board::default(); // Initializes to newboard
-    let mut moves_played: u8 = 0; // Moves played in game
-    let mut stop_channel: Option<Sender<bool>> = None;
+    // The cache thread is only started once something needs it
+    let mut session = UciSession::with_lazy_cache();
+    if let Some(name) = engine_name {
+        session.engine_name = name;
+    }
+    if let Some(author) = engine_author {
+        session.engine_author = author;
+    }
+    if let Some(config) = config {
+        session.load_config(config);
+    }
+    // The environment wins over the config file, the command line over both
+    apply_environment(&mut session);
+    for (flag, option, value) in [
+        ("--hash", "Hash", &cli.hash),
+        ("--threads", "Threads", &cli.threads),
+    ] {
+        if let Some(value) = value {
+            if let Err(e) = session.set_default_option(option, value) {
+                cli_error(&format!("{} {}", flag, e));
+            }
+        }
+    }
+    session.training = training;
+    // Before the GUI's first isready, so that usually doesn't have to wait for it
+    session.start_warmup();
+    let (shutdown, shutdown_rx) = watch::channel(false);
+    uci::quit_on_signals(shutdown);
+    let input = BufReader::new(tokio::io::stdin());
+    uci::run(session, input, uci::stdout(), shutdown_rx).await;
+    // Everything's shut down by now. A read of stdin may still be blocked, and returning would
+    // have the runtime wait on it
+    process::exit(0);
+}
 
-    // Set up the cache thread
-    let cache_arc = Arc::new(RwLock::new(Cache::default()));
-    let cache_arc_thread = cache_arc.clone();
+const DEFAULT_LOG: &str = "shallow-red.log";
 
-    let (cache_tx, cache_rx) = Cache::generate_channel();
+// "stderr", "none" for no log at all, or a file. A file that can't be opened (a read only
+// install, say) isn't worth refusing to play over, so that's a note on stderr and no log.
+fn start_logging(target: &str, level: LevelFilter, rotation: Rotation, json: bool) {
+    let sink: Box<dyn Write + Send> = match target {
+        "stderr" => Box::new(io::stderr()),
+        "none" => return,
+        path => match RotatingLog::open(Path::new(path), rotation.max_bytes, rotation.keep) {
+            Ok(log) => Box::new(log),
+            Err(e) => {
+                eprintln!("Can't log to {}, {}", path, e);
+                return;
+            }
+        },
+    };
+    match json {
+        true => jsonlog::install(sink, level),
+        false => simple_logging::log_to(sink, level),
+    }
+}
 
-    let _tx_spare = cache_tx.clone(); // Keep a spare sender around to prevent the cache server from quitting
+struct Rotation {
+    max_bytes: u64, // 0 lets the log grow
+    keep: u32,      // Rotated files
+}
 
-    let _cache_thread_hndl =
-        thread::spawn(move || Cache::cache_manager_server(cache_arc_thread, cache_rx));
+// SHALLOWRED_<OPTION> for any option, e.g. SHALLOWRED_HASH or SHALLOWRED_MOVE_OVERHEAD, set like
+// --hash is. SHALLOWRED_BOOK is the book file with OwnBook turned on, what a bot deployment wants.
+fn apply_environment(session: &mut UciSession) {
+    let mut from_env: Vec<(String, &str, String)> = Vec::new();
+    if let Ok(book) = env::var("SHALLOWRED_BOOK") {
+        from_env.push(("SHALLOWRED_BOOK".to_string(), "BookFile", book));
+        from_env.push(("SHALLOWRED_BOOK".to_string(), "OwnBook", "true".to_string()));
+    }
+    for spec in OPTIONS
+        .iter()
+        .filter(|spec| spec.kind != OptionKind::Button)
+    {
+        let var = env_name(spec.name);
+        if let Ok(value) = env::var(&var) {
+            from_env.push((var, spec.name, value));
+        }
+    }
+    for (var, option, value) in from_env {
+        if let Err(e) = session.set_default_option(option, &value) {
+            cli_error(&format!("{} {}", var, e));
+        }
+    }
+}
+
+// A bad flag stops the engine before it starts, a GUI would only see it go quiet
+fn cli_error(problem: &str) -> ! {
+    eprintln!("{}", problem);
+    process::exit(2);
+}
 
-    let cache = CacheInputGrouping {
-        cache_ref: cache_arc,
-        cache_tx,
+// --symtest [file], exit code 1 when any position scores differently from its mirror
+fn run_symtest(file: Option<&Path>) -> i32 {
+    let positions = match load_positions(file) {
+        Ok(positions) => positions,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 2;
+        }
     };
+    let report = symtest(&ShallowRed, &positions);
+    for line in report.lines() {
+        println!("{}", line);
+    }
+    if report.passed() {
+        0
+    } else {
+        1
+    }
+}
 
-    // Setup logging
-    let _ = simple_logging::log_to_file("shallow-red.log", LevelFilter::Info);
-    info!("Shallow Red starting");
-
-    loop {
-        let uci_input: String = read!("{}\n");
-        info!("Received << {}", uci_input);
-
-        let uci_output: Option<String> = parse_input(
-            uci_input,
-            &mut board,
-            &mut stop_channel,
-            Some(cache.clone()),
-            &mut moves_played,
-        )
-        .await;
-        info!("Sent >> {:#?}", uci_output);
-
-        // Only print out if we have a message
-        if let Some(out) = uci_output {
-            if out == *"quit".to_string() {
-                break;
-            } else {
-                println!("{}", out)
-            }
-        };
+// Whichever thread panicked, the process is in no state to keep playing
+// A GUI waiting on a bestmove gets one before we go, or it would only see us vanish and lose on time
+fn shutdown_after_panic() {
+    let output = uci::stdout();
+    if let Some(bestmove) = crash::fallback_bestmove() {
+        info!("Sent >> {}", bestmove);
+        output.send(&bestmove);
     }
+    output.flush(PANIC_FLUSH_WAIT);
+    log::logger().flush();
+    process::exit(101);
 }
 
-async fn parse_input(
-    uci_input: String,
-    board: &mut Board,
-    stop_channel: &mut Option<Sender<bool>>,
+const PANIC_FLUSH_WAIT: Duration = Duration::from_millis(500);
+const SELFCHECK_SEARCH: Duration = Duration::from_millis(100);
+
+// --selfcheck, exit code 1 with the failure on stderr when anything is off. Never reads stdin.
+fn run_selfcheck(
+    backend: Arc<dyn SearchBackend>,
     cache: Option<CacheInputGrouping>,
-    moves_played: &mut u8,
-) -> Option<String> {
-    // Split input by whitespace
-    let parsed_input: Vec<&str> = uci_input.split_whitespace().collect();
-
-    match parsed_input[0] {
-        "uci" => {
-            *moves_played = 0;
-            Some("info name shallow-red 0.1\nuciok".to_string())
+    search_time: Duration,
+) -> i32 {
+    let deadline = search_time * 20 + Duration::from_secs(1); // Plenty for a loaded container
+    match selfcheck(backend, cache, search_time, deadline) {
+        Ok(passed) => {
+            for line in passed {
+                info!("selfcheck {}", line);
+                println!("{}", line);
+            }
+            println!("selfcheck ok");
+            0
         }
-        "isready" => Some("readyok".to_string()),
-        "ucinewgame" => {
-            *board = Board::default();
-            *moves_played = 0;
-            None
-        } // Wipe board
-        "position" => {
-            load_position(parsed_input, board);
-            None
+        Err(e) => {
+            error!("selfcheck failed: {}", e);
+            eprintln!("selfcheck failed: {}", e);
+            1
         }
-        "go" => {
-            // Get our current time
-            let time_remaining = if parsed_input[1] == "movetime" {
-                Duration::from_millis(parsed_input[2].parse::<u64>().unwrap())
-            } else {
-                Duration::from_millis(match board.side_to_move() {
-                    chess::Color::White => parsed_input[2].parse::<u64>().unwrap(),
-                    chess::Color::Black => parsed_input[4].parse::<u64>().unwrap(),
-                })
-            };
-
-            // Create a channel for stopping the engine
-            let (tx, rx): (Sender<bool>, Receiver<bool>) = mpsc::channel(); // Stop channel
-            *stop_channel = Some(tx);
-
-            let mut settings = EngineSettings::default();
-            settings.stop_engine_rcv = Some(rx);
-            settings.verbose = false;
-            settings.cache_settings = cache;
-            settings.time_limit = thinking_time(*moves_played, time_remaining);
-
-            let moves_played_backup = *moves_played;
-
-            let board_run = board.clone(); // Copy the current board
-            task::spawn(async move {
-                // Spawn a long thread to monitor to run the engine, which returns the result when finished
-                // Give the search 2x its requested time before killing it
-                let engine_out = match timeout(settings.time_limit * 2, async {
-                    run_engine(board_run, settings)
-                })
-                .await
-                {
-                    Ok(result) => result,
-                    Err(_) => {
-                        // We ran outta time, try restarting the search with no cache
-                        let mut settings_backup = EngineSettings::default();
-                        settings_backup.time_limit =
-                            thinking_time(moves_played_backup, time_remaining);
-                        settings_backup.cache_settings = None; // Try without cache to correct issue
-                        info!("Hard reset search, it timedout");
-                        run_engine(board_run, settings_backup)
+    }
+}
+
+// --match [--games <n>] [--random-plies <n>] [--max-opening-cp <cp>] [--movetime <ms>]
+// [--seed <n>] [--pgn <file>], a line per game and the PGN on stdout unless --pgn is given
+fn run_self_play(args: &MatchArgs, training: Option<Arc<TrainingExport>>) -> i32 {
+    let defaults = MatchSettings::default();
+    let settings = MatchSettings {
+        games: args.games.unwrap_or(defaults.games),
+        random_plies: args.random_plies.unwrap_or(defaults.random_plies),
+        max_opening_cp: args.max_opening_cp.unwrap_or(defaults.max_opening_cp),
+        movetime: args
+            .movetime
+            .map_or(defaults.movetime, Duration::from_millis),
+        ..defaults
+    };
+    let mut rng = args
+        .seed
+        .map_or_else(SessionRng::from_entropy, SessionRng::seeded);
+    let pgn_file = args.pgn.as_deref();
+
+    let played = run_match(
+        &ShallowRed,
+        &settings,
+        &mut rng,
+        training.as_deref(),
+        |game| {
+            println!("game {}", game.metadata());
+            match pgn_file {
+                Some(path) => {
+                    if let Err(e) = game.record.append_pgn(path) {
+                        error!("Couldn't write PGN to {}: {}", path.display(), e);
                     }
-                };
-                println!("{}", engine_out);
-            });
-            *moves_played += 1;
-            None
+                }
+                None => println!("{}", game.record.to_pgn()),
+            }
+        },
+    );
+    match played {
+        Ok(_) => 0,
+        Err(e) => {
+            eprintln!("match: {}", e);
+            1
         }
-        "debuginternal" => {
-            let debug_board: String = read!("{}\n");
-            *board = Board::from_str(&debug_board).unwrap();
-            None
+    }
+}
+
+// --play [white|black] [--movetime <ms>] [--no-color], a game in the terminal instead of UCI
+fn run_console(args: &PlayArgs) -> i32 {
+    let settings = ConsoleSettings {
+        human: match args.side {
+            Side::White => chess::Color::White,
+            Side::Black => chess::Color::Black,
+        },
+        movetime: Duration::from_millis(args.movetime),
+        style: Style::detect(args.no_color),
+    };
+    let stdin = io::stdin();
+    match play_console(&ShallowRed, &settings, stdin.lock(), &mut io::stdout()) {
+        Ok(moves) => {
+            info!("Console game over after {} plies", moves.len());
+            0
         }
-        "stop" => {
-            match stop_channel {
-                Some(stop_chan) => {
-                    let _ = stop_chan.send(true);
-                } // Send a stop to engine
-                None => {} // Don't care
-            };
-            None
+        Err(e) => {
+            eprintln!("play: {}", e);
+            1
         }
-        "quit" => Some("quit".to_string()),
-        _ => None, // todo
     }
 }
 
-fn load_position(input: Vec<&str>, board: &mut Board) {
-    for str_move in &input[1..] {
-        match *str_move {
-            "startpos" => *board = Board::default(),
-            "moves" => {}
-            _ => {
-                let chessmove = ChessMove::from_str(str_move).expect("Move should be legal");
-                *board = board.make_move_new(chessmove);
-            }
+const CONSOLE_MOVETIME_MS: u64 = 2000;
+
+// --export-training <file>, alongside normal play or --match
+fn training_export(path: &Path) -> Arc<TrainingExport> {
+    let run = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |t| t.as_secs());
+    Arc::new(TrainingExport::new(path.into(), run))
+}
+
+// --check <file>, a verdict for every line and exit code 1 when any of them isn't ok
+fn run_check(file: &Path) -> i32 {
+    let script = match std::fs::read_to_string(file) {
+        Ok(script) => script,
+        Err(e) => {
+            eprintln!("Couldn't read {}: {}", file.display(), e);
+            return 2;
         }
+    };
+    let verdicts = check_script(&script);
+    for (line, verdict) in &verdicts {
+        println!("line {}: {}", line, verdict);
+    }
+    if verdicts.iter().all(|(_, verdict)| *verdict == Verdict::Ok) {
+        0
+    } else {
+        1
     }
 }
 
-fn run_engine(board: Board, settings: EngineSettings) -> String {
-    info!(
-        "Running search on board {}, with settings {:#?}",
-        board.to_string(),
-        settings
-    );
-    let (best_move, search_results) = enter_engine(board, settings);
-    if let Some(results) = search_results {
-        info!("Search finished with results: {:#?}", results)
+// --logreport <file>, summary of a shallow-red.log
+fn run_logreport(file: &Path) -> i32 {
+    // The log can have torn writes in it, so don't insist on utf-8
+    let log = match std::fs::read(file) {
+        Ok(log) => String::from_utf8_lossy(&log).into_owned(),
+        Err(e) => {
+            eprintln!("Couldn't read {}: {}", file.display(), e);
+            return 2;
+        }
+    };
+    for line in parse_log(&log).lines() {
+        println!("{}", line);
     }
-    "bestmove ".to_owned() + &best_move.to_string()
+    0
 }
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use chess::Square;
-
-    #[tokio::test]
-    async fn test_uciok() {
-        let input = "uci";
-        let mut board = Board::default();
-        let output = parse_input(input.to_string(), &mut board, &mut None, None, &mut 0)
-            .await
-            .unwrap();
-        assert_eq!(output, "info name shallow-red 0.1\nuciok")
+// bench [--save-baseline <name>] [--compare <name>] [--speed-tolerance <percent>] [--baseline-dir <dir>]
+// Exit code 1 when the comparison fails, 2 when there's nothing to compare or the bench can't run
+#[cfg(feature = "bench")]
+fn run_bench(args: &BenchArgs) -> i32 {
+    use uci_shallow_red::bench;
+
+    let dir = &args.baseline_dir;
+
+    let positions = load_positions(None).expect("Built in positions should load");
+    let result = bench::run_bench(&ShallowRed, &positions);
+    for line in result.lines() {
+        println!("{}", line);
     }
 
-    #[tokio::test]
-    async fn test_readyok() {
-        let input = "isready";
-        let mut board = Board::default();
-        let output = parse_input(input.to_string(), &mut board, &mut None, None, &mut 0)
-            .await
-            .unwrap();
-        assert_eq!(output, "readyok")
+    if let Some(name) = &args.save_baseline {
+        match bench::save_baseline(dir, name, &result) {
+            Ok(path) => println!("saved baseline {}", path.display()),
+            Err(e) => {
+                eprintln!("bench: {}", e);
+                return 2;
+            }
+        }
+    }
+    let Some(name) = &args.compare else {
+        return 0;
+    };
+    let comparison = match bench::load_baseline(dir, name)
+        .and_then(|baseline| bench::compare(&baseline, &result, args.speed_tolerance))
+    {
+        Ok(comparison) => comparison,
+        Err(e) => {
+            eprintln!("bench: {}", e);
+            return 2;
+        }
+    };
+    for line in comparison.lines.iter().chain(&comparison.failures) {
+        println!("{}", line);
+    }
+    if comparison.passed() {
+        0
+    } else {
+        1
     }
+}
 
-    #[tokio::test]
-    async fn test_newgame() {
-        let input = "ucinewgame";
-        let mut board = Board::default();
-        let output = parse_input(input.to_string(), &mut board, &mut None, None, &mut 0).await;
-        assert_eq!(output, None)
+// --serve-http <addr> [--http-time-cap <ms>], answers HTTP instead of reading UCI from stdin
+#[cfg(feature = "http")]
+fn serve_http(args: &HttpArgs, session: UciSession) {
+    let mut server = uci_shallow_red::http::HttpServer::bind(&args.addr, session)
+        .unwrap_or_else(|e| cli_error(&format!("Couldn't bind {}: {}", args.addr, e)));
+    if let Some(cap_ms) = args.http_time_cap {
+        server.time_cap = Duration::from_millis(cap_ms);
     }
+    server.serve();
+}
 
-    #[tokio::test]
-    async fn test_position() {
-        let input = "position startpos moves e2e4";
-        let mut board = Board::default();
-        parse_input(input.to_string(), &mut board, &mut None, None, &mut 0).await;
-        let board_e2e4 =
-            Board::default().make_move_new(ChessMove::new(Square::E2, Square::E4, None));
-        assert_eq!(board, board_e2e4);
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chess::{Board, ChessMove, Square};
+
+    // Plays a move that's never legal anywhere
+    struct Sabotaged;
+
+    impl SearchBackend for Sabotaged {
+        fn search(
+            &self,
+            _board: Board,
+            _settings: shallow_red_engine::utils::engine_interface::EngineSettings,
+        ) -> uci_shallow_red::backend::SearchOutcome {
+            uci_shallow_red::backend::SearchOutcome {
+                best_move: ChessMove::new(Square::A1, Square::H8, None),
+                score: None,
+                nodes: None,
+            }
+        }
     }
 
-    #[tokio::test]
-    async fn test_go() {
-        let input_pos = "position startpos moves e2e4";
-        let mut board = Board::default();
-        parse_input(input_pos.to_string(), &mut board, &mut None, None, &mut 0).await;
+    #[test]
+    fn test_flags_go_anywhere() {
+        use clap::CommandFactory;
 
-        let input = "go wtime 600000 btime 600000";
-        parse_input(input.to_string(), &mut board, &mut None, None, &mut 0).await;
+        Cli::command().debug_assert();
+        let cli = Cli::try_parse_from(["shallow-red", "--match", "--games", "3", "--hash", "64"])
+            .unwrap();
+        assert_eq!(cli.hash.as_deref(), Some("64"));
+        assert!(matches!(
+            cli.mode,
+            Some(Mode::Match(MatchArgs { games: Some(3), .. }))
+        ));
+        let cli = Cli::try_parse_from(["shallow-red", "--threads", "2", "--check", "script.txt"])
+            .unwrap();
+        assert_eq!(cli.threads.as_deref(), Some("2"));
+        assert!(matches!(&cli.mode, Some(Mode::Check { file }) if file == Path::new("script.txt")));
+        let cli = Cli::try_parse_from(["shallow-red", "--play", "black", "--no-color"]).unwrap();
+        assert!(matches!(
+            cli.mode,
+            Some(Mode::Play(PlayArgs {
+                side: Side::Black,
+                no_color: true,
+                ..
+            }))
+        ));
+        assert!(
+            Cli::try_parse_from(["shallow-red", "--threads", "2", "--version"])
+                .unwrap()
+                .version
+        );
+        assert!(Cli::try_parse_from(["shallow-red", "--check"]).is_err());
+        assert!(Cli::try_parse_from(["shallow-red", "--match", "--games", "many"]).is_err());
     }
 
-    #[tokio::test]
-    async fn test_blunder() {
-        let mut board =
-            Board::from_str("r3r1k1/ppp3pp/4p3/1P6/4p3/b3P3/qBQ2PPP/3R1RK1 w - - 0 1").unwrap();
-        let input = "go wtime 600000 btime 600000";
-        parse_input(input.to_string(), &mut board, &mut None, None, &mut 0).await;
+    #[test]
+    fn test_selfcheck_exit_codes() {
+        let tiny = Duration::from_millis(10);
+        let real = run_selfcheck(Arc::new(ShallowRed), Some(spawn_cache_manager()), tiny);
+        assert_eq!(real, 0);
+        assert_eq!(run_selfcheck(Arc::new(Sabotaged), None, tiny), 1);
     }
 }
@@ -0,0 +1,118 @@
+// go mate N. The engine can't be asked for a forced mate, so this looks for one itself: every
+// reply of theirs has to run into mate within our N moves. Exhaustive, so only quick for small N,
+// and it gives up as soon as `stop` says so.
+use chess::{Board, BoardStatus, ChessMove, MoveGen};
+
+// Our first move and how many moves the mate takes, shortest mate first. None when there's no
+// mate within `max_moves` or when stopped before finding one. `currmove` hears about each root
+// move as it's tried, with its number counting from 1.
+pub fn find_mate(
+    board: &Board,
+    max_moves: u8,
+    allowed: Option<&[ChessMove]>,
+    currmove: &mut dyn FnMut(ChessMove, usize),
+    stop: &mut dyn FnMut() -> bool,
+) -> Option<(ChessMove, u8)> {
+    let candidates: Vec<ChessMove> = MoveGen::new_legal(board)
+        .filter(|m| allowed.is_none_or(|allowed| allowed.contains(m)))
+        .collect();
+    for moves in 1..=max_moves {
+        for (index, chess_move) in candidates.iter().enumerate() {
+            currmove(*chess_move, index + 1);
+            let after = board.make_move_new(*chess_move);
+            if moves == 1 && after.checkers().popcnt() == 0 {
+                continue; // A mate has to be check
+            }
+            if forced(&after, moves - 1, stop)? {
+                return Some((*chess_move, moves));
+            }
+        }
+    }
+    None
+}
+
+// Whether the side to move (them) gets mated with `remaining` more moves of ours. None when stopped.
+fn forced(board: &Board, remaining: u8, stop: &mut dyn FnMut() -> bool) -> Option<bool> {
+    if stop() {
+        return None;
+    }
+    match board.status() {
+        BoardStatus::Checkmate => return Some(true),
+        BoardStatus::Stalemate => return Some(false),
+        BoardStatus::Ongoing if remaining == 0 => return Some(false),
+        BoardStatus::Ongoing => {}
+    }
+    for reply in MoveGen::new_legal(board) {
+        let after_reply = board.make_move_new(reply);
+        let mut refuted = true;
+        for ours in MoveGen::new_legal(&after_reply) {
+            let after = after_reply.make_move_new(ours);
+            if remaining == 1 && after.checkers().popcnt() == 0 {
+                continue;
+            }
+            if forced(&after, remaining - 1, stop)? {
+                refuted = false;
+                break;
+            }
+        }
+        if refuted {
+            return Some(false);
+        }
+    }
+    Some(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    const BACK_RANK: &str = "6k1/5ppp/8/8/8/8/5PPP/3R2K1 w - - 0 1";
+    const LADDER: &str = "7k/8/8/8/8/2K5/8/RR6 w - - 0 1"; // 1. Ra7 Kg8 2. Rb8#
+
+    fn solve(fen: &str, moves: u8) -> Option<(ChessMove, u8)> {
+        find_mate(
+            &Board::from_str(fen).unwrap(),
+            moves,
+            None,
+            &mut |_, _| {},
+            &mut || false,
+        )
+    }
+
+    #[test]
+    fn test_finds_shortest_mate() {
+        assert_eq!(
+            solve(BACK_RANK, 3),
+            Some((ChessMove::from_str("d1d8").unwrap(), 1))
+        );
+        assert_eq!(solve(LADDER, 1), None);
+        let (chess_move, moves) = solve(LADDER, 2).unwrap();
+        assert_eq!(moves, 2);
+        // Whatever the first move, every reply has to run into mate next move
+        let after = Board::from_str(LADDER).unwrap().make_move_new(chess_move);
+        assert_eq!(forced(&after, 1, &mut || false), Some(true));
+    }
+
+    #[test]
+    fn test_respects_root_and_stop() {
+        let rook_lift = ChessMove::from_str("d1d2").unwrap();
+        let board = Board::from_str(BACK_RANK).unwrap();
+        let mut tried = Vec::new();
+        assert_eq!(
+            find_mate(
+                &board,
+                1,
+                Some(&[rook_lift]),
+                &mut |chess_move, number| tried.push((chess_move, number)),
+                &mut || false
+            ),
+            None
+        );
+        assert_eq!(tried, vec![(rook_lift, 1)]);
+        assert_eq!(
+            find_mate(&board, 3, None, &mut |_, _| {}, &mut || true),
+            None
+        );
+    }
+}
@@ -0,0 +1,450 @@
+// Every UCI option we advertise. The defaults here can be overridden by values in the [options]
+// table of the config file, written by hand or by the Persist Options option.
+use std::{fs, io, path::Path};
+
+use crate::{
+    handicap::{DEFAULT_ELO, MAX_ELO, MIN_ELO},
+    timecontrol::{
+        DEFAULT_MAX_MOVE_SHARE, DEFAULT_MIN_THINKING_MS, DEFAULT_SAFETY_BUFFER_MS,
+        DEFAULT_SLOW_MOVER, TIME_MANAGERS,
+    },
+};
+use toml::{Table, Value};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OptionKind {
+    Check(bool),
+    String(&'static str),
+    Combo(&'static str, &'static [&'static str]),
+    Spin(i64, i64, i64), // Default, min, max
+    Button,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OptionSpec {
+    pub name: &'static str,
+    pub kind: OptionKind,
+    pub persist: bool, // False for per-session options that shouldn't outlive the GUI session
+}
+
+pub const OPTIONS: [OptionSpec; 28] = [
+    OptionSpec {
+        name: "Startup Warmup",
+        kind: OptionKind::Check(false),
+        persist: true,
+    },
+    OptionSpec {
+        name: "Bullet Fast Path",
+        kind: OptionKind::Check(false),
+        persist: true,
+    },
+    OptionSpec {
+        name: "Repertoire File",
+        kind: OptionKind::String(""),
+        persist: true,
+    },
+    OptionSpec {
+        name: "Capture File",
+        kind: OptionKind::String(""),
+        persist: true,
+    },
+    OptionSpec {
+        name: "OwnBook",
+        kind: OptionKind::Check(false),
+        persist: true,
+    },
+    OptionSpec {
+        name: "BookFile",
+        kind: OptionKind::String(""),
+        persist: true,
+    },
+    OptionSpec {
+        name: "Book Selection",
+        kind: OptionKind::Combo(
+            "weighted",
+            &["best", "weighted", "uniform", "min-weight 10"],
+        ),
+        persist: true,
+    },
+    OptionSpec {
+        name: "Persist Options",
+        kind: OptionKind::Check(false),
+        persist: true,
+    },
+    OptionSpec {
+        name: "UCI_Opponent",
+        kind: OptionKind::String(""),
+        persist: false,
+    },
+    OptionSpec {
+        name: "PGN File",
+        kind: OptionKind::String(""),
+        persist: true,
+    },
+    OptionSpec {
+        name: "Career File",
+        kind: OptionKind::String(""),
+        persist: true,
+    },
+    OptionSpec {
+        name: "Time Manager",
+        kind: OptionKind::Combo("classic", &TIME_MANAGERS),
+        persist: true,
+    },
+    OptionSpec {
+        name: "Avoid Moves",
+        kind: OptionKind::String(""),
+        persist: false,
+    },
+    OptionSpec {
+        name: "Full Reset",
+        kind: OptionKind::Button,
+        persist: false,
+    },
+    OptionSpec {
+        name: "Hash",
+        kind: OptionKind::Spin(DEFAULT_HASH_MB, 1, 65536), // MB, GUIs set it whether it sizes anything or not
+        persist: true,
+    },
+    OptionSpec {
+        name: "Clear Hash",
+        kind: OptionKind::Button,
+        persist: false,
+    },
+    OptionSpec {
+        name: "Threads",
+        kind: OptionKind::Spin(1, 1, 64),
+        persist: true,
+    },
+    OptionSpec {
+        name: "Ponder",
+        kind: OptionKind::Check(false),
+        persist: true,
+    },
+    OptionSpec {
+        name: "UCI_Chess960",
+        kind: OptionKind::Check(false),
+        persist: false,
+    },
+    OptionSpec {
+        name: "UCI_ShowWDL",
+        kind: OptionKind::Check(false),
+        persist: true,
+    },
+    OptionSpec {
+        name: "Move Overhead",
+        kind: OptionKind::Spin(0, 0, 5000), // ms
+        persist: true,
+    },
+    OptionSpec {
+        name: "Safety Buffer",
+        kind: OptionKind::Spin(DEFAULT_SAFETY_BUFFER_MS, 0, 5000), // ms
+        persist: true,
+    },
+    OptionSpec {
+        name: "Max Move Share",
+        kind: OptionKind::Spin(DEFAULT_MAX_MOVE_SHARE, 1, 100), // Percent of our clock
+        persist: true,
+    },
+    OptionSpec {
+        name: "SlowMover",
+        kind: OptionKind::Spin(DEFAULT_SLOW_MOVER, 10, 1000), // Percent
+        persist: true,
+    },
+    OptionSpec {
+        name: "Minimum Thinking Time",
+        kind: OptionKind::Spin(DEFAULT_MIN_THINKING_MS, 0, 5000), // ms
+        persist: true,
+    },
+    OptionSpec {
+        name: "SyzygyPath",
+        kind: OptionKind::String(""),
+        persist: true,
+    },
+    OptionSpec {
+        name: "UCI_LimitStrength",
+        kind: OptionKind::Check(false),
+        persist: true,
+    },
+    OptionSpec {
+        name: "UCI_Elo",
+        kind: OptionKind::Spin(DEFAULT_ELO, MIN_ELO, MAX_ELO),
+        persist: true,
+    },
+];
+
+pub const DEFAULT_HASH_MB: i64 = 16;
+
+// Option names aren't case sensitive in UCI
+pub fn find(name: &str) -> Option<&'static OptionSpec> {
+    OPTIONS
+        .iter()
+        .find(|spec| spec.name.eq_ignore_ascii_case(name))
+}
+
+impl OptionSpec {
+    pub fn default_value(&self) -> String {
+        match self.kind {
+            OptionKind::Check(default) => default.to_string(),
+            OptionKind::String(default) | OptionKind::Combo(default, _) => default.to_string(),
+            OptionKind::Spin(default, _, _) => default.to_string(),
+            OptionKind::Button => String::new(),
+        }
+    }
+
+    // The value as the option's handler expects it, or why it can't take it. Combos are left to
+    // their handler since some vars take an argument (min-weight 10).
+    pub fn check_value(&self, value: &str) -> Result<String, String> {
+        match self.kind {
+            OptionKind::Check(_) => match value.to_ascii_lowercase().as_str() {
+                checked @ ("true" | "false") => Ok(checked.to_string()),
+                _ => Err(format!("{} is true or false", self.name)),
+            },
+            OptionKind::Spin(_, min, max) => match value.parse::<i64>() {
+                Ok(spin) if (min..=max).contains(&spin) => Ok(spin.to_string()),
+                _ => Err(format!("{} is a number from {} to {}", self.name, min, max)),
+            },
+            OptionKind::String(_) | OptionKind::Combo(_, _) | OptionKind::Button => {
+                Ok(value.to_string())
+            }
+        }
+    }
+
+    // The `option name ...` line of the uci reply, advertising `default`
+    pub fn uci_line(&self, default: &str) -> String {
+        match self.kind {
+            OptionKind::Check(_) => {
+                format!("option name {} type check default {}", self.name, default)
+            }
+            OptionKind::String(_) => format!(
+                "option name {} type string default {}",
+                self.name,
+                if default.is_empty() {
+                    "<empty>"
+                } else {
+                    default
+                }
+            ),
+            OptionKind::Combo(_, vars) => format!(
+                "option name {} type combo default {} var {}",
+                self.name,
+                default,
+                vars.join(" var ")
+            ),
+            OptionKind::Spin(_, min, max) => format!(
+                "option name {} type spin default {} min {} max {}",
+                self.name, default, min, max
+            ),
+            OptionKind::Button => format!("option name {} type button", self.name),
+        }
+    }
+
+    fn to_toml(self, value: &str) -> Value {
+        match self.kind {
+            OptionKind::Check(_) => Value::Boolean(value == "true"),
+            OptionKind::Spin(_, _, _) => value
+                .parse()
+                .map_or(Value::String(value.to_string()), Value::Integer),
+            _ => Value::String(value.to_string()),
+        }
+    }
+}
+
+// Persisted option values as the strings setoption would have sent, unknown names are skipped
+pub fn load_persisted(path: &Path) -> Vec<(&'static OptionSpec, String)> {
+    let Some(table) = read_config(path) else {
+        return Vec::new();
+    };
+    let Some(options) = table.get("options").and_then(Value::as_table) else {
+        return Vec::new();
+    };
+    OPTIONS
+        .iter()
+        .filter_map(|spec| {
+            let value = match options.get(spec.name)? {
+                Value::Boolean(b) => b.to_string(),
+                Value::String(s) => s.clone(),
+                Value::Integer(i) => i.to_string(),
+                _ => return None,
+            };
+            Some((spec, value))
+        })
+        .collect()
+}
+
+// Writes one option into the config file, keeping everything else in it. Goes through a temp
+// file and a rename so a crash can't leave a half written config behind.
+pub fn persist(path: &Path, spec: &OptionSpec, value: &str) -> io::Result<()> {
+    let mut table = read_config(path).unwrap_or_default();
+    let options = table.entry("options").or_insert(Value::Table(Table::new()));
+    match options.as_table_mut() {
+        Some(options) => {
+            options.insert(spec.name.to_string(), spec.to_toml(value));
+        }
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "options in the config file isn't a table",
+            ))
+        }
+    }
+
+    let text =
+        toml::to_string(&table).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let temp = path.with_extension("toml.tmp");
+    fs::write(&temp, text)?;
+    fs::rename(&temp, path)
+}
+
+// The environment variable standing in for an option, spaces as underscores
+pub fn env_name(option: &str) -> String {
+    format!("SHALLOWRED_{}", option.to_uppercase().replace(' ', "_"))
+}
+
+// The [log] table, read before there's a session to load anything else into
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct LogSettings {
+    pub path: Option<String>,
+    pub level: Option<String>,
+    pub max_size: Option<u64>, // In MB
+    pub keep: Option<u32>,
+    pub format: Option<String>,
+}
+
+pub fn log_settings(path: &Path) -> LogSettings {
+    let Some(log) = config_table(path, "log") else {
+        return LogSettings::default();
+    };
+    let setting = |key: &str| log.get(key).and_then(Value::as_str).map(str::to_string);
+    let number = |key: &str| log.get(key).and_then(Value::as_integer);
+    LogSettings {
+        path: setting("path"),
+        level: setting("level"),
+        max_size: number("max_size").and_then(|mb| mb.try_into().ok()),
+        keep: number("keep").and_then(|keep| keep.try_into().ok()),
+        format: setting("format"),
+    }
+}
+
+// The [identity] table, name and author for the id lines of a fork
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Identity {
+    pub name: Option<String>,
+    pub author: Option<String>,
+}
+
+pub fn identity_settings(path: &Path) -> Identity {
+    let Some(identity) = config_table(path, "identity") else {
+        return Identity::default();
+    };
+    let setting = |key: &str| {
+        identity
+            .get(key)
+            .and_then(Value::as_str)
+            .map(str::to_string)
+    };
+    Identity {
+        name: setting("name"),
+        author: setting("author"),
+    }
+}
+
+fn config_table(path: &Path, name: &str) -> Option<Table> {
+    read_config(path)?.get(name)?.as_table().cloned()
+}
+
+pub fn read_config(path: &Path) -> Option<Table> {
+    fs::read_to_string(path).ok()?.parse::<Table>().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uci_lines() {
+        assert_eq!(
+            find("OwnBook").unwrap().uci_line("false"),
+            "option name OwnBook type check default false"
+        );
+        assert_eq!(
+            find("BookFile").unwrap().uci_line(""),
+            "option name BookFile type string default <empty>"
+        );
+        assert_eq!(
+            find("Book Selection").unwrap().uci_line("best"),
+            "option name Book Selection type combo default best var best var weighted var uniform var min-weight 10"
+        );
+        assert_eq!(
+            find("Full Reset").unwrap().uci_line(""),
+            "option name Full Reset type button"
+        );
+        let spin = OptionSpec {
+            name: "Spinner",
+            kind: OptionKind::Spin(16, 1, 1024),
+            persist: true,
+        };
+        assert_eq!(
+            spin.uci_line("32"),
+            "option name Spinner type spin default 32 min 1 max 1024"
+        );
+    }
+
+    #[test]
+    fn test_check_value() {
+        assert_eq!(find("ownbook").unwrap().name, "OwnBook");
+        let own_book = find("OwnBook").unwrap();
+        assert_eq!(own_book.check_value("TRUE"), Ok("true".to_string()));
+        assert!(own_book.check_value("yes").is_err());
+        let spin = OptionSpec {
+            name: "Spinner",
+            kind: OptionKind::Spin(16, 1, 1024),
+            persist: true,
+        };
+        assert_eq!(spin.check_value("1024"), Ok("1024".to_string()));
+        assert!(spin.check_value("0").is_err());
+        assert!(spin.check_value("lots").is_err());
+    }
+
+    #[test]
+    fn test_env_names() {
+        assert_eq!(env_name("Hash"), "SHALLOWRED_HASH");
+        assert_eq!(env_name("Move Overhead"), "SHALLOWRED_MOVE_OVERHEAD");
+        assert_eq!(env_name("UCI_Elo"), "SHALLOWRED_UCI_ELO");
+    }
+
+    #[test]
+    fn test_persist_keeps_other_settings() {
+        let path = std::env::temp_dir().join("shallow-red-options-test.toml");
+        fs::write(
+            &path,
+            "[log]\nlevel = \"debug\"\n\n[identity]\nname = \"Shallow Red Dev\"\n",
+        )
+        .unwrap();
+
+        persist(&path, find("OwnBook").unwrap(), "true").unwrap();
+        persist(&path, find("BookFile").unwrap(), "/books/main.bin").unwrap();
+
+        let persisted = load_persisted(&path);
+        assert_eq!(persisted.len(), 2);
+        assert!(persisted.contains(&(find("OwnBook").unwrap(), "true".to_string())));
+        assert!(fs::read_to_string(&path)
+            .unwrap()
+            .contains("level = \"debug\""));
+        assert_eq!(
+            log_settings(&path),
+            LogSettings {
+                level: Some("debug".to_string()),
+                ..LogSettings::default()
+            }
+        );
+        assert_eq!(
+            identity_settings(&path),
+            Identity {
+                name: Some("Shallow Red Dev".to_string()),
+                author: None
+            }
+        );
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,239 @@
+// Polyglot opening books. Files set with BookFile and the built-in default-book bytes both go
+// through PolyglotBook::from_bytes, lookups return every legal book move with its weight and
+// the session's SelectionPolicy picks one.
+use chess::{Board, ChessMove, Piece, Square, ALL_SQUARES};
+use shakmaty::{
+    fen::Fen,
+    zobrist::{Zobrist64, ZobristHash},
+    CastlingMode, Chess, EnPassantMode,
+};
+use std::{fs, path::Path};
+
+use crate::book::BookEntry;
+
+const ENTRY_SIZE: usize = 16;
+
+#[cfg(feature = "default-book")]
+static DEFAULT_BOOK_BYTES: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/default-book.bin"));
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct RawEntry {
+    key: u64,
+    chess_move: u16,
+    weight: u16,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct PolyglotBook {
+    entries: Vec<RawEntry>, // Sorted by key, as in the file
+}
+
+impl PolyglotBook {
+    pub fn from_bytes(bytes: &[u8]) -> Result<PolyglotBook, String> {
+        if !bytes.len().is_multiple_of(ENTRY_SIZE) {
+            return Err(format!(
+                "book is {} bytes, not a whole number of entries",
+                bytes.len()
+            ));
+        }
+        let mut entries: Vec<RawEntry> = bytes
+            .chunks_exact(ENTRY_SIZE)
+            .map(|entry| RawEntry {
+                key: u64::from_be_bytes(entry[0..8].try_into().unwrap()),
+                chess_move: u16::from_be_bytes(entry[8..10].try_into().unwrap()),
+                weight: u16::from_be_bytes(entry[10..12].try_into().unwrap()),
+            })
+            .collect();
+        entries.sort_by_key(|entry| entry.key); // Should already be sorted, lookups rely on it
+        Ok(PolyglotBook { entries })
+    }
+
+    pub fn load(path: &Path) -> Result<PolyglotBook, String> {
+        let bytes =
+            fs::read(path).map_err(|e| format!("can't read book {}: {}", path.display(), e))?;
+        PolyglotBook::from_bytes(&bytes)
+    }
+
+    // Adds (or reweights) a move, for building books. A position without a key is left out.
+    pub fn insert(&mut self, board: &Board, chess_move: ChessMove, weight: u16) {
+        let Some(key) = polyglot_key(board) else {
+            return;
+        };
+        let entry = RawEntry {
+            key,
+            chess_move: encode_move(board, chess_move),
+            weight,
+        };
+        match self
+            .entries
+            .iter_mut()
+            .find(|e| e.key == entry.key && e.chess_move == entry.chess_move)
+        {
+            Some(existing) => existing.weight = weight,
+            None => {
+                let at = self.entries.partition_point(|e| e.key <= entry.key);
+                self.entries.insert(at, entry);
+            }
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.entries.len() * ENTRY_SIZE);
+        for entry in &self.entries {
+            bytes.extend_from_slice(&entry.key.to_be_bytes());
+            bytes.extend_from_slice(&entry.chess_move.to_be_bytes());
+            bytes.extend_from_slice(&entry.weight.to_be_bytes());
+            bytes.extend_from_slice(&0u32.to_be_bytes()); // Learn
+        }
+        bytes
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // Legal book moves for the position with their weights, empty when out of book
+    pub fn candidates(&self, board: &Board) -> Vec<BookEntry> {
+        let Some(key) = polyglot_key(board) else {
+            return Vec::new();
+        };
+        let start = self.entries.partition_point(|entry| entry.key < key);
+        self.entries[start..]
+            .iter()
+            .take_while(|entry| entry.key == key)
+            .map(|entry| BookEntry {
+                chess_move: decode_move(board, entry.chess_move),
+                weight: entry.weight,
+            })
+            .filter(|entry| board.legal(entry.chess_move))
+            .collect()
+    }
+}
+
+// The book compiled in with the default-book feature
+pub fn default_book() -> Option<&'static PolyglotBook> {
+    #[cfg(feature = "default-book")]
+    {
+        static DEFAULT_BOOK: std::sync::OnceLock<PolyglotBook> = std::sync::OnceLock::new();
+        Some(DEFAULT_BOOK.get_or_init(|| {
+            PolyglotBook::from_bytes(DEFAULT_BOOK_BYTES).expect("Built-in book should be valid")
+        }))
+    }
+    #[cfg(not(feature = "default-book"))]
+    None
+}
+
+// Polyglot's own Zobrist keys, shakmaty uses the same random table. None for a position
+// shakmaty won't take, which no book can have.
+pub fn polyglot_key(board: &Board) -> Option<u64> {
+    let fen: Fen = board.to_string().parse().expect("Board FEN should parse");
+    let position: Chess = fen.into_position(CastlingMode::Standard).ok()?;
+    Some(position.zobrist_hash::<Zobrist64>(EnPassantMode::Legal).0)
+}
+
+// Bits: to file, to rank, from file, from rank (3 each), then promotion piece
+pub fn encode_move(board: &Board, chess_move: ChessMove) -> u16 {
+    let from = chess_move.get_source().to_index() as u16;
+    let mut to = chess_move.get_dest().to_index() as u16;
+    if board.piece_on(chess_move.get_source()) == Some(Piece::King) && from.abs_diff(to) == 2 {
+        to = if to > from { to + 1 } else { to - 2 }; // Castling is written as king takes rook
+    }
+    let promotion = match chess_move.get_promotion() {
+        Some(Piece::Knight) => 1,
+        Some(Piece::Bishop) => 2,
+        Some(Piece::Rook) => 3,
+        Some(Piece::Queen) => 4,
+        _ => 0,
+    };
+    to | from << 6 | promotion << 12
+}
+
+fn decode_move(board: &Board, raw: u16) -> ChessMove {
+    let to = square(raw & 0x3f);
+    let from = square((raw >> 6) & 0x3f);
+    let promotion = match (raw >> 12) & 0x7 {
+        1 => Some(Piece::Knight),
+        2 => Some(Piece::Bishop),
+        3 => Some(Piece::Rook),
+        4 => Some(Piece::Queen),
+        _ => None,
+    };
+
+    let castling_to = match (from, to) {
+        (Square::E1, Square::H1) => Some(Square::G1),
+        (Square::E1, Square::A1) => Some(Square::C1),
+        (Square::E8, Square::H8) => Some(Square::G8),
+        (Square::E8, Square::A8) => Some(Square::C8),
+        _ => None,
+    };
+    match castling_to {
+        Some(king_to) if board.piece_on(from) == Some(Piece::King) => {
+            ChessMove::new(from, king_to, None)
+        }
+        _ => ChessMove::new(from, to, promotion),
+    }
+}
+
+fn square(index: u16) -> Square {
+    ALL_SQUARES[index as usize]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_start_position_key() {
+        assert_eq!(polyglot_key(&Board::default()), Some(0x463b96181691fc9c));
+    }
+
+    #[test]
+    fn test_candidates_round_trip() {
+        let castling =
+            Board::from_str("r1bqk2r/pppp1ppp/2n2n2/2b1p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4")
+                .unwrap();
+        let mut built = PolyglotBook::default();
+        for (board, m, weight) in [
+            (Board::default(), "e2e4", 10),
+            (Board::default(), "d2d4", 5),
+            (castling, "e1g1", 3),
+        ] {
+            built.insert(&board, ChessMove::from_str(m).unwrap(), weight);
+        }
+        let book = PolyglotBook::from_bytes(&built.to_bytes()).unwrap();
+        assert_eq!(book.len(), 3);
+
+        let start: Vec<String> = book
+            .candidates(&Board::default())
+            .iter()
+            .map(|entry| format!("{} {}", entry.chess_move, entry.weight))
+            .collect();
+        assert_eq!(start.len(), 2);
+        assert!(start.contains(&"e2e4 10".to_string()));
+        assert!(start.contains(&"d2d4 5".to_string()));
+
+        // Stored as e1h1, read back as the king move
+        let castles = book.candidates(&castling);
+        assert_eq!(castles[0].chess_move, ChessMove::from_str("e1g1").unwrap());
+
+        let out_of_book = Board::default().make_move_new(ChessMove::from_str("a2a3").unwrap());
+        assert!(book.candidates(&out_of_book).is_empty());
+    }
+
+    #[test]
+    fn test_rejects_truncated_book() {
+        assert!(PolyglotBook::from_bytes(&[0; 17]).is_err());
+    }
+
+    #[cfg(feature = "default-book")]
+    #[test]
+    fn test_default_book_has_start_position() {
+        let book = default_book().unwrap();
+        assert!(!book.candidates(&Board::default()).is_empty());
+    }
+}
@@ -0,0 +1,223 @@
+// Whether pondering pays off: ponder searches started, how many the opponent's reply hit, and
+// how much thinking time the hits handed us. Kept per game and for the whole session, split by
+// where the predicted reply came from.
+use chess::ChessMove;
+use log::info;
+use toml::{Table, Value};
+use web_time::Instant;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PredictionSource {
+    Pv,   // Our last move came from a search
+    Book, // Our last move came from the book or repertoire
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PonderCounts {
+    pub started: u64,
+    pub hits: u64,
+    pub misses: u64,
+    pub credited_ms: u64, // Pondering time that ponderhits carried over into our own move
+}
+
+impl PonderCounts {
+    fn add(&mut self, other: &PonderCounts) {
+        self.started += other.started;
+        self.hits += other.hits;
+        self.misses += other.misses;
+        self.credited_ms += other.credited_ms;
+    }
+
+    pub fn average_credit_ms(&self) -> Option<u64> {
+        (self.hits > 0).then(|| self.credited_ms / self.hits)
+    }
+
+    fn to_toml(self) -> Table {
+        let mut table = Table::new();
+        for (key, count) in [
+            ("started", self.started),
+            ("hits", self.hits),
+            ("misses", self.misses),
+            ("credited_ms", self.credited_ms),
+        ] {
+            table.insert(key.to_string(), Value::Integer(count as i64));
+        }
+        table
+    }
+
+    fn from_toml(table: &Table) -> PonderCounts {
+        let count = |key| {
+            table
+                .get(key)
+                .and_then(Value::as_integer)
+                .map_or(0, |count| count.max(0) as u64)
+        };
+        PonderCounts {
+            started: count("started"),
+            hits: count("hits"),
+            misses: count("misses"),
+            credited_ms: count("credited_ms"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PonderStats {
+    pub pv: PonderCounts,
+    pub book: PonderCounts,
+}
+
+impl PonderStats {
+    pub fn total(&self) -> PonderCounts {
+        let mut total = self.pv;
+        total.add(&self.book);
+        total
+    }
+
+    pub fn add(&mut self, other: &PonderStats) {
+        self.pv.add(&other.pv);
+        self.book.add(&other.book);
+    }
+
+    fn counts_mut(&mut self, source: PredictionSource) -> &mut PonderCounts {
+        match source {
+            PredictionSource::Pv => &mut self.pv,
+            PredictionSource::Book => &mut self.book,
+        }
+    }
+
+    // e.g. "ponder started 4 hits 3 misses 1 avg credit 1200ms (pv 3/4, book 0/0)"
+    pub fn summary(&self) -> String {
+        let total = self.total();
+        let credit = match total.average_credit_ms() {
+            Some(ms) => format!("{}ms", ms),
+            None => "none".to_string(),
+        };
+        format!(
+            "ponder started {} hits {} misses {} avg credit {} (pv {}/{}, book {}/{})",
+            total.started,
+            total.hits,
+            total.misses,
+            credit,
+            self.pv.hits,
+            self.pv.started,
+            self.book.hits,
+            self.book.started
+        )
+    }
+
+    // [ponder.pv] and [ponder.book] tables of the career file
+    pub fn to_toml(self) -> Table {
+        let mut table = Table::new();
+        table.insert("pv".to_string(), Value::Table(self.pv.to_toml()));
+        table.insert("book".to_string(), Value::Table(self.book.to_toml()));
+        table
+    }
+
+    pub fn from_toml(table: &Table) -> PonderStats {
+        let counts = |key| {
+            table
+                .get(key)
+                .and_then(Value::as_table)
+                .map(PonderCounts::from_toml)
+                .unwrap_or_default()
+        };
+        PonderStats {
+            pv: counts("pv"),
+            book: counts("book"),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Pending {
+    predicted: Option<ChessMove>, // The opponent reply we're pondering on
+    source: PredictionSource,
+    started: Instant,
+}
+
+#[derive(Debug, Default)]
+pub struct PonderTracker {
+    pending: Option<Pending>,
+    pub game: PonderStats,
+    pub session: PonderStats,
+}
+
+impl PonderTracker {
+    pub fn start(&mut self, predicted: Option<ChessMove>, source: PredictionSource) {
+        self.miss(); // A ponder that was never resolved didn't hit
+        self.record(source, |counts| counts.started += 1);
+        self.pending = Some(Pending {
+            predicted,
+            source,
+            started: Instant::now(),
+        });
+    }
+
+    pub fn hit(&mut self) {
+        if let Some(pending) = self.pending.take() {
+            let credited = pending.started.elapsed().as_millis() as u64;
+            info!(
+                "Ponderhit on {:?} ({:?}), {}ms credited",
+                pending.predicted, pending.source, credited
+            );
+            self.record(pending.source, |counts| {
+                counts.hits += 1;
+                counts.credited_ms += credited;
+            });
+        }
+    }
+
+    pub fn miss(&mut self) {
+        if let Some(pending) = self.pending.take() {
+            info!(
+                "Ponder miss on {:?} ({:?})",
+                pending.predicted, pending.source
+            );
+            self.record(pending.source, |counts| counts.misses += 1);
+        }
+    }
+
+    pub fn pondering(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    pub fn new_game(&mut self) {
+        self.miss();
+        self.game = PonderStats::default();
+    }
+
+    fn record(&mut self, source: PredictionSource, update: impl Fn(&mut PonderCounts)) {
+        update(self.game.counts_mut(source));
+        update(self.session.counts_mut(source));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counts_by_source() {
+        let mut tracker = PonderTracker::default();
+        tracker.start(None, PredictionSource::Pv);
+        tracker.hit();
+        tracker.start(None, PredictionSource::Pv);
+        tracker.start(None, PredictionSource::Book); // Replaces an unresolved ponder
+        tracker.hit();
+        tracker.hit(); // Nothing pending
+        tracker.new_game();
+
+        assert_eq!(tracker.game, PonderStats::default());
+        let total = tracker.session.total();
+        assert_eq!((total.started, total.hits, total.misses), (3, 2, 1));
+        assert_eq!(
+            (tracker.session.book.started, tracker.session.book.hits),
+            (1, 1)
+        );
+        assert_eq!(
+            PonderStats::from_toml(&tracker.session.to_toml()),
+            tracker.session
+        );
+    }
+}
@@ -0,0 +1,240 @@
+// Per-opponent option profiles from the [[profiles]] tables of the config file, picked by the
+// name and rating the GUI announces through UCI_Opponent. Everything here is pure, the session
+// does the applying and reverting.
+use std::collections::HashSet;
+use toml::{Table, Value};
+
+// setoption name UCI_Opponent value <title> <elo> <computer|human> <name>
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Opponent {
+    pub title: Option<String>,
+    pub rating: Option<u32>,
+    pub computer: bool,
+    pub name: String,
+}
+
+impl Opponent {
+    pub fn parse(value: &str) -> Option<Opponent> {
+        let mut parts = value.splitn(4, ' ');
+        let title = parts.next()?;
+        let rating = parts.next()?;
+        let kind = parts.next()?;
+        Some(Opponent {
+            title: (title != "none").then(|| title.to_string()),
+            rating: rating.parse().ok(),
+            computer: kind == "computer",
+            name: parts.next().unwrap_or_default().trim().to_string(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Profile {
+    pub name: String,
+    pub opponent: Option<String>, // Case insensitive part of the opponent's name
+    pub min_rating: Option<u32>,
+    pub max_rating: Option<u32>,
+    pub default: bool,                  // Used when no other profile matches
+    pub options: Vec<(String, String)>, // Option name and value, as setoption would send them
+}
+
+impl Profile {
+    fn matches(&self, opponent: &Opponent) -> bool {
+        let by_name = self.opponent.is_some();
+        let by_rating = self.min_rating.is_some() || self.max_rating.is_some();
+        if !by_name && !by_rating {
+            return false;
+        }
+        let name_ok = self.opponent.as_ref().is_none_or(|pattern| {
+            opponent
+                .name
+                .to_lowercase()
+                .contains(&pattern.to_lowercase())
+        });
+        let rating_ok = !by_rating
+            || opponent.rating.is_some_and(|rating| {
+                self.min_rating.is_none_or(|min| rating >= min)
+                    && self.max_rating.is_none_or(|max| rating <= max)
+            });
+        name_ok && rating_ok
+    }
+
+    // How wide the rating range is, narrower ranges win when they overlap
+    fn range_width(&self) -> u32 {
+        self.max_rating.unwrap_or(u32::MAX) - self.min_rating.unwrap_or(0)
+    }
+}
+
+// Name matches beat rating matches, then the narrowest rating range, then whatever comes first
+pub fn match_profile<'a>(profiles: &'a [Profile], opponent: &Opponent) -> Option<&'a Profile> {
+    profiles
+        .iter()
+        .enumerate()
+        .filter(|(_, profile)| profile.matches(opponent))
+        .min_by_key(|(i, profile)| (profile.opponent.is_none(), profile.range_width(), *i))
+        .map(|(_, profile)| profile)
+        .or_else(|| profiles.iter().find(|profile| profile.default))
+}
+
+// Which of a profile's options to set, and which to leave because the GUI set them itself
+pub fn plan<'a>(
+    profile: &'a Profile,
+    gui_set: &HashSet<String>,
+) -> (Vec<&'a (String, String)>, Vec<&'a str>) {
+    let (skipped, apply): (Vec<_>, Vec<_>) = profile
+        .options
+        .iter()
+        .partition(|(name, _)| gui_set.contains(name));
+    (
+        apply,
+        skipped.iter().map(|(name, _)| name.as_str()).collect(),
+    )
+}
+
+pub fn load_profiles(config: &Table) -> Vec<Profile> {
+    let Some(profiles) = config.get("profiles").and_then(Value::as_array) else {
+        return Vec::new();
+    };
+    profiles
+        .iter()
+        .filter_map(Value::as_table)
+        .map(|table| Profile {
+            name: table
+                .get("name")
+                .and_then(Value::as_str)
+                .unwrap_or("unnamed")
+                .to_string(),
+            opponent: table
+                .get("opponent")
+                .and_then(Value::as_str)
+                .map(str::to_string),
+            min_rating: rating(table, "min_rating"),
+            max_rating: rating(table, "max_rating"),
+            default: table
+                .get("default")
+                .and_then(Value::as_bool)
+                .unwrap_or(false),
+            options: table
+                .get("options")
+                .and_then(Value::as_table)
+                .map(|options| {
+                    options
+                        .iter()
+                        .filter_map(|(name, value)| Some((name.clone(), option_value(value)?)))
+                        .collect()
+                })
+                .unwrap_or_default(),
+        })
+        .collect()
+}
+
+fn rating(table: &Table, key: &str) -> Option<u32> {
+    table
+        .get(key)
+        .and_then(Value::as_integer)
+        .and_then(|rating| u32::try_from(rating).ok())
+}
+
+pub fn option_value(value: &Value) -> Option<String> {
+    match value {
+        Value::Boolean(b) => Some(b.to_string()),
+        Value::String(s) => Some(s.clone()),
+        Value::Integer(i) => Some(i.to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn opponent(value: &str) -> Opponent {
+        Opponent::parse(value).unwrap()
+    }
+
+    fn profiles() -> Vec<Profile> {
+        let profile = |name: &str, opponent: Option<&str>, min, max, default| Profile {
+            name: name.to_string(),
+            opponent: opponent.map(str::to_string),
+            min_rating: min,
+            max_rating: max,
+            default,
+            options: vec![("Book Selection".to_string(), name.to_string())],
+        };
+        vec![
+            profile("club", None, Some(1200), Some(2200), false),
+            profile("strong", None, Some(2000), None, false),
+            profile("expert", None, Some(2000), Some(2300), false),
+            profile("stockfish", Some("stockfish"), None, None, false),
+            profile("fallback", None, None, None, true),
+        ]
+    }
+
+    fn matched(value: &str) -> Option<String> {
+        match_profile(&profiles(), &opponent(value)).map(|profile| profile.name.clone())
+    }
+
+    #[test]
+    fn test_parse_opponent() {
+        assert_eq!(
+            opponent("GM 2800 human Gary Kasparov"),
+            Opponent {
+                title: Some("GM".to_string()),
+                rating: Some(2800),
+                computer: false,
+                name: "Gary Kasparov".to_string()
+            }
+        );
+        let engine = opponent("none none computer Shredder");
+        assert_eq!(
+            (engine.title, engine.rating, engine.computer),
+            (None, None, true)
+        );
+        assert_eq!(Opponent::parse("none"), None);
+    }
+
+    #[test]
+    fn test_match_profiles() {
+        assert_eq!(matched("none 1500 human Someone").as_deref(), Some("club"));
+        // club, strong and expert all cover 2100, expert has the narrowest range
+        assert_eq!(
+            matched("none 2100 human Someone").as_deref(),
+            Some("expert")
+        );
+        assert_eq!(
+            matched("none 2500 human Someone").as_deref(),
+            Some("strong")
+        );
+        // Name beats rating
+        assert_eq!(
+            matched("none 2100 computer Stockfish 16").as_deref(),
+            Some("stockfish")
+        );
+        // Nothing fits, unrated, or no profiles at all
+        assert_eq!(
+            matched("none 800 human Beginner").as_deref(),
+            Some("fallback")
+        );
+        assert_eq!(
+            matched("none none human Someone").as_deref(),
+            Some("fallback")
+        );
+        assert_eq!(
+            match_profile(&[], &opponent("none 1500 human Someone")),
+            None
+        );
+    }
+
+    #[test]
+    fn test_plan_favours_gui() {
+        let mut profile = profiles().remove(0);
+        profile
+            .options
+            .push(("OwnBook".to_string(), "true".to_string()));
+        let gui_set: HashSet<String> = ["Book Selection".to_string()].into_iter().collect();
+
+        let (apply, skipped) = plan(&profile, &gui_set);
+        assert_eq!(apply, vec![&("OwnBook".to_string(), "true".to_string())]);
+        assert_eq!(skipped, vec!["Book Selection"]);
+    }
+}
@@ -0,0 +1,187 @@
+// User supplied opening repertoire. While the game is still in one of its lines the
+// repertoire move is played instead of searching. Keyed by position hash, so
+// transpositions back into a line are picked up too.
+use chess::{Board, ChessMove};
+use log::info;
+use std::{collections::HashMap, fs, path::Path, str::FromStr};
+
+#[derive(Debug, Default, Clone)]
+pub struct Repertoire {
+    moves: HashMap<u64, ChessMove>, // Position hash -> our move, first line listed wins
+}
+
+impl Repertoire {
+    pub fn load(path: &Path) -> Result<Repertoire, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("can't read repertoire {}: {}", path.display(), e))?;
+        Repertoire::parse(&text)
+    }
+
+    // One line per variation, SAN or UCI moves. Move numbers, results, PGN tags and {comments} are skipped
+    pub fn parse(text: &str) -> Result<Repertoire, String> {
+        let mut repertoire = Repertoire::default();
+        for (line_no, line) in text.lines().enumerate() {
+            let line = strip_comments(line);
+            if line.trim_start().starts_with('[') {
+                continue;
+            }
+
+            let mut board = Board::default();
+            for token in line.split_whitespace() {
+                let token = strip_move_number(token);
+                if token.is_empty() || ["1-0", "0-1", "1/2-1/2", "*"].contains(&token) {
+                    continue;
+                }
+                let chess_move = parse_move(&board, token).ok_or(format!(
+                    "repertoire line {}: {} isn't legal here",
+                    line_no + 1,
+                    token
+                ))?;
+                repertoire
+                    .moves
+                    .entry(board.get_hash())
+                    .or_insert(chess_move);
+                board = board.make_move_new(chess_move);
+            }
+        }
+        Ok(repertoire)
+    }
+
+    pub fn move_for(&self, board: &Board) -> Option<ChessMove> {
+        self.moves
+            .get(&board.get_hash())
+            .copied()
+            .filter(|m| board.legal(*m)) // Guard against hash collisions
+    }
+
+    pub fn len(&self) -> usize {
+        self.moves.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.moves.is_empty()
+    }
+}
+
+// Remembers whether we were in the repertoire last move so leaving (and re-entering) it gets logged
+#[derive(Debug, Default, Clone)]
+pub struct RepertoireTracker {
+    in_line: bool,
+}
+
+impl RepertoireTracker {
+    pub fn probe(&mut self, repertoire: &Repertoire, board: &Board) -> Option<ChessMove> {
+        let found = repertoire.move_for(board);
+        match (self.in_line, found.is_some()) {
+            (true, false) => info!("Left the repertoire at {}", board),
+            (false, true) => info!("In the repertoire at {}", board),
+            _ => {}
+        }
+        self.in_line = found.is_some();
+        found
+    }
+
+    pub fn reset(&mut self) {
+        self.in_line = false;
+    }
+}
+
+fn strip_comments(line: &str) -> String {
+    let mut out = String::new();
+    let mut depth = 0;
+    for c in line.chars() {
+        match c {
+            '{' => depth += 1,
+            '}' if depth > 0 => depth -= 1,
+            _ if depth == 0 => out.push(c),
+            _ => {}
+        }
+    }
+    out
+}
+
+// "12.e4", "12." and "12...e5" all leave just the move (or nothing)
+fn strip_move_number(token: &str) -> &str {
+    let digits = token.trim_start_matches(|c: char| c.is_ascii_digit());
+    if digits.len() < token.len() && digits.starts_with('.') {
+        digits.trim_start_matches('.')
+    } else {
+        token
+    }
+}
+
+fn parse_move(board: &Board, token: &str) -> Option<ChessMove> {
+    let token = token.trim_end_matches(['+', '#', '!', '?']);
+    ChessMove::from_san(board, token)
+        .ok()
+        .or_else(|| ChessMove::from_str(token).ok())
+        .filter(|m| board.legal(*m))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn play(moves: &str) -> Board {
+        moves.split_whitespace().fold(Board::default(), |board, m| {
+            board.make_move_new(ChessMove::from_str(m).unwrap())
+        })
+    }
+
+    fn uci(m: &str) -> Option<ChessMove> {
+        Some(ChessMove::from_str(m).unwrap())
+    }
+
+    const REPERTOIRE: &str = "\
+[Event \"my repertoire\"]
+1. e4 e5 2. Nf3 Nc6 3. Bb5 {the Spanish} a6
+1. Nf3 d5 2. d4 Nf6 3. c4
+d2d4 g8f6 c2c4 e7e6
+";
+
+    #[test]
+    fn test_plays_in_line() {
+        let repertoire = Repertoire::parse(REPERTOIRE).unwrap();
+        assert_eq!(repertoire.move_for(&Board::default()), uci("e2e4"));
+        assert_eq!(repertoire.move_for(&play("e2e4 e7e5")), uci("g1f3"));
+        assert_eq!(
+            repertoire.move_for(&play("e2e4 e7e5 g1f3 b8c6")),
+            uci("f1b5")
+        );
+    }
+
+    #[test]
+    fn test_exit_point() {
+        let repertoire = Repertoire::parse(REPERTOIRE).unwrap();
+        let mut tracker = RepertoireTracker::default();
+        assert_eq!(tracker.probe(&repertoire, &play("e2e4 e7e5")), uci("g1f3"));
+        assert_eq!(
+            tracker.probe(&repertoire, &play("e2e4 e7e5 g1f3 g8f6")),
+            None
+        );
+        assert!(!tracker.in_line);
+    }
+
+    #[test]
+    fn test_transposition_reenters() {
+        let repertoire = Repertoire::parse(REPERTOIRE).unwrap();
+        // 1. d4 d5 2. Nf3 reaches the same position as 1. Nf3 d5 2. d4
+        assert_eq!(repertoire.move_for(&play("d2d4 d7d5 g1f3")), uci("g8f6"));
+        // and 1. d4 Nf6 2. Nf3 d5 3. c4 from the UCI line lands back in the first Nf3 line
+        assert_eq!(
+            repertoire.move_for(&play("d2d4 g8f6 g1f3 d7d5")),
+            uci("c2c4")
+        );
+    }
+
+    #[test]
+    fn test_load_file_and_reject_illegal() {
+        let path = std::env::temp_dir().join("shallow-red-repertoire-test.txt");
+        fs::write(&path, REPERTOIRE).unwrap();
+        let repertoire = Repertoire::load(&path).unwrap();
+        assert!(!repertoire.is_empty());
+        let _ = fs::remove_file(&path);
+
+        assert!(Repertoire::parse("1. e4 e4").is_err());
+    }
+}
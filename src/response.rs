@@ -0,0 +1,191 @@
+// What the engine sends back, as a UciResponse. Everything the GUI sees is written out here, so the
+// order of an info line's fields and the forms of bestmove only have to be right in one place.
+use chess::ChessMove;
+use std::{fmt, time::Duration};
+
+use crate::{display::Score, options::OptionSpec};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UciResponse {
+    Id {
+        name: String,
+        author: String,
+    },
+    UciOk,
+    ReadyOk,
+    Info(InfoFields),
+    BestMove {
+        best: Option<ChessMove>, // None when there's nothing legal, sent as 0000
+        ponder: Option<ChessMove>,
+    },
+    Option {
+        spec: &'static OptionSpec,
+        default: String, // What we advertise, a config file can change it from the spec's
+    },
+}
+
+// Sent in this order, string last since it takes the rest of the line
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InfoFields {
+    pub depth: Option<u32>,
+    pub multipv: Option<u8>,
+    pub time: Option<Duration>,
+    pub hashfull: Option<u64>,
+    pub currmove: Option<(ChessMove, usize)>, // And its number, counting from 1
+    pub score: Option<Score>,
+    pub wdl: Option<(u32, u32, u32)>,
+    pub pv: Vec<ChessMove>,
+    pub string: Option<String>,
+}
+
+impl UciResponse {
+    pub fn info_string(text: impl Into<String>) -> UciResponse {
+        UciResponse::Info(InfoFields {
+            string: Some(text.into()),
+            ..Default::default()
+        })
+    }
+
+    pub fn bestmove(best: ChessMove) -> UciResponse {
+        UciResponse::BestMove {
+            best: Some(best),
+            ponder: None,
+        }
+    }
+}
+
+impl fmt::Display for UciResponse {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UciResponse::Id { name, author } => write!(f, "id name {}\nid author {}", name, author),
+            UciResponse::UciOk => write!(f, "uciok"),
+            UciResponse::ReadyOk => write!(f, "readyok"),
+            UciResponse::Info(fields) => write!(f, "info{}", fields),
+            UciResponse::BestMove { best, ponder } => {
+                match best {
+                    Some(best) => write!(f, "bestmove {}", best)?,
+                    None => write!(f, "bestmove 0000")?,
+                }
+                match ponder {
+                    Some(ponder) if best.is_some() => write!(f, " ponder {}", ponder),
+                    _ => Ok(()),
+                }
+            }
+            UciResponse::Option { spec, default } => write!(f, "{}", spec.uci_line(default)),
+        }
+    }
+}
+
+// Each field with the space before it, so an empty InfoFields writes nothing
+impl fmt::Display for InfoFields {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(depth) = self.depth {
+            write!(f, " depth {}", depth)?;
+        }
+        if let Some(index) = self.multipv {
+            write!(f, " multipv {}", index)?;
+        }
+        if let Some(time) = self.time {
+            write!(f, " time {}", time.as_millis())?;
+        }
+        if let Some(permille) = self.hashfull {
+            write!(f, " hashfull {}", permille)?;
+        }
+        if let Some((chess_move, number)) = self.currmove {
+            write!(f, " currmove {} currmovenumber {}", chess_move, number)?;
+        }
+        if let Some(score) = self.score {
+            write!(f, " score {}", score.uci())?;
+        }
+        if let Some((win, draw, loss)) = self.wdl {
+            write!(f, " wdl {} {} {}", win, draw, loss)?;
+        }
+        if !self.pv.is_empty() {
+            let pv: Vec<String> = self.pv.iter().map(ChessMove::to_string).collect();
+            write!(f, " pv {}", pv.join(" "))?;
+        }
+        if let Some(text) = &self.string {
+            write!(f, " string {}", text)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::OPTIONS;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_response_lines() {
+        let e2e4 = ChessMove::from_str("e2e4").unwrap();
+        let e7e5 = ChessMove::from_str("e7e5").unwrap();
+        assert_eq!(
+            UciResponse::Id {
+                name: "Shallow Red".to_string(),
+                author: "someone".to_string()
+            }
+            .to_string(),
+            "id name Shallow Red\nid author someone"
+        );
+        assert_eq!(UciResponse::ReadyOk.to_string(), "readyok");
+        assert_eq!(
+            UciResponse::BestMove {
+                best: Some(e2e4),
+                ponder: Some(e7e5)
+            }
+            .to_string(),
+            "bestmove e2e4 ponder e7e5"
+        );
+        assert_eq!(
+            UciResponse::BestMove {
+                best: None,
+                ponder: Some(e7e5)
+            }
+            .to_string(),
+            "bestmove 0000"
+        );
+        let hash = OPTIONS.iter().find(|spec| spec.name == "Hash").unwrap();
+        assert_eq!(
+            UciResponse::Option {
+                spec: hash,
+                default: "16".to_string()
+            }
+            .to_string(),
+            hash.uci_line("16")
+        );
+    }
+
+    #[test]
+    fn test_info_field_order() {
+        let e2e4 = ChessMove::from_str("e2e4").unwrap();
+        let info = InfoFields {
+            string: Some("last".to_string()),
+            pv: vec![e2e4, ChessMove::from_str("e7e5").unwrap()],
+            wdl: Some((500, 400, 100)),
+            score: Some(Score::Centipawns(30)),
+            multipv: Some(1),
+            depth: Some(3),
+            ..Default::default()
+        };
+        assert_eq!(
+            UciResponse::Info(info).to_string(),
+            "info depth 3 multipv 1 score cp 30 wdl 500 400 100 pv e2e4 e7e5 string last"
+        );
+        let progress = InfoFields {
+            time: Some(Duration::from_millis(1500)),
+            hashfull: Some(12),
+            currmove: Some((e2e4, 4)),
+            ..Default::default()
+        };
+        assert_eq!(
+            UciResponse::Info(progress).to_string(),
+            "info time 1500 hashfull 12 currmove e2e4 currmovenumber 4"
+        );
+        assert_eq!(
+            UciResponse::info_string("unknown option Foo").to_string(),
+            "info string unknown option Foo"
+        );
+    }
+}
@@ -0,0 +1,50 @@
+// Small seedable generator (splitmix64) so anything random in a session can be replayed from a seed
+use web_time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionRng {
+    state: u64,
+}
+
+impl SessionRng {
+    pub fn seeded(seed: u64) -> SessionRng {
+        SessionRng { state: seed }
+    }
+
+    pub fn from_entropy() -> SessionRng {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|t| t.as_nanos() as u64)
+            .unwrap_or(0);
+        SessionRng::seeded(nanos)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // Uniform value in 0..n, n must be non-zero
+    pub fn below(&mut self, n: u64) -> u64 {
+        ((self.next_u64() as u128 * n as u128) >> 64) as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SessionRng;
+
+    #[test]
+    fn test_seed_reproducible() {
+        let mut a = SessionRng::seeded(42);
+        let mut b = SessionRng::seeded(42);
+        for _ in 0..100 {
+            let value = a.below(7);
+            assert_eq!(value, b.below(7));
+            assert!(value < 7);
+        }
+    }
+}
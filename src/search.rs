@@ -0,0 +1,1589 @@
+use chess::{Board, BoardStatus, ChessMove, Color, MoveGen, Piece, Rank, ALL_PIECES};
+use log::{error, info, warn};
+use parking_lot::{Mutex, RwLock};
+use shallow_red_engine::{
+    managers::cache_manager::{Cache, CacheInputGrouping},
+    utils::engine_interface::EngineSettings,
+};
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+        Arc, OnceLock,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+use web_time::Instant;
+
+use crate::{
+    backend::{SearchBackend, SearchOutcome},
+    capture::{centipawns, EpdCapture},
+    chess960::bestmove_out,
+    display::Score,
+    game::next_halfmove_clock,
+    mate::find_mate,
+    response::{InfoFields, UciResponse},
+    timecontrol::ScoreLog,
+    timeusage::TimeUsage,
+    training::TrainingExport,
+};
+
+// A search for the session's worker, or for the frontend to run inline when it has no threads (wasm)
+pub struct SearchRequest {
+    pub board: Board,
+    pub settings: EngineSettings,
+    pub backend: Arc<dyn SearchBackend>,
+    pub generation: u64, // Counts up with every go
+    pub fast: bool,      // Bullet fast path, cache writes deferred and logging kept quiet
+    pub deferred_cache: Option<Box<dyn FnOnce() + Send>>, // Applies cache writes queued during the search
+    pub capture: Option<Arc<EpdCapture>>,                 // Capture File option
+    pub root_moves: Option<Vec<ChessMove>>, // searchmoves/avoidmoves, the bestmove has to be one of these
+    pub notice: Option<String>,             // info string sent ahead of the bestmove
+    pub training: Option<(Arc<TrainingExport>, u32)>, // --export-training, and the ply being searched
+    pub until_stop: bool, // go infinite, the bestmove waits for stop even when the engine is done first
+    pub mate: Option<u8>, // go mate, look for a forced mate in this many moves before asking the engine
+    pub helpers: u8,      // Threads option, extra searches sharing the cache with this one
+    pub chess960: bool,   // UCI_Chess960, castling goes out as king takes rook
+    pub show_wdl: bool,   // UCI_ShowWDL, scores come with a win/draw/loss estimate
+    pub ponder_move: bool, // Ponder option, the bestmove line names the reply we expect
+    pub progress: Option<Arc<Progress>>, // Set by the worker, inline searches report nothing
+    pub hash_usage: Option<Arc<HashUsage>>, // Counts this search's cache writes for info hashfull
+    pub history: Option<Arc<Vec<u64>>>, // Hashes of the game's positions so far, this one included
+    pub halfmove_clock: u32, // Plies since the last capture or pawn move, for the fifty move rule
+    pub hard_limit: Option<Duration>, // On the clock, the worker stops the engine here if it's still going
+    pub scores: Option<Arc<ScoreLog>>, // Where a game search leaves its eval for the next budget
+    pub time_usage: Option<Arc<TimeUsage>>, // Game searches, how long finding the move took
+}
+
+impl SearchRequest {
+    // Runs the search, hands the bestmove line to `output` and only then applies any deferred cache writes
+    pub fn run(mut self, output: impl FnOnce(String)) {
+        let (board, chess960, generation) = (self.board, self.chess960, self.generation);
+        let (started, time_usage) = (Instant::now(), self.time_usage.take());
+        let budget = self.settings.time_limit; // Everything up to the bestmove line is paid from this
+        let output = move |out: String| {
+            if let Some(time_usage) = time_usage {
+                time_usage.finish(generation, started.elapsed());
+            }
+            match chess960 {
+                true => output(bestmove_out(&board, &out)),
+                false => output(out),
+            }
+        };
+        if let Some(moves) = self.mate {
+            if let Some(mate_out) = self.mate_search(moves) {
+                return match self.notice {
+                    Some(notice) => output(format!("info string {}\n{}", notice, mate_out)),
+                    None => output(mate_out),
+                };
+            }
+        }
+        self.count_cache_writes();
+        // Guessing their reply comes out of our time, so the engine gets that much less. Not on
+        // the fast path, the time's too short.
+        let guess_time = match self.ponder_move && !self.fast {
+            true => (self.settings.time_limit / 10).min(PONDER_GUESS_TIME),
+            false => Duration::ZERO,
+        };
+        self.settings.time_limit -= guess_time;
+        let guess_cache = self.settings.cache_settings.clone();
+        let helpers = self.start_helpers();
+        let (mut engine_out, mut outcome) = run_engine(
+            &*self.backend,
+            self.board,
+            self.settings,
+            self.fast,
+            self.capture.as_deref(),
+            self.root_moves.as_deref(),
+            self.training
+                .as_ref()
+                .map(|(training, ply)| (&**training, *ply)),
+        );
+        for stop in helpers {
+            let _ = stop.send(true); // Already finished is fine
+        }
+        // No more than the budget has left if the engine ran over, and nothing once the search has
+        // been stopped, the GUI's waiting on the bestmove by then
+        let guess_time = guess_time.min(budget.saturating_sub(started.elapsed()));
+        let stopped = self
+            .progress
+            .as_ref()
+            .is_some_and(|progress| progress.stopping());
+        if let Some(reply) = (!guess_time.is_zero() && !stopped)
+            .then(|| {
+                expected_reply(
+                    &*self.backend,
+                    self.board,
+                    outcome.best_move,
+                    guess_time,
+                    guess_cache,
+                )
+            })
+            .flatten()
+        {
+            engine_out += &format!(" ponder {}", reply);
+        }
+        // The engine doesn't know the game's history, so draws by it are spotted here
+        let history = self.history.as_ref().map(|history| history.as_slice());
+        if let Some(draw) = drawn_by(&self.board, outcome.best_move, history, self.halfmove_clock) {
+            info!("{} {}", outcome.best_move, draw);
+            outcome.score = Some(Score::Centipawns(0));
+            engine_out = format!(
+                "info string {} {}, a draw\n{}",
+                outcome.best_move, draw, engine_out
+            );
+        }
+        if let Some(scores) = &self.scores {
+            scores.record(outcome.score);
+        }
+        if !self.fast {
+            let none = || "none".to_string();
+            info!(
+                "Search summary: bestmove {}, score {}, nodes {}, time {}ms, fen {}",
+                outcome.best_move,
+                outcome.score.map_or_else(none, |score| score.uci()),
+                outcome.nodes.map_or_else(none, |nodes| nodes.to_string()),
+                started.elapsed().as_millis(),
+                self.board
+            );
+        }
+        if self.show_wdl && outcome.score.is_some() {
+            engine_out = format!("{}\n{}", info_line(&outcome, true), engine_out);
+        }
+        match self.notice {
+            Some(notice) => output(format!("info string {}\n{}", notice, engine_out)),
+            None => output(engine_out),
+        }
+        if let Some(apply_cache_writes) = self.deferred_cache {
+            apply_cache_writes();
+        }
+    }
+
+    // Only worth it when there are info lines to put hashfull in
+    fn count_cache_writes(&mut self) {
+        let (Some(progress), Some(usage)) = (&self.progress, &self.hash_usage) else {
+            return;
+        };
+        if let Some(cache) = self
+            .settings
+            .cache_settings
+            .as_mut()
+            .filter(|_| self.deferred_cache.is_none())
+        {
+            usage.relay(cache);
+            progress.set_hash_usage(usage.clone());
+        }
+    }
+
+    // The engine searches on one thread, so Threads runs more searches of the same position to fill
+    // the shared cache for this one. Their moves are dropped, and they're stopped once it answers.
+    // Not when cache writes wait until after the bestmove (fast path, wasm).
+    fn start_helpers(&self) -> Vec<Sender<bool>> {
+        let Some(cache) = self
+            .settings
+            .cache_settings
+            .as_ref()
+            .filter(|_| self.deferred_cache.is_none())
+        else {
+            return Vec::new();
+        };
+        (0..self.helpers)
+            .map(|_| {
+                let (stop_tx, stop_rx) = mpsc::channel();
+                let settings = EngineSettings {
+                    time_limit: self.settings.time_limit,
+                    verbose: false,
+                    stop_engine_rcv: Some(stop_rx),
+                    cache_settings: Some(cache.clone()),
+                    ..Default::default()
+                };
+                let (backend, board) = (self.backend.clone(), self.board);
+                thread::spawn(move || backend.search(board, settings));
+                stop_tx
+            })
+            .collect()
+    }
+
+    // Up to half the time on the mate search, what's left goes to the engine if there's no mate.
+    // After a stop the engine only gets a moment, there's still a bestmove owed.
+    fn mate_search(&mut self, moves: u8) -> Option<String> {
+        let started = Instant::now();
+        let deadline = started + self.settings.time_limit / 2;
+        let mut stopped = false;
+        let stop_rx = self.settings.stop_engine_rcv.as_ref();
+        let progress = self.progress.as_deref();
+        let found = find_mate(
+            &self.board,
+            moves,
+            self.root_moves.as_deref(),
+            &mut |chess_move, number| {
+                if let Some(progress) = progress {
+                    progress.set_currmove(chess_move, number);
+                }
+            },
+            &mut || {
+                stopped = stopped || stop_rx.is_some_and(|rx| rx.try_recv().is_ok());
+                stopped || Instant::now() > deadline
+            },
+        );
+        if let Some((chess_move, moves)) = found {
+            info!("Mate in {} with {} on {}", moves, chess_move, self.board);
+            let info = UciResponse::Info(InfoFields {
+                depth: Some(moves as u32 * 2 - 1),
+                score: Some(Score::Mate(moves as i32)),
+                wdl: self.show_wdl.then_some((1000, 0, 0)),
+                pv: vec![chess_move],
+                ..Default::default()
+            });
+            // Every reply gets mated, any of them will do
+            let bestmove = UciResponse::BestMove {
+                best: Some(chess_move),
+                ponder: match self.ponder_move {
+                    true => MoveGen::new_legal(&self.board.make_move_new(chess_move)).next(),
+                    false => None,
+                },
+            };
+            return Some(format!("{}\n{}", info, bestmove));
+        }
+        self.settings.time_limit = match stopped {
+            true => STOPPED_MATE_SEARCH_TIME,
+            false => self.settings.time_limit.saturating_sub(started.elapsed()),
+        };
+        None
+    }
+}
+
+const STOPPED_MATE_SEARCH_TIME: Duration = Duration::from_millis(10);
+const PONDER_GUESS_TIME: Duration = Duration::from_millis(20);
+
+// The engine has no PV to take a ponder move from, so it gets a quick look at their side of the
+// board after `played`. Nothing when the game's over or the engine comes back with nonsense.
+fn expected_reply(
+    backend: &dyn SearchBackend,
+    board: Board,
+    played: ChessMove,
+    time_limit: Duration,
+    cache: Option<CacheInputGrouping>,
+) -> Option<ChessMove> {
+    let after = board.make_move_new(played);
+    MoveGen::new_legal(&after).next()?;
+    let settings = EngineSettings {
+        time_limit,
+        verbose: false,
+        cache_settings: cache,
+        ..Default::default()
+    };
+    let reply = backend.search(after, settings).best_move;
+    checked_move(&after, reply, None).ok()
+}
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+const STOP_GRACE: Duration = Duration::from_millis(100); // From stop to a fallback bestmove
+
+pub fn run_engine(
+    backend: &dyn SearchBackend,
+    board: Board,
+    settings: EngineSettings,
+    quiet: bool,
+    capture: Option<&EpdCapture>,
+    root_moves: Option<&[ChessMove]>,
+    training: Option<(&TrainingExport, u32)>,
+) -> (String, SearchOutcome) {
+    if !quiet {
+        info!(
+            "Running search on board {}, with settings {:#?}",
+            board.to_string(),
+            settings
+        );
+    }
+    // Only some root moves allowed, and not all of them
+    let restricted = root_moves.filter(|allowed| allowed.len() < MoveGen::new_legal(&board).len());
+    let outcome = match restricted {
+        Some(allowed) => search_allowed(backend, board, settings, allowed),
+        None => backend.search(board, settings),
+    };
+    let outcome = SearchOutcome {
+        score: outcome.score.map(Score::normalised),
+        ..outcome
+    };
+    let best_move = outcome.best_move;
+    let checked = checked_move(&board, best_move, root_moves);
+    let (played, fallback) = match &checked {
+        Ok(played) => (*played, false),
+        Err((substitute, _)) => (*substitute, true),
+    };
+    if let Some(capture) = capture {
+        capture.after_search(&board, played, outcome.score, fallback);
+    }
+    if let Some((training, ply)) = training {
+        training.after_search(&board, ply, played, outcome.score, fallback);
+    }
+
+    let played_outcome = SearchOutcome {
+        best_move: played,
+        ..outcome
+    };
+    let out = match checked {
+        Ok(best_move) => UciResponse::bestmove(best_move).to_string(),
+        Err((fallback, problem)) => {
+            // An illegal bestmove forfeits the game, anything legal is better than that
+            error!(
+                "Engine returned {} on {}: {}, playing {} instead",
+                best_move, board, problem, fallback
+            );
+            let note = format!(
+                "engine move {} rejected ({}), substituted {}",
+                best_move, problem, fallback
+            );
+            format!(
+                "{}\n{}",
+                UciResponse::info_string(note),
+                UciResponse::bestmove(fallback)
+            )
+        }
+    };
+    (out, played_outcome)
+}
+
+// The info line for a searched move, the score (and wdl) only when the backend gave one
+fn info_line(outcome: &SearchOutcome, wdl: bool) -> String {
+    UciResponse::Info(InfoFields {
+        score: outcome.score,
+        wdl: outcome.score.filter(|_| wdl).map(|score| score.wdl()),
+        pv: vec![outcome.best_move],
+        ..Default::default()
+    })
+    .to_string()
+}
+
+// The move back, or a legal substitute plus what was wrong with it. A restricted root is
+// searched a move at a time, so a move outside `root_moves` means the backend went wrong, and
+// it's swapped for the first one allowed.
+pub(crate) fn checked_move(
+    board: &Board,
+    chess_move: ChessMove,
+    root_moves: Option<&[ChessMove]>,
+) -> Result<ChessMove, (ChessMove, String)> {
+    let source = chess_move.get_source();
+    let last_rank = match board.side_to_move() {
+        Color::White => Rank::Eighth,
+        Color::Black => Rank::First,
+    };
+    let needs_promotion = board.piece_on(source) == Some(Piece::Pawn)
+        && board.color_on(source) == Some(board.side_to_move())
+        && chess_move.get_dest().get_rank() == last_rank;
+
+    let problem = if needs_promotion && chess_move.get_promotion().is_none() {
+        "promotion missing"
+    } else if !needs_promotion && chess_move.get_promotion().is_some() {
+        "promotion on a non promoting move"
+    } else if !board.legal(chess_move) {
+        "illegal in this position"
+    } else if root_moves.is_some_and(|allowed| !allowed.contains(&chess_move)) {
+        "excluded at the root"
+    } else {
+        return Ok(chess_move);
+    };
+
+    let fallback = match root_moves {
+        Some(allowed) => allowed.iter().copied().find(|m| board.legal(*m)),
+        None => MoveGen::new_legal(board).next(),
+    };
+    match fallback {
+        Some(fallback) => Err((fallback, problem.to_string())),
+        None => Ok(chess_move), // Game is over, nothing legal to swap in
+    }
+}
+
+// The engine can't be told to leave root moves out, so each allowed move gets an equal share of
+// the time on the position after it, and the one leaving the opponent worst off is played. That's
+// their score when the backend gives one, and the material once the engine's reply is on the
+// board when it doesn't.
+fn search_allowed(
+    backend: &dyn SearchBackend,
+    board: Board,
+    mut settings: EngineSettings,
+    allowed: &[ChessMove],
+) -> SearchOutcome {
+    let stop_rx = settings.stop_engine_rcv.take();
+    let current_stop = &Mutex::new(None);
+    let (stopped, done) = (&AtomicBool::new(false), &AtomicBool::new(false));
+    let share = settings.time_limit / allowed.len().max(1) as u32;
+    thread::scope(|scope| {
+        if let Some(stop_rx) = stop_rx {
+            scope.spawn(move || relay_stop(stop_rx, current_stop, stopped, done));
+        }
+        let mut best: Option<(i32, ChessMove, Option<Score>)> = None;
+        let mut nodes = Vec::new();
+        for &chess_move in allowed {
+            let after = board.make_move_new(chess_move);
+            let (rank, score) = match after.status() {
+                BoardStatus::Checkmate => (i32::MAX, Some(Score::Mate(1))),
+                BoardStatus::Stalemate => (0, Some(Score::Centipawns(0))),
+                BoardStatus::Ongoing => {
+                    let (stop_tx, stop_rx) = mpsc::channel();
+                    *current_stop.lock() = Some(stop_tx); // Before looking, so no stop is missed
+                    let time_limit = match stopped.load(Ordering::SeqCst) {
+                        true => STOPPED_ROOT_SEARCH_TIME,
+                        false => share,
+                    };
+                    let outcome = backend.search(
+                        after,
+                        EngineSettings {
+                            time_limit,
+                            verbose: false,
+                            stop_engine_rcv: Some(stop_rx),
+                            cache_settings: settings.cache_settings.clone(),
+                            ..Default::default()
+                        },
+                    );
+                    nodes.push(outcome.nodes);
+                    match outcome.score {
+                        Some(score) => {
+                            let ours = score.normalised().negated();
+                            (centipawns(ours), Some(ours))
+                        }
+                        None => {
+                            let reply = checked_move(&after, outcome.best_move, None)
+                                .unwrap_or_else(|(fallback, _)| fallback);
+                            let replied = after.make_move_new(reply);
+                            (material(&replied, board.side_to_move()), None)
+                        }
+                    }
+                }
+            };
+            if best.is_none_or(|(best_rank, ..)| rank > best_rank) {
+                best = Some((rank, chess_move, score));
+            }
+        }
+        done.store(true, Ordering::SeqCst);
+        let (_, best_move, score) = best.expect("no allowed moves");
+        info!(
+            "Searched {} allowed moves on {}, {} is the best of them",
+            allowed.len(),
+            board,
+            best_move
+        );
+        SearchOutcome {
+            best_move,
+            score,
+            nodes: nodes.into_iter().sum(),
+        }
+    })
+}
+
+const STOPPED_ROOT_SEARCH_TIME: Duration = Duration::from_millis(10);
+const STOP_RELAY_POLL: Duration = Duration::from_millis(5);
+
+// Passes a stop for the whole search on to whichever allowed move is being searched, until `done`
+fn relay_stop(
+    stop_rx: Receiver<bool>,
+    current_stop: &Mutex<Option<Sender<bool>>>,
+    stopped: &AtomicBool,
+    done: &AtomicBool,
+) {
+    while !done.load(Ordering::SeqCst) {
+        match stop_rx.recv_timeout(STOP_RELAY_POLL) {
+            Ok(_) => {
+                stopped.store(true, Ordering::SeqCst);
+                if let Some(stop) = current_stop.lock().as_ref() {
+                    let _ = stop.send(true);
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+    }
+}
+
+const PIECE_CP: [i32; 5] = [100, 300, 300, 500, 900]; // Pawn to queen
+
+// Material balance for `color`, in centipawns
+fn material(board: &Board, color: Color) -> i32 {
+    let side = |color: Color| -> i32 {
+        ALL_PIECES
+            .iter()
+            .zip(PIECE_CP)
+            .map(|(piece, cp)| {
+                (board.pieces(*piece) & board.color_combined(color)).popcnt() as i32 * cp
+            })
+            .sum()
+    };
+    side(color) - side(!color)
+}
+
+// Fresh table with its own manager thread, which quits once every sender has been dropped
+pub fn spawn_cache_manager() -> CacheInputGrouping {
+    start_cache_manager().0
+}
+
+// The same, with the thread to wait on at shutdown
+fn start_cache_manager() -> (CacheInputGrouping, JoinHandle<()>) {
+    let cache_ref = Arc::new(RwLock::new(Cache::default()));
+    let (cache_tx, cache_rx) = Cache::generate_channel();
+    let server_ref = cache_ref.clone();
+    let manager = thread::spawn(move || Cache::cache_manager_server(server_ref, cache_rx));
+    let cache = CacheInputGrouping {
+        cache_ref,
+        cache_tx,
+    };
+    (cache, manager)
+}
+
+// The cache and its manager thread, only built once something needs them so uci and isready
+// don't wait on it. Whoever gets there first builds it, anyone else waits for that one.
+#[derive(Default)]
+pub struct LazyCache {
+    cache: OnceLock<CacheInputGrouping>,
+    started: AtomicBool,
+    manager: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl LazyCache {
+    pub fn get(&self) -> &CacheInputGrouping {
+        self.started.store(true, Ordering::SeqCst);
+        self.cache.get_or_init(|| {
+            let (cache, manager) = start_cache_manager();
+            *self.manager.lock() = Some(manager);
+            cache
+        })
+    }
+
+    // The manager thread, for quit to wait on once every sender's gone
+    pub fn take_manager(&self) -> Option<JoinHandle<()>> {
+        self.manager.lock().take()
+    }
+
+    // Builds it in the background, get() waits for that instead of building another
+    pub fn kick_off(self: &Arc<Self>) {
+        if !self.started.swap(true, Ordering::SeqCst) {
+            let lazy = self.clone();
+            thread::spawn(move || {
+                lazy.get();
+            });
+        }
+    }
+
+    pub fn started(&self) -> bool {
+        self.started.load(Ordering::SeqCst)
+    }
+
+    pub fn is_initialized(&self) -> bool {
+        self.cache.get().is_some()
+    }
+}
+
+// How full the shared table is, for info hashfull. The Cache can't say, so this counts what
+// searches send its manager and guesses ESTIMATED_ENTRY_BYTES for each against the Hash size.
+// Overwrites count as new entries, so it runs high late in a game.
+pub struct HashUsage {
+    writes: AtomicU64,
+    capacity: AtomicU64, // Bytes
+}
+
+const ESTIMATED_ENTRY_BYTES: u64 = 64;
+
+impl HashUsage {
+    pub fn new(megabytes: i64) -> HashUsage {
+        let usage = HashUsage {
+            writes: AtomicU64::new(0),
+            capacity: AtomicU64::new(0),
+        };
+        usage.reset(megabytes);
+        usage
+    }
+
+    // A new or emptied table
+    pub fn reset(&self, megabytes: i64) {
+        self.writes.store(0, Ordering::SeqCst);
+        self.set_capacity(megabytes);
+    }
+
+    pub fn set_capacity(&self, megabytes: i64) {
+        self.capacity
+            .store(megabytes.max(1) as u64 * 1024 * 1024, Ordering::SeqCst);
+    }
+
+    pub fn permille(&self) -> u64 {
+        let used = self.writes.load(Ordering::SeqCst) * ESTIMATED_ENTRY_BYTES;
+        (used * 1000 / self.capacity.load(Ordering::SeqCst).max(1)).min(1000)
+    }
+
+    // Puts a counting hop between `cache`'s users and its manager, gone once they drop their senders
+    fn relay(self: &Arc<Self>, cache: &mut CacheInputGrouping) {
+        let (relay_tx, relay_rx) = Cache::generate_channel();
+        let manager_tx = std::mem::replace(&mut cache.cache_tx, relay_tx);
+        let usage = self.clone();
+        thread::spawn(move || {
+            for message in relay_rx {
+                usage.writes.fetch_add(1, Ordering::SeqCst);
+                if manager_tx.send(message).is_err() {
+                    return;
+                }
+            }
+        });
+    }
+}
+
+// Same shared table, but writes go to a private channel and are only applied by calling the
+// returned closure. The cache server drains the channel and returns once the search drops its sender.
+pub fn deferred_cache(
+    cache_ref: &Arc<RwLock<Cache>>,
+) -> (CacheInputGrouping, Box<dyn FnOnce() + Send>) {
+    let (cache_tx, cache_rx) = Cache::generate_channel();
+    let server_ref = cache_ref.clone();
+    let apply_writes = Box::new(move || Cache::cache_manager_server(server_ref, cache_rx));
+    (
+        CacheInputGrouping {
+            cache_ref: cache_ref.clone(),
+            cache_tx,
+        },
+        apply_writes,
+    )
+}
+
+pub enum WorkerMessage {
+    Search(Box<SearchRequest>),
+    Stop,
+    PonderHit(Duration), // The ponder search becomes a normal one with this long left
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchCompleted {
+    pub generation: u64, // Matches SearchRequest::generation
+    pub output: String,  // The bestmove line, or an info line sent while searching
+}
+
+// Where a worker sends what its searches produce, called from the worker's threads. It goes once
+// the worker and its searches are done with it, which closes a channel behind it.
+pub type CompletedSink = Arc<dyn Fn(SearchCompleted) + Send + Sync>;
+
+pub fn channel_sink(done_tx: Sender<SearchCompleted>) -> CompletedSink {
+    Arc::new(move |done| {
+        let _ = done_tx.send(done);
+    })
+}
+
+// JoinHandle::join, but giving up after `within`
+pub fn join_within(handle: JoinHandle<()>, within: Duration) -> bool {
+    let deadline = Instant::now() + within;
+    while !handle.is_finished() {
+        if Instant::now() > deadline {
+            return false;
+        }
+        thread::sleep(Duration::from_millis(5));
+    }
+    let _ = handle.join();
+    true
+}
+
+// What a worker search streams before its bestmove. The engine says nothing until it's done, so
+// that's how long it's been at it, plus the root move being tried when the search is our own (go
+// mate). Sent every PROGRESS_INTERVAL until answer(), which goes through the same sender so no
+// info line can come after the bestmove.
+pub struct Progress {
+    generation: u64,
+    started: Instant,
+    fallback: String, // bestmove line for when the engine doesn't stop in time
+    state: Mutex<Lifecycle>,
+    currmove: Mutex<Option<(ChessMove, usize)>>, // And its number, counting from 1
+    hash_usage: Mutex<Option<Arc<HashUsage>>>,
+}
+
+// Where a worker search is at. Answering is the only way out of Searching and Stopping and
+// nothing leaves Answered, so every go gets its one bestmove whichever of the engine, stop,
+// ponderhit, the fallback or quit gets there first.
+enum Lifecycle {
+    Searching(CompletedSink),
+    Stopping(CompletedSink), // Told to stop, the fallback goes out after STOP_GRACE
+    Answered,
+}
+
+impl Progress {
+    fn start(generation: u64, fallback: String, done_tx: CompletedSink) -> Arc<Progress> {
+        let progress = Arc::new(Progress {
+            generation,
+            started: Instant::now(),
+            fallback,
+            state: Mutex::new(Lifecycle::Searching(done_tx)),
+            currmove: Mutex::new(None),
+            hash_usage: Mutex::new(None),
+        });
+        let ticker = progress.clone();
+        thread::spawn(move || loop {
+            thread::sleep(PROGRESS_INTERVAL);
+            if !ticker.send_line() {
+                return;
+            }
+        });
+        progress
+    }
+
+    pub fn set_currmove(&self, chess_move: ChessMove, number: usize) {
+        *self.currmove.lock() = Some((chess_move, number));
+    }
+
+    fn set_hash_usage(&self, usage: Arc<HashUsage>) {
+        *self.hash_usage.lock() = Some(usage);
+    }
+
+    fn line(&self) -> String {
+        UciResponse::Info(InfoFields {
+            time: Some(self.started.elapsed()),
+            hashfull: self
+                .hash_usage
+                .lock()
+                .as_ref()
+                .map(|usage| usage.permille()),
+            currmove: *self.currmove.lock(),
+            ..Default::default()
+        })
+        .to_string()
+    }
+
+    // False once finished
+    fn send_line(&self) -> bool {
+        let state = self.state.lock(); // Held while sending, the bestmove waits for it
+        let (Lifecycle::Searching(done_tx) | Lifecycle::Stopping(done_tx)) = &*state else {
+            return false;
+        };
+        done_tx(SearchCompleted {
+            generation: self.generation,
+            output: self.line(),
+        });
+        true
+    }
+
+    fn answered(&self) -> bool {
+        matches!(*self.state.lock(), Lifecycle::Answered)
+    }
+
+    // Told to stop, or already answered
+    fn stopping(&self) -> bool {
+        !matches!(*self.state.lock(), Lifecycle::Searching(_))
+    }
+
+    // False when the search was already answered
+    fn answer(&self, output: String) -> bool {
+        let mut state = self.state.lock();
+        let (Lifecycle::Searching(done_tx) | Lifecycle::Stopping(done_tx)) =
+            std::mem::replace(&mut *state, Lifecycle::Answered)
+        else {
+            return false;
+        };
+        done_tx(SearchCompleted {
+            generation: self.generation,
+            output,
+        });
+        true
+    }
+
+    // The hard time limit. The engine only takes the soft one and ought to be done by then, this
+    // is for when it overruns.
+    fn stop_at(self: &Arc<Self>, hard_limit: Duration, stop: Sender<bool>) {
+        let progress = self.clone();
+        thread::spawn(move || {
+            thread::sleep(hard_limit.saturating_sub(progress.started.elapsed()));
+            if !progress.answered() {
+                warn!(
+                    "Search {} still going at its {}ms hard limit, stopping it",
+                    progress.generation,
+                    hard_limit.as_millis()
+                );
+                let _ = stop.send(true);
+                progress.answer_after_stop();
+            }
+        });
+    }
+
+    // Plays the fallback if the search still hasn't answered a STOP_GRACE after its stop. Only
+    // the first stop starts the wait.
+    fn answer_after_stop(self: &Arc<Self>) {
+        {
+            let mut state = self.state.lock();
+            let Lifecycle::Searching(done_tx) = &*state else {
+                return;
+            };
+            *state = Lifecycle::Stopping(done_tx.clone());
+        }
+        let progress = self.clone();
+        thread::spawn(move || {
+            thread::sleep(STOP_GRACE);
+            if progress.answer(progress.fallback.clone()) {
+                warn!(
+                    "Search {} didn't stop within {}ms, sent {}",
+                    progress.generation,
+                    STOP_GRACE.as_millis(),
+                    progress.fallback
+                );
+            }
+        });
+    }
+}
+
+// Why playing `chess_move` draws, if it does. Mate on the hundredth ply still wins.
+fn drawn_by(
+    board: &Board,
+    chess_move: ChessMove,
+    history: Option<&[u64]>,
+    halfmove_clock: u32,
+) -> Option<&'static str> {
+    let after = board.make_move_new(chess_move);
+    if let Some(history) = history {
+        let hash = after.get_hash();
+        if history.iter().filter(|seen| **seen == hash).count() >= 2 {
+            return Some("repeats the position a third time");
+        }
+    }
+    let clock = next_halfmove_clock(board, chess_move, halfmove_clock);
+    (clock >= 100 && after.status() != BoardStatus::Checkmate)
+        .then_some("reaches the fifty move rule")
+}
+
+// The first legal move allowed at the root, what a stuck search plays
+fn fallback_bestmove(request: &SearchRequest) -> String {
+    let board = &request.board;
+    let chess_move = match &request.root_moves {
+        Some(allowed) => allowed.iter().copied().find(|m| board.legal(*m)),
+        None => MoveGen::new_legal(board).next(),
+    };
+    let out = match chess_move {
+        Some(chess_move) => format!(
+            "{}\n{}",
+            UciResponse::info_string(format!(
+                "search didn't stop in time, playing {}",
+                chess_move
+            )),
+            UciResponse::bestmove(chess_move)
+        ),
+        // Nothing legal, the game's over anyway
+        None => UciResponse::BestMove {
+            best: None,
+            ponder: None,
+        }
+        .to_string(),
+    };
+    match request.chess960 {
+        true => bestmove_out(board, &out),
+        false => out,
+    }
+}
+
+// Long lived search thread owned by the session, searches run one after another in the order sent.
+// Its channels are std mpsc, not tokio: the stop channel and the cache's are the engine's own
+// types, and this module builds for wasm, where there's no tokio. Only what comes out goes through
+// the CompletedSink, which the stdio frontend points at a tokio channel.
+pub struct SearchWorker {
+    jobs: Sender<(SearchRequest, Sender<bool>)>, // With a sender for its stop channel
+    current_stop: Mutex<Option<Sender<bool>>>,   // Stop channel of the latest search
+    running: Arc<Mutex<Option<Arc<Progress>>>>,  // The search the worker thread is on
+    thread: JoinHandle<()>,
+    released: Sender<u64>, // Generation whose until_stop bestmove can go out
+    latest_generation: Mutex<u64>,
+}
+
+impl SearchWorker {
+    pub fn spawn() -> (SearchWorker, Receiver<SearchCompleted>) {
+        let (done_tx, done_rx) = mpsc::channel::<SearchCompleted>();
+        (SearchWorker::spawn_with(channel_sink(done_tx)), done_rx)
+    }
+
+    // For a frontend that wants the results some other way than a std channel
+    pub fn spawn_with(done_tx: CompletedSink) -> SearchWorker {
+        let (jobs, job_rx) = mpsc::channel::<(SearchRequest, Sender<bool>)>();
+        let (released, released_rx) = mpsc::channel::<u64>();
+        let running = Arc::new(Mutex::new(None));
+        let worker_running = running.clone();
+        let thread = thread::spawn(move || {
+            for (mut request, stop) in job_rx {
+                let generation = request.generation;
+                let until_stop = request.until_stop;
+                let fallback = fallback_bestmove(&request);
+                let progress = Progress::start(generation, fallback, done_tx.clone());
+                if let Some(hard_limit) = request.hard_limit {
+                    progress.stop_at(hard_limit, stop);
+                }
+                request.progress = Some(progress.clone());
+                *worker_running.lock() = Some(progress.clone());
+                request.run(|output| {
+                    // Held until its stop, or until a later search is sent
+                    if until_stop {
+                        while released_rx
+                            .recv()
+                            .is_ok_and(|released| released < generation)
+                        {}
+                    }
+                    progress.answer(output);
+                });
+                // Every way through run() answers, this is in case one stops doing so
+                if progress.answer(progress.fallback.clone()) {
+                    error!("Search {} ended without a bestmove", generation);
+                }
+            }
+        });
+
+        SearchWorker {
+            jobs,
+            current_stop: Mutex::new(None),
+            running,
+            thread,
+            released,
+            latest_generation: Mutex::new(0),
+        }
+    }
+
+    pub fn send(&self, message: WorkerMessage) {
+        match message {
+            WorkerMessage::Search(mut request) => {
+                // A go without a stop first. The search before it stops and answers, then this
+                // one runs, so the bestmoves come out one each and in order.
+                if self.searching() {
+                    warn!("go while still searching, stopping the last search first");
+                    self.send(WorkerMessage::Stop);
+                }
+                // Registered before the job is queued so a stop straight after go can't get lost
+                let (stop_tx, stop_rx) = mpsc::channel();
+                request.settings.stop_engine_rcv = Some(stop_rx);
+                *self.current_stop.lock() = Some(stop_tx.clone());
+                let previous =
+                    std::mem::replace(&mut *self.latest_generation.lock(), request.generation);
+                let _ = self.released.send(previous);
+                let _ = self.jobs.send((*request, stop_tx));
+            }
+            WorkerMessage::Stop => {
+                if let Some(stop) = self.current_stop.lock().as_ref() {
+                    let _ = stop.send(true); // Send a stop to engine
+                }
+                let latest = *self.latest_generation.lock();
+                let _ = self.released.send(latest);
+                // A search still queued behind another hears the stop as soon as it starts
+                if let Some(progress) = self.running_search(latest) {
+                    progress.answer_after_stop();
+                }
+            }
+            WorkerMessage::PonderHit(budget) => {
+                // The bestmove can go out as soon as the engine has one, and it gets stopped
+                // once the budget is used up
+                let latest = *self.latest_generation.lock();
+                let _ = self.released.send(latest);
+                if let Some(stop) = self.current_stop.lock().clone() {
+                    let running = self.running_search(latest);
+                    thread::spawn(move || {
+                        thread::sleep(budget);
+                        let _ = stop.send(true);
+                        if let Some(progress) = running {
+                            progress.answer_after_stop();
+                        }
+                    });
+                }
+            }
+        }
+    }
+
+    // For quit: stops the search, lets the thread run out of jobs and gives it `within` to finish.
+    // False if it's still going, the engine can't be made to stop.
+    pub fn shut_down(self, within: Duration) -> bool {
+        self.send(WorkerMessage::Stop);
+        let SearchWorker { jobs, thread, .. } = self;
+        drop(jobs);
+        join_within(thread, within)
+    }
+
+    // Stops the search and waits until its bestmove has gone to the sink, for an answer that
+    // doesn't go through the worker but has to come after it. False if that takes over `within`.
+    pub fn stop_and_wait(&self, within: Duration) -> bool {
+        self.send(WorkerMessage::Stop);
+        let deadline = Instant::now() + within;
+        while self.searching() {
+            if Instant::now() > deadline {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        true
+    }
+
+    // A search is running or queued, its bestmove isn't out yet
+    pub fn searching(&self) -> bool {
+        let latest = *self.latest_generation.lock();
+        match self.running.lock().as_ref() {
+            Some(progress) => progress.generation < latest || !progress.answered(),
+            None => latest > 0,
+        }
+    }
+
+    fn running_search(&self, generation: u64) -> Option<Arc<Progress>> {
+        self.running
+            .lock()
+            .clone()
+            .filter(|progress| progress.generation == generation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        backend::MockBackend,
+        session::{Reply, UciSession},
+    };
+    use std::{
+        str::FromStr,
+        time::{Duration, Instant},
+    };
+
+    // Always answers with the same move, whatever the board
+    struct FixedBackend(ChessMove);
+
+    impl SearchBackend for FixedBackend {
+        fn search(&self, _board: Board, _settings: EngineSettings) -> SearchOutcome {
+            SearchOutcome {
+                best_move: self.0,
+                score: None,
+                nodes: None,
+            }
+        }
+    }
+
+    fn fixed(m: &str) -> FixedBackend {
+        FixedBackend(ChessMove::from_str(m).unwrap())
+    }
+
+    fn go(session: &mut UciSession, fast: bool) -> SearchRequest {
+        session.fast_path = fast;
+        match session.handle("go wtime 600000 btime 600000") {
+            Some(Reply::Search(request)) => *request,
+            _ => panic!("go should hand back a search"),
+        }
+    }
+
+    fn mock_session() -> UciSession {
+        let mut session = UciSession::new(None);
+        session.backend = Arc::new(MockBackend);
+        session.handle("position startpos moves e2e4 e7e5");
+        session
+    }
+
+    // Counts the searches it's asked for
+    struct CountingBackend(Arc<Mutex<u32>>);
+
+    impl SearchBackend for CountingBackend {
+        fn search(&self, board: Board, settings: EngineSettings) -> SearchOutcome {
+            *self.0.lock() += 1;
+            MockBackend.search(board, settings)
+        }
+    }
+
+    #[test]
+    fn test_info_line() {
+        let outcome = SearchOutcome {
+            best_move: ChessMove::from_str("e2e4").unwrap(),
+            score: Some(Score::Centipawns(-35)),
+            nodes: None,
+        };
+        assert_eq!(info_line(&outcome, false), "info score cp -35 pv e2e4");
+        assert_eq!(
+            info_line(&outcome, true),
+            "info score cp -35 wdl 136 624 240 pv e2e4"
+        );
+    }
+
+    #[test]
+    fn test_ponder_move() {
+        let mut session = mock_session();
+        let mut out = String::new();
+        go(&mut session, false).run(|bestmove| out = bestmove);
+        assert_eq!(out.split_whitespace().count(), 2);
+
+        session.handle("setoption name Ponder value true");
+        go(&mut session, false).run(|bestmove| out = bestmove);
+        let words: Vec<&str> = out.split_whitespace().collect();
+        assert_eq!((words.len(), words[2]), (4, "ponder"));
+        let played = ChessMove::from_str(words[1]).unwrap();
+        let after = session.board.make_move_new(played);
+        assert!(after.legal(ChessMove::from_str(words[3]).unwrap()));
+
+        // The engine used all of the time and more, there's none left to guess with
+        session.backend = Arc::new(StuckBackend);
+        let request = match session.handle("go movetime 300") {
+            Some(Reply::Search(request)) => *request,
+            _ => panic!("go should hand back a search"),
+        };
+        request.run(|bestmove| out = bestmove);
+        assert_eq!(out.split_whitespace().count(), 2);
+    }
+
+    #[test]
+    fn test_third_repetition_scored_as_draw() {
+        let mut session = UciSession::new(None);
+        session.backend = Arc::new(fixed("f6g8"));
+        session.handle("setoption name UCI_ShowWDL value true");
+        session.handle("position startpos moves g1f3 g8f6 f3g1 f6g8 g1f3 g8f6 f3g1");
+        let mut out = String::new();
+        go(&mut session, false).run(|bestmove| out = bestmove);
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "info score cp 0 wdl 182 636 182 pv f6g8",
+                "info string f6g8 repeats the position a third time, a draw",
+                "bestmove f6g8"
+            ]
+        );
+
+        // Twice is fine
+        session.handle("position startpos moves g1f3 g8f6 f3g1");
+        go(&mut session, false).run(|bestmove| out = bestmove);
+        assert_eq!(out, "bestmove f6g8");
+    }
+
+    #[test]
+    fn test_fifty_move_rule_scored_as_draw() {
+        let mut session = UciSession::new(None);
+        session.backend = Arc::new(fixed("a1a2"));
+        session.handle("position fen 4k3/8/8/8/8/8/8/R3K3 w - - 97 80 moves e1d1 e8d8");
+        let mut out = String::new();
+        go(&mut session, false).run(|bestmove| out = bestmove);
+        assert_eq!(
+            out,
+            "info string a1a2 reaches the fifty move rule, a draw\nbestmove a1a2"
+        );
+
+        // A pawn move or capture starts the count again
+        session.handle("position fen 4k3/p7/8/8/8/8/8/R3K3 b - - 98 80 moves a7a6 e1d1");
+        go(&mut session, false).run(|bestmove| out = bestmove);
+        assert_eq!(out, "bestmove a1a2");
+    }
+
+    #[test]
+    fn test_threads_start_helpers() {
+        let searches = Arc::new(Mutex::new(0));
+        let mut session = UciSession::new(Some(spawn_cache_manager()));
+        session.backend = Arc::new(CountingBackend(searches.clone()));
+        session.handle("setoption name Threads value 3");
+        go(&mut session, false).run(|out| assert!(out.starts_with("bestmove ")));
+
+        let started = Instant::now();
+        while *searches.lock() < 3 && started.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(*searches.lock(), 3);
+
+        // The fast path searches alone
+        go(&mut session, true).run(|_| {});
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(*searches.lock(), 4);
+    }
+
+    #[test]
+    fn test_fast_path_same_move() {
+        let mut session = mock_session();
+
+        let mut normal = String::new();
+        go(&mut session, false).run(|out| normal = out);
+
+        let (worker, completed) = SearchWorker::spawn();
+        let request = go(&mut session, true);
+        assert!(request.fast);
+        worker.send(WorkerMessage::Search(Box::new(request)));
+
+        let completed = completed.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(completed.output, normal);
+    }
+
+    // Takes its time, so the worker has something to report
+    struct SlowBackend;
+
+    impl SearchBackend for SlowBackend {
+        fn search(&self, board: Board, settings: EngineSettings) -> SearchOutcome {
+            thread::sleep(PROGRESS_INTERVAL + Duration::from_millis(300));
+            MockBackend.search(board, settings)
+        }
+    }
+
+    // Doesn't listen for the stop
+    struct StuckBackend;
+
+    impl SearchBackend for StuckBackend {
+        fn search(&self, board: Board, settings: EngineSettings) -> SearchOutcome {
+            thread::sleep(Duration::from_millis(800));
+            MockBackend.search(board, settings)
+        }
+    }
+
+    #[test]
+    fn test_stop_answers_in_time() {
+        let mut session = mock_session();
+        session.backend = Arc::new(StuckBackend);
+        let (worker, completed) = SearchWorker::spawn();
+        let request = go(&mut session, false);
+        worker.send(WorkerMessage::Search(Box::new(request)));
+        thread::sleep(Duration::from_millis(50));
+
+        let stopped = Instant::now();
+        worker.send(WorkerMessage::Stop);
+        let done = completed.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(stopped.elapsed() < Duration::from_millis(500));
+        assert!(done
+            .output
+            .starts_with("info string search didn't stop in time"));
+        let played = done.output.lines().last().unwrap();
+        assert_eq!(
+            played,
+            format!(
+                "bestmove {}",
+                MoveGen::new_legal(&session.board).next().unwrap()
+            )
+        );
+        // The engine's own bestmove doesn't follow it
+        assert!(completed.recv_timeout(Duration::from_secs(2)).is_err());
+    }
+
+    #[test]
+    fn test_one_bestmove_per_search() {
+        let (done_tx, done_rx) = mpsc::channel();
+        let progress = Progress::start(1, "bestmove a2a3".to_string(), channel_sink(done_tx));
+        progress.answer_after_stop();
+        progress.answer_after_stop();
+        assert!(progress.answer("bestmove e2e4".to_string()));
+        assert!(!progress.answer("bestmove d2d4".to_string()));
+        thread::sleep(STOP_GRACE * 2); // The fallbacks don't get out either
+        let sent: Vec<String> = done_rx.try_iter().map(|done| done.output).collect();
+        assert_eq!(sent, ["bestmove e2e4"]);
+        assert!(!progress.send_line());
+    }
+
+    #[test]
+    fn test_hard_limit_stops_the_engine() {
+        let mut session = mock_session();
+        session.backend = Arc::new(StuckBackend);
+        let mut request = go(&mut session, false);
+        assert_eq!(request.hard_limit, Some(request.settings.time_limit * 2));
+        request.hard_limit = Some(Duration::from_millis(100));
+
+        let (worker, completed) = SearchWorker::spawn();
+        let started = Instant::now();
+        worker.send(WorkerMessage::Search(Box::new(request)));
+        let done = completed.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(started.elapsed() < Duration::from_millis(500));
+        assert!(done.output.contains("bestmove"));
+        assert!(completed.recv_timeout(Duration::from_secs(2)).is_err());
+
+        session.handle("position startpos");
+        match session.handle("go infinite") {
+            Some(Reply::Search(request)) => assert_eq!(request.hard_limit, None),
+            _ => panic!("go should hand back a search"),
+        }
+    }
+
+    #[test]
+    fn test_second_go_stops_the_first() {
+        let mut session = mock_session();
+        session.backend = Arc::new(StuckBackend);
+        let (worker, completed) = SearchWorker::spawn();
+        let first = go(&mut session, false);
+        worker.send(WorkerMessage::Search(Box::new(first)));
+        thread::sleep(Duration::from_millis(50));
+        let second = go(&mut session, false);
+        worker.send(WorkerMessage::Search(Box::new(second)));
+
+        let bestmoves: Vec<u64> = completed
+            .iter()
+            .filter(|done| done.output.contains("bestmove"))
+            .take(2)
+            .map(|done| done.generation)
+            .collect();
+        assert_eq!(bestmoves, [1, 2]);
+        assert!(completed.recv_timeout(Duration::from_secs(2)).is_err());
+    }
+
+    #[test]
+    fn test_isready_while_searching() {
+        let mut session = mock_session();
+        session.backend = Arc::new(StuckBackend);
+        let (worker, completed) = SearchWorker::spawn();
+        session.worker = Some(worker);
+        session.handle("go wtime 600000 btime 600000");
+        assert!(session.worker.as_ref().unwrap().searching());
+        session.handle("setoption name Startup Warmup value true");
+
+        // No warm-up search next to the real one, readyok straight away
+        assert!(matches!(
+            session.handle("isready"),
+            Some(Reply::Output(out)) if out == "readyok"
+        ));
+        assert!(session.warmed_up);
+        let done = completed.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(done.output.starts_with("bestmove "));
+        assert!(!session.worker.as_ref().unwrap().searching());
+    }
+
+    #[test]
+    fn test_shut_down_mid_search() {
+        let mut session = mock_session();
+        session.backend = Arc::new(SlowBackend);
+        let (worker, completed) = SearchWorker::spawn();
+        worker.send(WorkerMessage::Search(Box::new(go(&mut session, false))));
+        // SlowBackend doesn't hear the stop either, so it gets long enough to finish its sleep
+        assert!(worker.shut_down(Duration::from_secs(5)));
+        let outputs: Vec<String> = completed.iter().map(|done| done.output).collect();
+        assert_eq!(
+            outputs.last().map(|out| out.contains("bestmove ")),
+            Some(true)
+        );
+
+        let (worker, _completed) = SearchWorker::spawn();
+        worker.send(WorkerMessage::Search(Box::new(go(&mut session, false))));
+        assert!(!worker.shut_down(Duration::from_millis(50)));
+    }
+
+    #[test]
+    fn test_progress_while_searching() {
+        let mut session = mock_session();
+        session.backend = Arc::new(SlowBackend);
+        let (worker, completed) = SearchWorker::spawn();
+        let request = go(&mut session, false);
+        let generation = request.generation;
+        worker.send(WorkerMessage::Search(Box::new(request)));
+
+        let progress = completed.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(progress.generation, generation);
+        let elapsed: u64 = progress
+            .output
+            .strip_prefix("info time ")
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(elapsed >= PROGRESS_INTERVAL.as_millis() as u64);
+        let done = completed.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(done.output.starts_with("bestmove "));
+        // Nothing after the bestmove
+        assert!(completed.recv_timeout(PROGRESS_INTERVAL * 2).is_err());
+    }
+
+    #[test]
+    fn test_progress_currmove() {
+        let (done_tx, done_rx) = mpsc::channel();
+        let progress = Progress::start(7, "bestmove a2a3".to_string(), channel_sink(done_tx));
+        assert!(progress.line().starts_with("info time "));
+        progress.set_currmove(ChessMove::from_str("d1d8").unwrap(), 2);
+        assert!(progress.line().ends_with(" currmove d1d8 currmovenumber 2"));
+        assert!(progress.answer("bestmove d1d8".to_string()));
+        assert!(!progress.answer("bestmove e2e4".to_string()));
+        assert!(!progress.send_line());
+        assert_eq!(done_rx.try_recv().unwrap().output, "bestmove d1d8");
+        assert!(done_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_hashfull_estimate() {
+        let usage = HashUsage::new(1);
+        assert_eq!(usage.permille(), 0);
+        usage
+            .writes
+            .store(1024 * 1024 / ESTIMATED_ENTRY_BYTES / 4, Ordering::SeqCst);
+        assert_eq!(usage.permille(), 250);
+        usage.writes.store(u32::MAX as u64, Ordering::SeqCst);
+        assert_eq!(usage.permille(), 1000);
+        usage.reset(16);
+        assert_eq!(usage.permille(), 0);
+    }
+
+    #[test]
+    fn test_worker_completes_in_order() {
+        let mut session = mock_session();
+        let (worker, completed) = SearchWorker::spawn();
+
+        let first = go(&mut session, false);
+        let second = go(&mut session, false);
+        let generations = [first.generation, second.generation];
+        assert!(generations[0] < generations[1]);
+
+        worker.send(WorkerMessage::Search(Box::new(first)));
+        worker.send(WorkerMessage::Stop);
+        worker.send(WorkerMessage::Search(Box::new(second)));
+        for generation in generations {
+            let done = completed.recv_timeout(Duration::from_secs(5)).unwrap();
+            assert_eq!(done.generation, generation);
+            assert!(done.output.starts_with("bestmove "));
+        }
+    }
+
+    #[test]
+    fn test_worker_sends_to_its_sink() {
+        let mut session = mock_session();
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let sink = sent.clone();
+        let worker = SearchWorker::spawn_with(Arc::new(move |done: SearchCompleted| {
+            sink.lock().push(done.output)
+        }));
+        worker.send(WorkerMessage::Search(Box::new(go(&mut session, false))));
+        assert!(worker.shut_down(Duration::from_secs(5)));
+        let sent = sent.lock();
+        assert_eq!(sent.len(), 1);
+        assert!(sent[0].starts_with("bestmove "));
+    }
+
+    #[test]
+    fn test_lazy_cache_built_once() {
+        let lazy = Arc::new(LazyCache::default());
+        assert!(!lazy.started() && !lazy.is_initialized());
+
+        lazy.kick_off();
+        let racing = lazy.clone();
+        let other = thread::spawn(move || racing.get().cache_ref.clone());
+        let cache_ref = lazy.get().cache_ref.clone();
+        assert!(Arc::ptr_eq(&cache_ref, &other.join().unwrap()));
+        assert!(lazy.is_initialized());
+        assert!(lazy.take_manager().is_some());
+        assert!(lazy.take_manager().is_none());
+    }
+
+    #[test]
+    fn test_infinite_waits_for_stop() {
+        let mut session = mock_session();
+        let (worker, completed) = SearchWorker::spawn();
+        let mut request = go(&mut session, false);
+        request.until_stop = true;
+        worker.send(WorkerMessage::Search(Box::new(request)));
+
+        // The mock is done straight away, but nothing comes out before the stop
+        assert!(completed.recv_timeout(Duration::from_millis(200)).is_err());
+        worker.send(WorkerMessage::Stop);
+        let done = completed.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(done.output.starts_with("bestmove "));
+    }
+
+    #[test]
+    fn test_ponder_until_ponderhit() {
+        let mut session = mock_session();
+        let (worker, completed) = SearchWorker::spawn();
+        session.worker = Some(worker);
+        session.handle("setoption name Ponder value true");
+        assert!(session
+            .handle("go ponder wtime 600000 btime 600000")
+            .is_none());
+        assert!(completed.recv_timeout(Duration::from_millis(200)).is_err());
+        assert!(session.ponder_budget.is_some());
+
+        session.handle("ponderhit");
+        let done = completed.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(done.output.starts_with("bestmove "));
+        assert_eq!(session.ponder_budget, None);
+
+        // A stop ends it the same way
+        session.handle("go ponder wtime 600000 btime 600000");
+        assert!(completed.recv_timeout(Duration::from_millis(200)).is_err());
+        session.handle("stop");
+        assert!(completed.recv_timeout(Duration::from_secs(5)).is_ok());
+    }
+
+    #[test]
+    fn test_illegal_move_substituted() {
+        // e2 is empty after 1. e4 e5
+        let board = mock_session().board;
+        let (out, _) = run_engine(
+            &fixed("e2e4"),
+            board,
+            EngineSettings::default(),
+            true,
+            None,
+            None,
+            None,
+        );
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("info string engine move e2e4 rejected (illegal"));
+        let played = ChessMove::from_str(lines[1].strip_prefix("bestmove ").unwrap()).unwrap();
+        assert!(board.legal(played));
+    }
+
+    #[test]
+    fn test_restricted_root_searches_each_move() {
+        // The mock always plays the first legal move, the rook taking the queen is the better one
+        let board = Board::from_str("4k3/8/8/3q4/8/8/3R4/4K3 w - - 0 1").unwrap();
+        let allowed = [
+            ChessMove::from_str("e1f1").unwrap(),
+            ChessMove::from_str("d2d5").unwrap(),
+        ];
+        let (out, outcome) = run_engine(
+            &MockBackend,
+            board,
+            EngineSettings::default(),
+            true,
+            None,
+            Some(&allowed),
+            None,
+        );
+        assert_eq!(out, "bestmove d2d5"); // Not a substitute, nothing was rejected
+        assert_eq!(outcome.best_move, allowed[1]);
+    }
+
+    #[test]
+    fn test_promotion_field_checked() {
+        let board = Board::from_str("8/4P3/8/8/8/8/k7/4K3 w - - 0 1").unwrap();
+        let (out, _) = run_engine(
+            &fixed("e7e8"),
+            board,
+            EngineSettings::default(),
+            true,
+            None,
+            None,
+            None,
+        );
+        assert!(out.contains("(promotion missing)"));
+
+        let (out, _) = run_engine(
+            &fixed("e7e8q"),
+            board,
+            EngineSettings::default(),
+            true,
+            None,
+            None,
+            None,
+        );
+        assert_eq!(out, "bestmove e7e8q");
+
+        let (out, _) = run_engine(
+            &fixed("e1d1q"),
+            board,
+            EngineSettings::default(),
+            true,
+            None,
+            None,
+            None,
+        );
+        assert!(out.contains("(promotion on a non promoting move)"));
+    }
+
+    // cargo test -- --ignored test_fast_path_overhead --nocapture
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore]
+    async fn test_fast_path_overhead() {
+        const MOVES: u32 = 500;
+        let mut session = mock_session();
+
+        let start = Instant::now();
+        for _ in 0..MOVES {
+            let request = go(&mut session, false);
+            let (tx, rx) = mpsc::channel();
+            tokio::task::spawn(async move { request.run(|out| tx.send(out).unwrap()) });
+            rx.recv().unwrap();
+        }
+        let normal = start.elapsed() / MOVES;
+
+        let (worker, completed) = SearchWorker::spawn();
+        let start = Instant::now();
+        for _ in 0..MOVES {
+            worker.send(WorkerMessage::Search(Box::new(go(&mut session, true))));
+            completed.recv().unwrap();
+        }
+        let fast = start.elapsed() / MOVES;
+
+        println!(
+            "per move overhead: normal {:?}, fast path {:?}",
+            normal, fast
+        );
+        assert!(fast <= normal);
+    }
+}
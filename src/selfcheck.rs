@@ -0,0 +1,125 @@
+// --selfcheck: a quick health check after deploying a build. Move generation, two short
+// searches against a deadline and a search that goes through the cache, without touching stdin.
+use chess::{Board, MoveGen};
+use shallow_red_engine::{
+    managers::cache_manager::CacheInputGrouping, utils::engine_interface::EngineSettings,
+};
+use std::{str::FromStr, sync::mpsc, sync::Arc, thread, time::Duration};
+
+use crate::backend::SearchBackend;
+
+const KIWIPETE: &str = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+
+// Position, depth and the node count every correct move generator gets
+const PERFT: [(&str, u32, u64); 2] = [
+    (
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        3,
+        8902,
+    ),
+    (KIWIPETE, 2, 2039),
+];
+
+const SEARCH_POSITIONS: [&str; 2] = [
+    "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+    "r3r1k1/ppp3pp/4p3/1P6/4p3/b3P3/qBQ2PPP/3R1RK1 w - - 0 1",
+];
+
+pub fn perft(board: &Board, depth: u32) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+    MoveGen::new_legal(board)
+        .map(|chess_move| perft(&board.make_move_new(chess_move), depth - 1))
+        .sum()
+}
+
+// Lines describing each step that passed, or what failed first
+pub fn selfcheck(
+    backend: Arc<dyn SearchBackend>,
+    cache: Option<CacheInputGrouping>,
+    search_time: Duration,
+    deadline: Duration,
+) -> Result<Vec<String>, String> {
+    let mut passed = Vec::new();
+    for (fen, depth, expected) in PERFT {
+        let nodes = perft(&Board::from_str(fen).expect("Built in FEN"), depth);
+        if nodes != expected {
+            return Err(format!(
+                "perft {} depth {}: expected {} got {}",
+                fen, depth, expected, nodes
+            ));
+        }
+        passed.push(format!("perft depth {} {} nodes", depth, nodes));
+    }
+
+    for fen in SEARCH_POSITIONS {
+        let chess_move = timed_search(&backend, fen, None, search_time, deadline)?;
+        passed.push(format!("search {} bestmove {}", fen, chess_move));
+    }
+
+    if let Some(cache) = cache {
+        if cache.cache_ref.try_read_for(deadline).is_none() {
+            return Err("cache lock still held after the deadline".to_string());
+        }
+        // Once to fill the table through the manager thread, then again reading it back
+        for _ in 0..2 {
+            timed_search(
+                &backend,
+                SEARCH_POSITIONS[0],
+                Some(cache.clone()),
+                search_time,
+                deadline,
+            )?;
+        }
+        passed.push("cache round trip".to_string());
+    }
+    Ok(passed)
+}
+
+fn timed_search(
+    backend: &Arc<dyn SearchBackend>,
+    fen: &str,
+    cache: Option<CacheInputGrouping>,
+    search_time: Duration,
+    deadline: Duration,
+) -> Result<String, String> {
+    let board = Board::from_str(fen).expect("Built in FEN");
+    let settings = EngineSettings {
+        verbose: false,
+        time_limit: search_time,
+        cache_settings: cache,
+        ..Default::default()
+    };
+    let (tx, rx) = mpsc::channel();
+    let search_backend = backend.clone();
+    thread::spawn(move || {
+        let _ = tx.send(search_backend.search(board, settings).best_move);
+    });
+
+    match rx.recv_timeout(deadline) {
+        Ok(chess_move) if board.legal(chess_move) => Ok(chess_move.to_string()),
+        Ok(chess_move) => Err(format!(
+            "search {} returned illegal move {}",
+            fen, chess_move
+        )),
+        Err(mpsc::RecvTimeoutError::Timeout) => Err(format!(
+            "search {} gave no bestmove within {}ms",
+            fen,
+            deadline.as_millis()
+        )),
+        Err(mpsc::RecvTimeoutError::Disconnected) => Err(format!("search {} panicked", fen)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_perft() {
+        let kiwipete = Board::from_str(KIWIPETE).unwrap();
+        assert_eq!(perft(&kiwipete, 1), 48);
+        assert_eq!(perft(&Board::default(), 2), 400);
+    }
+}
@@ -0,0 +1,337 @@
+// --match: the engine against itself, for generating varied positions. Each game opens with a
+// few uniformly random legal moves drawn from a per-game seed, drops openings the engine
+// already thinks are lopsided, and is played out from there. The seed and the random prefix go
+// into the PGN tags so any game can be replayed.
+use chess::{Board, BoardStatus, ChessMove, Color, MoveGen};
+use log::{info, warn};
+use shallow_red_engine::utils::engine_interface::EngineSettings;
+use std::time::Duration;
+
+use crate::{
+    backend::SearchBackend,
+    display::Score,
+    game::{GameRecord, GameResult, ENGINE_NAME},
+    rng::SessionRng,
+    search::checked_move,
+    training::TrainingExport,
+};
+
+const MAX_OPENING_ATTEMPTS: u32 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MatchSettings {
+    pub games: u32,
+    pub random_plies: u32,
+    pub max_opening_cp: i32, // Openings evaluated outside ±this are drawn again
+    pub movetime: Duration,
+    pub max_plies: u32, // Games still going after this many plies are left unfinished
+}
+
+impl Default for MatchSettings {
+    fn default() -> Self {
+        MatchSettings {
+            games: 1,
+            random_plies: 0,
+            max_opening_cp: 300,
+            movetime: Duration::from_millis(100),
+            max_plies: 300,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MatchGame {
+    pub record: GameRecord,
+    pub seed: u64,
+    pub opening: Vec<ChessMove>, // The random prefix, also the first moves of the record
+    pub rejected: u32,           // Openings thrown away before this one
+}
+
+impl MatchGame {
+    pub fn metadata(&self) -> String {
+        format!(
+            "seed {} random plies {} opening {} rejected {} result {}",
+            self.seed,
+            self.opening.len(),
+            moves_text(&self.opening),
+            self.rejected,
+            self.record.result.unwrap_or(GameResult::Unfinished)
+        )
+    }
+}
+
+fn moves_text(moves: &[ChessMove]) -> String {
+    match moves.is_empty() {
+        true => "-".to_string(),
+        false => moves
+            .iter()
+            .map(ChessMove::to_string)
+            .collect::<Vec<String>>()
+            .join(" "),
+    }
+}
+
+// None when the random moves run into a finished game
+pub fn random_opening(plies: u32, rng: &mut SessionRng) -> Option<Vec<ChessMove>> {
+    let mut board = Board::default();
+    let mut moves = Vec::new();
+    for _ in 0..plies {
+        let legal: Vec<ChessMove> = MoveGen::new_legal(&board).collect();
+        if legal.is_empty() {
+            return None;
+        }
+        let chess_move = legal[rng.below(legal.len() as u64) as usize];
+        board = board.make_move_new(chess_move);
+        moves.push(chess_move);
+    }
+    match board.status() {
+        BoardStatus::Ongoing => Some(moves),
+        _ => None,
+    }
+}
+
+fn search(
+    backend: &dyn SearchBackend,
+    board: Board,
+    movetime: Duration,
+) -> (ChessMove, Option<Score>) {
+    let outcome = backend.search(
+        board,
+        EngineSettings {
+            verbose: false,
+            time_limit: movetime,
+            ..Default::default()
+        },
+    );
+    (outcome.best_move, outcome.score)
+}
+
+// Backends that can't score a position can't reject anything
+fn balanced(score: Option<Score>, max_cp: i32) -> bool {
+    match score {
+        Some(Score::Centipawns(cp)) => cp.abs() <= max_cp,
+        Some(Score::Mate(_)) => false,
+        None => true,
+    }
+}
+
+pub fn pick_opening(
+    backend: &dyn SearchBackend,
+    settings: &MatchSettings,
+    rng: &mut SessionRng,
+) -> Result<(Vec<ChessMove>, u32), String> {
+    if settings.random_plies == 0 {
+        return Ok((Vec::new(), 0));
+    }
+    for rejected in 0..MAX_OPENING_ATTEMPTS {
+        let Some(opening) = random_opening(settings.random_plies, rng) else {
+            continue;
+        };
+        let board = opening
+            .iter()
+            .fold(Board::default(), |board, m| board.make_move_new(*m));
+        let (_, score) = search(backend, board, settings.movetime);
+        if balanced(score, settings.max_opening_cp) {
+            return Ok((opening, rejected));
+        }
+        info!("Rejected opening {} at {:?}", moves_text(&opening), score);
+    }
+    Err(format!(
+        "no opening within ±{}cp after {} attempts",
+        settings.max_opening_cp, MAX_OPENING_ATTEMPTS
+    ))
+}
+
+pub fn play_game(
+    backend: &dyn SearchBackend,
+    settings: &MatchSettings,
+    seed: u64,
+    round: u32,
+    training: Option<&TrainingExport>,
+) -> Result<MatchGame, String> {
+    let mut rng = SessionRng::seeded(seed);
+    let (opening, rejected) = pick_opening(backend, settings, &mut rng)?;
+
+    let mut record = GameRecord {
+        our_color: Some(Color::White),
+        opponent: Some(ENGINE_NAME.to_string()),
+        moves: opening.clone(),
+        ..Default::default()
+    };
+    record.tags = vec![
+        ("Event".to_string(), "shallow-red self-play".to_string()),
+        ("Round".to_string(), round.to_string()),
+        ("Seed".to_string(), seed.to_string()),
+        ("RandomPlies".to_string(), opening.len().to_string()),
+        ("RandomOpening".to_string(), moves_text(&opening)),
+    ];
+
+    let mut board = opening
+        .iter()
+        .fold(Board::default(), |board, m| board.make_move_new(*m));
+    while record.moves.len() < (opening.len() + settings.max_plies as usize) {
+        if board.status() != BoardStatus::Ongoing {
+            break;
+        }
+        let (engine_move, score) = search(backend, board, settings.movetime);
+        let (chess_move, substituted) = match checked_move(&board, engine_move, None) {
+            Ok(chess_move) => (chess_move, false),
+            Err((fallback, problem)) => {
+                warn!(
+                    "Engine returned {} on {}: {}, playing {} instead",
+                    engine_move, board, problem, fallback
+                );
+                (fallback, true)
+            }
+        };
+        if let Some(training) = training {
+            let ply = record.moves.len() as u32;
+            training.after_search(&board, ply, chess_move, score, substituted);
+        }
+        board = board.make_move_new(chess_move);
+        record.moves.push(chess_move);
+    }
+
+    let (result, comment) = match board.status() {
+        BoardStatus::Checkmate if board.side_to_move() == Color::White => {
+            (GameResult::BlackWins, "Black mates")
+        }
+        BoardStatus::Checkmate => (GameResult::WhiteWins, "White mates"),
+        BoardStatus::Stalemate => (GameResult::Draw, "Stalemate"),
+        BoardStatus::Ongoing => (GameResult::Unfinished, "Ply limit reached"),
+    };
+    record.result = Some(result);
+    record.comment = Some(comment.to_string());
+    if let Some(training) = training {
+        training.finish_game(Some(result));
+    }
+    Ok(MatchGame {
+        record,
+        seed,
+        opening,
+        rejected,
+    })
+}
+
+// Game seeds come from the match rng, so one match seed reproduces every game
+pub fn run_match(
+    backend: &dyn SearchBackend,
+    settings: &MatchSettings,
+    rng: &mut SessionRng,
+    training: Option<&TrainingExport>,
+    mut on_game: impl FnMut(&MatchGame),
+) -> Result<Vec<MatchGame>, String> {
+    let mut games = Vec::new();
+    for round in 1..=settings.games {
+        let game = play_game(backend, settings, rng.next_u64(), round, training)?;
+        info!("Match game {}: {}", round, game.metadata());
+        on_game(&game);
+        games.push(game);
+    }
+    Ok(games)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{MockBackend, SearchOutcome};
+    use parking_lot::Mutex;
+
+    // First legal move, with opening evals handed out in order
+    struct ScriptedEvals(Mutex<Vec<i32>>);
+
+    impl SearchBackend for ScriptedEvals {
+        fn search(&self, board: Board, _settings: EngineSettings) -> SearchOutcome {
+            let mut evals = self.0.lock();
+            SearchOutcome {
+                best_move: MoveGen::new_legal(&board).next().unwrap(),
+                score: match evals.is_empty() {
+                    true => None,
+                    false => Some(Score::Centipawns(evals.remove(0))),
+                },
+                nodes: None,
+            }
+        }
+    }
+
+    fn settings(random_plies: u32) -> MatchSettings {
+        MatchSettings {
+            random_plies,
+            movetime: Duration::from_millis(1),
+            max_plies: 10,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_random_opening_is_legal() {
+        let mut rng = SessionRng::seeded(3);
+        for _ in 0..20 {
+            let opening = random_opening(8, &mut rng).expect("8 plies can't end a game");
+            assert_eq!(opening.len(), 8);
+            let mut board = Board::default();
+            for chess_move in opening {
+                assert!(board.legal(chess_move));
+                board = board.make_move_new(chess_move);
+            }
+        }
+    }
+
+    #[test]
+    fn test_rejects_lopsided_openings() {
+        let backend = ScriptedEvals(Mutex::new(vec![450, -301, 120]));
+        let (opening, rejected) =
+            pick_opening(&backend, &settings(6), &mut SessionRng::seeded(5)).unwrap();
+        assert_eq!(rejected, 2);
+        assert_eq!(opening.len(), 6);
+
+        let always_winning = ScriptedEvals(Mutex::new(vec![1000; MAX_OPENING_ATTEMPTS as usize]));
+        assert!(pick_opening(&always_winning, &settings(6), &mut SessionRng::seeded(5)).is_err());
+    }
+
+    #[test]
+    fn test_seed_reproduces_games() {
+        let play = |seed| {
+            let mut rng = SessionRng::seeded(seed);
+            run_match(
+                &MockBackend,
+                &MatchSettings {
+                    games: 2,
+                    ..settings(8)
+                },
+                &mut rng,
+                None,
+                |_| {},
+            )
+            .unwrap()
+        };
+        let (first, again, other) = (play(11), play(11), play(12));
+        for (a, b) in first.iter().zip(&again) {
+            assert_eq!(a.seed, b.seed);
+            assert_eq!(a.opening, b.opening);
+            assert_eq!(a.record.moves, b.record.moves);
+        }
+        assert_ne!(first[0].opening, other[0].opening);
+        // Replaying a single game from its own seed gives the same opening
+        let replay = play_game(&MockBackend, &settings(8), first[1].seed, 2, None).unwrap();
+        assert_eq!(replay.opening, first[1].opening);
+    }
+
+    #[test]
+    fn test_prefix_in_pgn() {
+        let game = play_game(&MockBackend, &settings(4), 9, 1, None).unwrap();
+        assert_eq!(game.record.moves[..4], game.opening[..]);
+        assert!(game.record.moves.len() <= 14); // 4 random plies then at most the 10 ply cap
+        let pgn = game.record.to_pgn();
+        assert!(pgn.starts_with("[Event \"shallow-red self-play\"]\n"));
+        assert!(pgn.contains("\n[Seed \"9\"]\n[RandomPlies \"4\"]\n"));
+        assert!(pgn.contains(&format!(
+            "[RandomOpening \"{}\"]",
+            moves_text(&game.opening)
+        )));
+        assert!(pgn.contains("[White \"shallow-red\"]\n[Black \"shallow-red\"]"));
+        assert!(game
+            .metadata()
+            .starts_with("seed 9 random plies 4 opening "));
+    }
+}
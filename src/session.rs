@@ -0,0 +1,2312 @@
+use chess::{Board, BoardStatus, ChessMove, MoveGen};
+use log::{info, warn};
+use shallow_red_engine::{
+    managers::cache_manager::{Cache, CacheInputGrouping},
+    utils::engine_interface::EngineSettings,
+};
+#[cfg(not(target_arch = "wasm32"))]
+use std::thread::{self, JoinHandle};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::tablebase::SyzygyTables;
+use crate::{
+    backend::{SearchBackend, ShallowRed},
+    book::SelectionPolicy,
+    capture::EpdCapture,
+    chess960,
+    command::{parse_command, GoParams, ParseError, PositionStart, UciCommand},
+    crash::{self, CrashContext},
+    game::{CareerStats, GameRecord, GameResult},
+    handicap,
+    options::{self, OptionKind, OPTIONS},
+    polyglot::{default_book, PolyglotBook},
+    ponder::{PonderTracker, PredictionSource},
+    profiles::{load_profiles, match_profile, plan, Opponent, Profile},
+    repertoire::{Repertoire, RepertoireTracker},
+    response::UciResponse,
+    rng::SessionRng,
+    search::{
+        deferred_cache, spawn_cache_manager, HashUsage, LazyCache, SearchRequest, SearchWorker,
+        WorkerMessage,
+    },
+    symtest::{load_positions, symtest},
+    tablebase::{root_move, tbprobe, NoTablebases, Prober},
+    timecontrol::{
+        stability_budget, time_manager, within_clock, Classic, ScoreLog, TimeContext, TimeManager,
+        DEFAULT_MAX_MOVE_SHARE, DEFAULT_MIN_THINKING_MS, DEFAULT_SAFETY_BUFFER_MS,
+        DEFAULT_SLOW_MOVER,
+    },
+    timeusage::TimeUsage,
+    training::TrainingExport,
+    version::VERSION,
+};
+
+// Game state shared by every frontend (the stdin loop and the wasm exports)
+pub struct UciSession {
+    pub board: Board,
+    pub cache: Option<CacheInputGrouping>,
+    pub lazy_cache: Option<Arc<LazyCache>>, // Fills in `cache` on first use when set
+    pub backend: Arc<dyn SearchBackend>,
+    pub rng: SessionRng, // Every random choice (book picks etc.) draws from here
+    pub warmup: bool,    // Startup Warmup option
+    pub warmed_up: bool,
+    #[cfg(not(target_arch = "wasm32"))]
+    pub warming: Option<JoinHandle<()>>, // Warm-up search still going, isready waits for it
+    pub fast_path: bool, // Bullet Fast Path option, forces the fast path for every move
+    pub worker: Option<SearchWorker>, // Set by frontends that have threads, searches run here
+    pub generation: u64,
+    pub repertoire: Option<Repertoire>, // Repertoire File option, played instead of searching while in a line
+    pub repertoire_line: RepertoireTracker,
+    pub capture: Option<Arc<EpdCapture>>, // Capture File option
+    pub prober: Arc<dyn Prober>,
+    pub own_book: bool,
+    pub book_file: Option<PolyglotBook>, // BookFile option, takes precedence over the built-in book
+    pub book_selection: SelectionPolicy,
+    pub config_path: Option<PathBuf>, // shallowred.toml, where Persist Options writes to
+    pub persist_options: bool,
+    pub option_defaults: HashMap<&'static str, String>, // Persisted values advertised in place of the built in defaults
+    pub option_values: HashMap<&'static str, String>, // Current value of every option that has been set
+    pub gui_options: HashSet<String>, // Options the GUI set itself, profiles leave these alone
+    pub profiles: Vec<Profile>,
+    pub opponent: Option<Opponent>, // Announced through UCI_Opponent since the last ucinewgame
+    pub active_profile: Option<ActiveProfile>,
+    pub game: GameRecord, // Finished off by a result command
+    pub last_position: Option<(PositionStart, Vec<String>, Board)>, // Last position command and its board
+    pub pgn_file: Option<PathBuf>, // PGN File option, finished games are appended here
+    pub career_file: Option<PathBuf>, // Career File option, win/draw/loss totals
+    pub ponder: PonderTracker,
+    pub last_move_source: PredictionSource, // Where our last bestmove came from, the GUI ponders on its reply
+    pub time_manager: Box<dyn TimeManager>, // Time Manager option
+    pub avoid_moves: Vec<ChessMove>,        // Avoid Moves option, left out of every search
+    pub ponder_budget: Option<Duration>, // Time the running ponder search gets once ponderhit comes
+    pub training: Option<Arc<TrainingExport>>, // --export-training
+    pub engine_name: String, // id name, a fork can set its own with --name or [identity]
+    pub engine_author: String, // id author, the same with --author
+    pub hash_mb: i64,        // Hash option
+    pub hash_usage: Arc<HashUsage>, // Rough fill of the current table, for info hashfull
+    pub scores: Arc<ScoreLog>, // Our last evals this game, for how long to think
+    pub time_usage: Arc<TimeUsage>, // Budget against time taken, per move this game
+    pub threads: u8,         // Threads option
+    pub ponder_enabled: bool, // Ponder option
+    pub chess960: bool,      // UCI_Chess960 option
+    pub show_wdl: bool,      // UCI_ShowWDL option
+    pub move_overhead: Duration, // Move Overhead option
+    pub safety_buffer: Duration, // Safety Buffer option
+    pub max_move_share: u32, // Max Move Share option, percent
+    pub slow_mover: u32,     // SlowMover option, percent
+    pub min_thinking_time: Duration, // Minimum Thinking Time option
+    pub limit_strength: bool, // UCI_LimitStrength option
+    pub elo: i64,            // UCI_Elo option, only used with UCI_LimitStrength
+    pub debug: bool,         // debug on, diagnostics go to the GUI as info strings too
+}
+
+// A profile in effect and the option values it replaced
+pub struct ActiveProfile {
+    pub name: String,
+    pub replaced: Vec<(String, String)>,
+}
+
+pub enum Reply {
+    Output(String),
+    Search(Box<SearchRequest>),
+    Quit,
+}
+
+const WARMUP_TIME: Duration = Duration::from_millis(100);
+const FAST_PATH_BUDGET: Duration = Duration::from_millis(250); // Below this every ms of overhead counts
+const AUTHOR: &str = "15jgme";
+const INFINITE_TIME_LIMIT: Duration = Duration::from_secs(24 * 60 * 60); // Until stop, in practice
+const INLINE_INFINITE_CAP: Duration = Duration::from_secs(10);
+const MATE_TIME_LIMIT: Duration = Duration::from_secs(10); // go mate without a clock
+const HASH_NOTE: &str = "Hash isn't supported, the engine's table has a size of its own";
+const LAST_SEARCH_WAIT: Duration = Duration::from_millis(500); // For its bestmove, before answering a go
+
+impl UciSession {
+    pub fn new(cache: Option<CacheInputGrouping>) -> UciSession {
+        UciSession {
+            board: Board::default(), // Initializes to newboard
+            cache,
+            lazy_cache: None,
+            backend: Arc::new(ShallowRed),
+            rng: SessionRng::from_entropy(),
+            warmup: false,
+            warmed_up: false,
+            #[cfg(not(target_arch = "wasm32"))]
+            warming: None,
+            fast_path: false,
+            worker: None,
+            generation: 0,
+            repertoire: None,
+            repertoire_line: RepertoireTracker::default(),
+            capture: None,
+            prober: Arc::new(NoTablebases),
+            own_book: false,
+            book_file: None,
+            book_selection: SelectionPolicy::default(),
+            config_path: None,
+            persist_options: false,
+            option_defaults: HashMap::new(),
+            option_values: HashMap::new(),
+            gui_options: HashSet::new(),
+            profiles: Vec::new(),
+            opponent: None,
+            active_profile: None,
+            game: GameRecord::default(),
+            last_position: None,
+            pgn_file: None,
+            career_file: None,
+            ponder: PonderTracker::default(),
+            last_move_source: PredictionSource::Pv,
+            time_manager: Box::new(Classic),
+            avoid_moves: Vec::new(),
+            ponder_budget: None,
+            training: None,
+            engine_name: format!("Shallow Red {}", VERSION),
+            engine_author: AUTHOR.to_string(),
+            hash_mb: options::DEFAULT_HASH_MB,
+            hash_usage: Arc::new(HashUsage::new(options::DEFAULT_HASH_MB)),
+            scores: Arc::default(),
+            time_usage: Arc::default(),
+            threads: 1,
+            ponder_enabled: false,
+            chess960: false,
+            show_wdl: false,
+            move_overhead: Duration::ZERO,
+            safety_buffer: Duration::from_millis(DEFAULT_SAFETY_BUFFER_MS as u64),
+            max_move_share: DEFAULT_MAX_MOVE_SHARE as u32,
+            slow_mover: DEFAULT_SLOW_MOVER as u32,
+            min_thinking_time: Duration::from_millis(DEFAULT_MIN_THINKING_MS as u64),
+            limit_strength: false,
+            elo: handicap::DEFAULT_ELO,
+            debug: false,
+        }
+    }
+
+    // Cache built on first use, see cache_settings()
+    pub fn with_lazy_cache() -> UciSession {
+        UciSession {
+            lazy_cache: Some(Arc::default()),
+            ..UciSession::new(None)
+        }
+    }
+
+    // First search of a session is slow, get it out of the way before the GUI starts timing us.
+    // It runs next to whatever comes in, only an isready sent meanwhile waits for it.
+    pub fn start_warmup(&mut self) {
+        // A search under way is already the slow first one
+        if self.worker.as_ref().is_some_and(SearchWorker::searching) {
+            self.warmed_up = true;
+        }
+        if !self.warmup || self.warmed_up {
+            return;
+        }
+        self.warmed_up = true;
+        let settings = EngineSettings {
+            verbose: false,
+            cache_settings: self.cache_settings(),
+            time_limit: WARMUP_TIME,
+            ..Default::default()
+        };
+        let request = self.search_request(Board::default(), settings);
+        // Nothing from it reaches the GUI
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            self.warming = Some(thread::spawn(move || request.run(|_| {})));
+        }
+        #[cfg(target_arch = "wasm32")]
+        request.run(|_| {}); // No threads in the browser, it's over before the next command
+    }
+
+    fn finish_warmup(&mut self) {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(warming) = self.warming.take() {
+            let _ = warming.join(); // A panic in it was reported already
+        }
+    }
+
+    // Cache for a search, building it first if that hasn't happened yet
+    pub fn cache_settings(&mut self) -> Option<CacheInputGrouping> {
+        if self.cache.is_none() {
+            if let Some(lazy) = &self.lazy_cache {
+                self.cache = Some(lazy.get().clone());
+            }
+        }
+        self.cache.clone()
+    }
+
+    // Applies option values persisted in the config file, they become the advertised defaults too
+    pub fn load_config(&mut self, path: PathBuf) {
+        for (spec, value) in options::load_persisted(&path) {
+            info!("Persisted option {} = {}", spec.name, value);
+            self.set_option(spec.name, &value);
+            self.option_defaults.insert(spec.name, value);
+        }
+        if let Some(config) = options::read_config(&path) {
+            self.profiles = load_profiles(&config);
+        }
+        self.config_path = Some(path);
+    }
+
+    // From the command line: set, and advertised as the default from then on like a persisted value
+    pub fn set_default_option(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let spec = options::find(name).ok_or_else(|| format!("unknown option {}", name))?;
+        let value = spec.check_value(value)?;
+        if !self.set_option(spec.name, &value) {
+            return Err(format!("{} can't be {}", spec.name, value));
+        }
+        info!("Command line option {} = {}", spec.name, value);
+        self.option_defaults.insert(spec.name, value);
+        Ok(())
+    }
+
+    pub fn handle(&mut self, uci_input: &str) -> Option<Reply> {
+        crash::record_received(uci_input);
+        let reply = match parse_command(uci_input) {
+            Ok(command) => self.dispatch(command),
+            Err(ParseError::Empty) => None,
+            Err(e @ ParseError::SetOption) => {
+                warn!("Ignoring {}", uci_input.trim());
+                Some(Reply::Output(
+                    UciResponse::info_string(e.to_string()).to_string(),
+                ))
+            }
+            Err(e @ ParseError::Position(_)) => {
+                warn!("Ignoring {}: {}", uci_input, e);
+                position_ignored(e)
+            }
+            Err(e @ ParseError::Debug(_)) => {
+                warn!("{}", e);
+                None
+            }
+        };
+        self.answered(reply)
+    }
+
+    // A command that's already been parsed, for frontends that don't speak text to us
+    pub fn handle_command(&mut self, command: UciCommand) -> Option<Reply> {
+        crash::record_received(&format!("{:?}", command));
+        let reply = self.dispatch(command);
+        self.answered(reply)
+    }
+
+    fn answered(&self, reply: Option<Reply>) -> Option<Reply> {
+        if let Some(Reply::Output(out)) = &reply {
+            crash::record_sent(out);
+        }
+        crash::set_context(self.crash_context());
+        reply
+    }
+
+    fn dispatch(&mut self, command: UciCommand) -> Option<Reply> {
+        match command {
+            UciCommand::Uci => {
+                let mut out = vec![UciResponse::Id {
+                    name: self.engine_name.clone(),
+                    author: self.engine_author.clone(),
+                }];
+                for spec in &OPTIONS {
+                    let default = self
+                        .option_defaults
+                        .get(spec.name)
+                        .cloned()
+                        .unwrap_or_else(|| spec.default_value());
+                    out.push(UciResponse::Option { spec, default });
+                }
+                out.push(UciResponse::UciOk);
+                let lines: Vec<String> = out.iter().map(UciResponse::to_string).collect();
+                Some(Reply::Output(lines.join("\n")))
+            }
+            UciCommand::IsReady => {
+                // Once the cache is on its way, readyok means it's there
+                if self.lazy_cache.as_ref().is_some_and(|lazy| lazy.started()) {
+                    self.cache_settings();
+                }
+                self.finish_warmup();
+                Some(Reply::Output(UciResponse::ReadyOk.to_string()))
+            }
+            UciCommand::SetOption { name, value } => {
+                if let (true, Some(lazy)) = (name.eq_ignore_ascii_case("Hash"), &self.lazy_cache) {
+                    lazy.kick_off(); // GUIs send Hash just before playing, start building it now
+                }
+                // Anything not in OPTIONS gets an answer, GUIs show info strings to the user
+                let Some(spec) = options::find(&name) else {
+                    warn!("Unknown option {}", name);
+                    return Some(Reply::Output(
+                        UciResponse::info_string(format!("unknown option {}", name)).to_string(),
+                    ));
+                };
+                let value = match spec.check_value(&value) {
+                    Ok(value) => value,
+                    Err(e) => {
+                        warn!("Ignoring setoption: {}", e);
+                        return Some(Reply::Output(UciResponse::info_string(e).to_string()));
+                    }
+                };
+                if spec.name == "Full Reset" {
+                    return Some(Reply::Output(self.reset()));
+                }
+                if spec.name == "Clear Hash" {
+                    self.clear_hash();
+                    return Some(Reply::Output(
+                        UciResponse::info_string("hash cleared").to_string(),
+                    ));
+                }
+                if !self.set_option(spec.name, &value) {
+                    let refused = format!("{} can't be set to {}", spec.name, value);
+                    return Some(Reply::Output(UciResponse::info_string(refused).to_string()));
+                }
+                self.gui_options.insert(spec.name.to_string());
+                if spec.name == "Startup Warmup" {
+                    self.start_warmup();
+                }
+                self.persist_option(spec.name, &value);
+                // GUIs show info strings to the user, the nearest UCI has to option help
+                (spec.name == "Hash")
+                    .then(|| Reply::Output(UciResponse::info_string(HASH_NOTE).to_string()))
+            }
+            UciCommand::UciNewGame => {
+                self.board = Board::default();
+                self.repertoire_line.reset();
+                // Until the next opponent is announced they don't match the current profile
+                self.opponent = None;
+                self.revert_profile();
+                self.game = GameRecord::default();
+                self.last_position = None;
+                self.scores.clear();
+                self.time_usage.finish_game(); // Already done if a result came in
+                self.ponder.new_game();
+                self.ponder_budget = None;
+                self.last_move_source = PredictionSource::Pv;
+                // Last game's entries only get in the way, and the Cache has no clear message
+                self.clear_hash();
+                if let Some(capture) = &self.capture {
+                    capture.new_game();
+                }
+                if let Some(training) = &self.training {
+                    training.finish_game(None); // Nothing pending if a result came in
+                }
+                None
+            } // Wipe board
+            UciCommand::Position {
+                start: position_start,
+                moves: tokens,
+            } => {
+                // GUIs send the whole game every ply, usually it's the last one plus a move or two
+                let parsed = match self.new_moves(&position_start, &tokens) {
+                    Some((added, first_ply)) => extend_position(
+                        (self.game.start, self.game.moves.clone(), self.board),
+                        added,
+                        first_ply,
+                        self.chess960,
+                    )
+                    .map(|(start, moves, board)| {
+                        let counters = (self.game.start_halfmoves, self.game.start_fullmove);
+                        (start, moves, board, counters)
+                    }),
+                    None => parse_position(&position_start, &tokens, self.chess960).map(
+                        |(start, moves)| {
+                            let board = play_moves(start, &moves);
+                            (start, moves, board, fen_counters(&position_start))
+                        },
+                    ),
+                };
+                match parsed {
+                    Ok((start, moves, board, (start_halfmoves, start_fullmove))) => {
+                        self.game.record_position(start, &moves);
+                        self.game.start_halfmoves = start_halfmoves;
+                        self.game.start_fullmove = start_fullmove;
+                        self.board = board;
+                        self.last_position = Some((position_start, tokens, board));
+                    }
+                    Err(e) => {
+                        warn!("Ignoring position: {}", e);
+                        return position_ignored(e);
+                    }
+                }
+                None
+            }
+            UciCommand::Go(go) => {
+                // Nothing to search, and the engine doesn't cope with no legal moves
+                let over = match self.board.status() {
+                    BoardStatus::Ongoing => None,
+                    BoardStatus::Checkmate => Some("checkmate"),
+                    BoardStatus::Stalemate => Some("stalemate"),
+                };
+                if let Some(over) = over {
+                    info!("go in a finished game, {}", over);
+                    return self.answer_go(format!(
+                        "info string no legal moves, {}\nbestmove 0000",
+                        over
+                    ));
+                }
+                let (root_moves, notice) = match self.root_moves(&go) {
+                    Ok(root_moves) => root_moves,
+                    Err(problem) => {
+                        return self.answer_go(format!("info string {}\nbestmove 0000", problem))
+                    }
+                };
+                // The position already has the predicted reply on it. With a worker the ponder
+                // search runs until ponderhit or stop, and only then gets its normal budget.
+                let mut ponder = go.ponder;
+                if ponder && !self.ponder_enabled {
+                    warn!("go ponder with the Ponder option off, searching normally");
+                    ponder = false;
+                }
+                let pondering = ponder && self.worker.is_some();
+                if ponder {
+                    self.ponder
+                        .start(self.game.moves.last().copied(), self.last_move_source);
+                } else {
+                    self.ponder.miss();
+                }
+                self.ponder_budget = None;
+
+                self.game.our_color = Some(self.board.side_to_move());
+                // A restricted root is analysis, the book and repertoire don't know about it. A
+                // ponder search can't answer before ponderhit, so it doesn't use them either.
+                if let (None, Some(repertoire), false) = (&root_moves, &self.repertoire, pondering)
+                {
+                    if let Some(chess_move) = self.repertoire_line.probe(repertoire, &self.board) {
+                        self.last_move_source = PredictionSource::Book;
+                        return self.answer_go(self.bestmove(chess_move));
+                    }
+                }
+                if let Some((chess_move, built_in)) = (root_moves.is_none() && !pondering)
+                    .then(|| self.book_move())
+                    .flatten()
+                {
+                    self.last_move_source = PredictionSource::Book;
+                    let notice = if built_in {
+                        "info string using built-in book\n"
+                    } else {
+                        ""
+                    };
+                    return self.answer_go(notice.to_string() + &self.bestmove(chess_move));
+                }
+                // A limited strength engine throws some moves away without thinking
+                if let Some(chess_move) = (self.limit_strength && !pondering)
+                    .then(|| {
+                        handicap::random_move(
+                            self.elo,
+                            &self.board,
+                            root_moves.as_deref(),
+                            &mut self.rng,
+                        )
+                    })
+                    .flatten()
+                {
+                    info!("UCI_Elo {} random move {}", self.elo, chess_move);
+                    self.last_move_source = PredictionSource::Pv;
+                    return self.answer_go(self.bestmove(chess_move));
+                }
+                // Nothing to search for once the tables have the answer
+                if let Some((chess_move, wdl)) = (!pondering)
+                    .then(|| root_move(&*self.prober, &self.board, root_moves.as_deref()))
+                    .flatten()
+                {
+                    info!("Tablebase move {} ({}) on {}", chess_move, wdl, self.board);
+                    self.last_move_source = PredictionSource::Pv;
+                    return self.answer_go(format!(
+                        "info string tablebase {}\n{}",
+                        wdl,
+                        self.bestmove(chess_move)
+                    ));
+                }
+
+                // Get our current time
+                let mut context = TimeContext::from_go(&go, &self.board, self.game.moves_made());
+                context.move_overhead = self.move_overhead;
+                context.safety_buffer = self.safety_buffer;
+                context.max_move_share = self.max_move_share;
+                context.slow_mover = self.slow_mover;
+                context.min_thinking_time = Some(self.min_thinking_time);
+                (context.previous_score, context.last_score) = self.scores.last_two();
+                let fixed =
+                    context.movetime.is_some() || context.infinite || context.mate.is_some();
+                if context.clock.is_none() && !fixed {
+                    warn!("go without movetime or our clock, {:?}", go);
+                }
+                let (mut time_limit, limit_notice) = self.time_limit(&context);
+                if self.limit_strength && !context.infinite {
+                    time_limit = handicap::time_limit(self.elo, time_limit);
+                }
+                if pondering {
+                    self.ponder_budget = Some(time_limit);
+                    time_limit = INFINITE_TIME_LIMIT;
+                }
+                let notice = match (notice, limit_notice) {
+                    (Some(first), Some(second)) => {
+                        Some(format!("{}\ninfo string {}", first, second))
+                    }
+                    (first, second) => first.or(second),
+                };
+
+                let settings = EngineSettings {
+                    verbose: false,
+                    cache_settings: self.cache_settings(),
+                    time_limit,
+                    ..Default::default()
+                };
+
+                let mut request = self.search_request(self.board, settings);
+                request.root_moves = root_moves;
+                request.notice = notice;
+                request.until_stop = (context.infinite || pondering) && self.worker.is_some();
+                request.scores = Some(self.scores.clone());
+                // A ponder or infinite search's time is up to the GUI, nothing to learn from it
+                if !pondering && !context.infinite {
+                    self.time_usage
+                        .start(request.generation, time_limit, context.clock);
+                    request.time_usage = Some(self.time_usage.clone());
+                }
+                // Pondering has no limit until ponderhit, and ponderhit brings its own
+                request.hard_limit = self
+                    .hard_limit(&context)
+                    .filter(|_| !pondering)
+                    .map(|hard_limit| hard_limit.max(time_limit));
+                request.mate = context.mate;
+                request.training = self
+                    .training
+                    .clone()
+                    .map(|training| (training, self.game.moves.len() as u32));
+                if self.fast_path || time_limit < FAST_PATH_BUDGET {
+                    // Cache writes wait until bestmove is out, lookups still read the shared table
+                    request.fast = true;
+                    if let Some(cache) = &self.cache {
+                        let (cache_settings, apply_writes) = deferred_cache(&cache.cache_ref);
+                        request.settings.cache_settings = Some(cache_settings);
+                        request.deferred_cache = Some(apply_writes);
+                    }
+                }
+                if self.debug {
+                    let debug = self.debug_allocation(&context, time_limit, &request);
+                    request.notice = Some(match request.notice.take() {
+                        Some(notice) => format!("{}\ninfo string {}", notice, debug),
+                        None => debug,
+                    });
+                }
+
+                self.last_move_source = PredictionSource::Pv;
+                match &self.worker {
+                    Some(worker) => {
+                        worker.send(WorkerMessage::Search(Box::new(request)));
+                        None
+                    }
+                    None => Some(Reply::Search(Box::new(request))),
+                }
+            }
+            UciCommand::Capture => match &self.capture {
+                // Debug command, snapshot the current position
+                Some(capture) => {
+                    capture.snapshot(&self.board);
+                    None
+                }
+                None => Some(Reply::Output(
+                    "info string capture needs the Capture File option".to_string(),
+                )),
+            },
+            UciCommand::TbProbe => Some(Reply::Output(
+                tbprobe(&*self.prober, &self.board).join("\n"),
+            )),
+            UciCommand::SymTest(file) => {
+                // Debug command, blocks until every position has been searched twice
+                let out = match load_positions(file.as_deref().map(Path::new)) {
+                    Ok(positions) => symtest(&*self.backend, &positions).lines().join("\n"),
+                    Err(e) => format!("info string symtest {}", e),
+                };
+                Some(Reply::Output(out))
+            }
+            UciCommand::Reset => Some(Reply::Output(self.reset())),
+            UciCommand::Result(args) => {
+                let args: Vec<&str> = args.iter().map(String::as_str).collect();
+                self.record_result(&args).map(Reply::Output)
+            }
+            UciCommand::Stats => Some(Reply::Output(self.stats_lines().join("\n"))),
+            UciCommand::Stop => {
+                self.ponder.miss();
+                self.ponder_budget = None;
+                if let Some(worker) = &self.worker {
+                    worker.send(WorkerMessage::Stop);
+                }
+                None
+            }
+            UciCommand::PonderHit => {
+                self.ponder.hit();
+                match (&self.worker, self.ponder_budget.take()) {
+                    (Some(worker), Some(budget)) => worker.send(WorkerMessage::PonderHit(budget)),
+                    _ => info!("ponderhit ignored, nothing is pondering"),
+                }
+                None
+            }
+            UciCommand::Quit => Some(Reply::Quit),
+            #[cfg(test)]
+            UciCommand::DebugPanic => panic!("debugpanic requested"),
+            UciCommand::Debug(on) => {
+                self.debug = on;
+                None
+            }
+            UciCommand::Unknown(command) => {
+                warn!("Ignoring unknown command {}", command);
+                self.debug
+                    .then(|| Reply::Output(format!("info string unknown command {}", command)))
+            }
+        }
+    }
+
+    // Back to how the session looked after startup: search stopped, a new cache and its thread,
+    // no game and every option at its configured default. Fine to call at any point.
+    pub fn reset(&mut self) -> String {
+        if let Some(worker) = &self.worker {
+            worker.send(WorkerMessage::Stop);
+        }
+        // The old manager quits once the stopped search lets go of its sender
+        if self.lazy_cache.is_some() {
+            self.lazy_cache = Some(Arc::default());
+            self.cache = None;
+        } else if self.cache.is_some() {
+            self.cache = Some(spawn_cache_manager());
+        }
+
+        self.board = Board::default();
+        self.repertoire_line.reset();
+        self.game = GameRecord::default();
+        self.last_position = None;
+        self.scores.clear();
+        self.time_usage.finish_game();
+        self.ponder.new_game();
+        self.opponent = None;
+        self.ponder_budget = None;
+        self.active_profile = None;
+        if let Some(capture) = &self.capture {
+            capture.new_game();
+        }
+        if let Some(training) = &self.training {
+            training.finish_game(None);
+        }
+
+        self.option_values.clear();
+        self.gui_options.clear();
+        for spec in &OPTIONS {
+            if spec.kind == OptionKind::Button {
+                continue;
+            }
+            let default = self
+                .option_defaults
+                .get(spec.name)
+                .cloned()
+                .unwrap_or_else(|| spec.default_value());
+            self.apply_option(spec.name, &default);
+        }
+        self.warmed_up = false; // New cache is cold again
+        self.start_warmup();
+        info!("Session reset");
+        UciResponse::info_string("reset complete").to_string()
+    }
+
+    // False when the option is unknown or the value was rejected
+    fn set_option(&mut self, name: &str, value: &str) -> bool {
+        let accepted = self.apply_option(name, value);
+        if let (true, Some(spec)) = (accepted, options::find(name)) {
+            self.option_values.insert(spec.name, value.to_string());
+        }
+        accepted
+    }
+
+    fn option_value(&self, name: &str) -> Option<String> {
+        let spec = options::find(name)?;
+        self.option_values
+            .get(spec.name)
+            .or(self.option_defaults.get(spec.name))
+            .cloned()
+            .or_else(|| Some(spec.default_value()))
+    }
+
+    fn apply_option(&mut self, name: &str, value: &str) -> bool {
+        match name {
+            "Startup Warmup" => self.warmup = value == "true",
+            "Bullet Fast Path" => self.fast_path = value == "true",
+            "Repertoire File" => return self.set_repertoire(value),
+            "OwnBook" => self.own_book = value == "true",
+            "BookFile" => return self.set_book_file(value),
+            "Book Selection" => match value.parse() {
+                Ok(policy) => self.book_selection = policy,
+                Err(e) => {
+                    warn!("{}", e);
+                    return false;
+                }
+            },
+            "Capture File" => {
+                self.capture = match value {
+                    "" | "<empty>" => None,
+                    path => Some(Arc::new(EpdCapture::new(PathBuf::from(path)))),
+                }
+            }
+            "Persist Options" => self.persist_options = value == "true",
+            "UCI_Opponent" => self.announce_opponent(value),
+            "Time Manager" => match time_manager(value) {
+                Some(manager) => self.time_manager = manager,
+                None => {
+                    warn!("Unknown time manager {}", value);
+                    return false;
+                }
+            },
+            "Avoid Moves" => {
+                let moves: Result<Vec<ChessMove>, _> =
+                    value.split_whitespace().map(ChessMove::from_str).collect();
+                match moves {
+                    Ok(moves) => self.avoid_moves = moves,
+                    Err(_) if value == "<empty>" => self.avoid_moves.clear(),
+                    Err(_) => {
+                        warn!("Avoid Moves takes moves like e2e4, got {}", value);
+                        return false;
+                    }
+                }
+            }
+            "PGN File" => self.pgn_file = optional_path(value),
+            "Career File" => self.career_file = optional_path(value),
+            // Only the hashfull estimate goes by it, see HASH_NOTE
+            "Hash" => match value.parse() {
+                Ok(megabytes) => {
+                    self.hash_mb = megabytes;
+                    self.hash_usage.set_capacity(megabytes);
+                }
+                Err(_) => return false,
+            },
+            "Ponder" => self.ponder_enabled = value == "true",
+            "UCI_Chess960" => self.chess960 = value == "true",
+            "UCI_ShowWDL" => self.show_wdl = value == "true",
+            "SyzygyPath" => self.set_syzygy_path(value),
+            "UCI_LimitStrength" => self.limit_strength = value == "true",
+            "UCI_Elo" => match value.parse() {
+                Ok(elo) => self.elo = elo,
+                Err(_) => return false,
+            },
+            "Move Overhead" => match value.parse() {
+                Ok(ms) => self.move_overhead = Duration::from_millis(ms),
+                Err(_) => return false,
+            },
+            "Safety Buffer" => match value.parse() {
+                Ok(ms) => self.safety_buffer = Duration::from_millis(ms),
+                Err(_) => return false,
+            },
+            "Max Move Share" => match value.parse() {
+                Ok(percent) => self.max_move_share = percent,
+                Err(_) => return false,
+            },
+            "SlowMover" => match value.parse() {
+                Ok(percent) => self.slow_mover = percent,
+                Err(_) => return false,
+            },
+            "Minimum Thinking Time" => match value.parse() {
+                Ok(ms) => self.min_thinking_time = Duration::from_millis(ms),
+                Err(_) => return false,
+            },
+            "Threads" => match value.parse() {
+                Ok(threads) => self.threads = threads,
+                Err(_) => return false,
+            },
+            _ => return false,
+        }
+        true
+    }
+
+    fn set_syzygy_path(&mut self, value: &str) {
+        self.prober = match value {
+            "" | "<empty>" => Arc::new(NoTablebases),
+            #[cfg(not(target_arch = "wasm32"))]
+            path => match SyzygyTables::open(path) {
+                Ok(tables) => Arc::new(tables),
+                Err(e) => {
+                    warn!("SyzygyPath: {}", e);
+                    Arc::new(NoTablebases)
+                }
+            },
+            #[cfg(target_arch = "wasm32")]
+            _ => {
+                warn!("No tablebases in the browser, SyzygyPath ignored");
+                Arc::new(NoTablebases)
+            }
+        };
+    }
+
+    // debug on: how the time for a search was arrived at and what it does with the cache
+    fn debug_allocation(
+        &self,
+        context: &TimeContext,
+        time_limit: Duration,
+        request: &SearchRequest,
+    ) -> String {
+        let clock = match context.clock {
+            Some(clock) => format!("{}ms", clock.as_millis()),
+            None => "none".to_string(),
+        };
+        let cache = match (&request.settings.cache_settings, &request.deferred_cache) {
+            (None, _) => "none",
+            (Some(_), Some(_)) => "writes deferred",
+            (Some(_), None) => "shared",
+        };
+        format!(
+            "debug time {}ms, clock {} inc {}ms movestogo {}, manager {}, cache {}",
+            time_limit.as_millis(),
+            clock,
+            context.increment.as_millis(),
+            context
+                .moves_to_go
+                .map_or("none".to_string(), |moves| moves.to_string()),
+            self.option_value("Time Manager").unwrap_or_default(),
+            cache
+        )
+    }
+
+    // A go answered without a search goes straight out, ahead of anything the worker sends. So a
+    // search still running there is stopped first and its bestmove let out, or the two could
+    // arrive either way round.
+    fn answer_go(&self, out: String) -> Option<Reply> {
+        if let Some(worker) = self.worker.as_ref().filter(|worker| worker.searching()) {
+            warn!("go while still searching, stopping the last search first");
+            if !worker.stop_and_wait(LAST_SEARCH_WAIT) {
+                warn!("Last search still hasn't answered, answering the go anyway");
+            }
+        }
+        Some(Reply::Output(out))
+    }
+
+    // The bestmove line for a move we didn't search for, as the GUI expects to see it. With Ponder
+    // on it names the reply we expect.
+    fn bestmove(&self, chess_move: ChessMove) -> String {
+        let out = UciResponse::BestMove {
+            best: Some(chess_move),
+            ponder: self
+                .ponder_enabled
+                .then(|| self.expected_reply(chess_move))
+                .flatten(),
+        }
+        .to_string();
+        match self.chess960 {
+            true => chess960::bestmove_out(&self.board, &out),
+            false => out,
+        }
+    }
+
+    // The book's favourite answer to our move, or what the tables say they should play
+    fn expected_reply(&self, chess_move: ChessMove) -> Option<ChessMove> {
+        let after = self.board.make_move_new(chess_move);
+        let book = match &self.book_file {
+            Some(book) => Some(book),
+            None => default_book(),
+        };
+        book.filter(|_| self.own_book)
+            .and_then(|book| {
+                book.candidates(&after)
+                    .into_iter()
+                    .max_by_key(|entry| entry.weight)
+            })
+            .map(|entry| entry.chess_move)
+            .or_else(|| root_move(&*self.prober, &after, None).map(|(reply, _)| reply))
+    }
+
+    // Empties the shared table in place, so the manager thread and running searches keep going
+    fn clear_hash(&mut self) {
+        if let Some(cache) = &self.cache {
+            *cache.cache_ref.write() = Cache::default();
+            info!("Hash cleared");
+        }
+        self.hash_usage.reset(self.hash_mb);
+        self.warmed_up = false;
+        self.start_warmup();
+    }
+
+    fn announce_opponent(&mut self, value: &str) {
+        self.opponent = Opponent::parse(value);
+        let matched = self
+            .opponent
+            .as_ref()
+            .and_then(|opponent| match_profile(&self.profiles, opponent))
+            .cloned();
+        let active = self
+            .active_profile
+            .as_ref()
+            .map(|active| active.name.clone());
+        if matched.as_ref().map(|profile| &profile.name) == active.as_ref() {
+            return;
+        }
+
+        self.revert_profile();
+        let Some(profile) = matched else {
+            return;
+        };
+        let (apply, skipped) = plan(&profile, &self.gui_options);
+        for name in skipped {
+            info!(
+                "Profile {} leaves {} alone, the GUI set it",
+                profile.name, name
+            );
+        }
+        let mut replaced = Vec::new();
+        for (name, value) in apply {
+            let previous = self.option_value(name);
+            if self.set_option(name, value) {
+                replaced.extend(previous.map(|previous| (name.clone(), previous)));
+            } else {
+                warn!(
+                    "Profile {} has a bad option {} = {}",
+                    profile.name, name, value
+                );
+            }
+        }
+        info!("Applied profile {} for {:?}", profile.name, self.opponent);
+        self.active_profile = Some(ActiveProfile {
+            name: profile.name,
+            replaced,
+        });
+    }
+
+    fn revert_profile(&mut self) {
+        let Some(active) = self.active_profile.take() else {
+            return;
+        };
+        for (name, value) in active.replaced {
+            if !self.gui_options.contains(&name) {
+                self.set_option(&name, &value);
+            }
+        }
+        info!("Reverted profile {}", active.name);
+    }
+
+    // result <1-0|0-1|1/2-1/2|*> [comment], once per game. Answers with the end-of-game summary.
+    fn record_result(&mut self, args: &[&str]) -> Option<String> {
+        let result = match args.first().map(|token| token.parse::<GameResult>()) {
+            Some(Ok(result)) => result,
+            Some(Err(e)) => {
+                warn!("Ignoring result: {}", e);
+                return None;
+            }
+            None => {
+                warn!("Ignoring result without a score");
+                return None;
+            }
+        };
+        if let Some(recorded) = self.game.result {
+            warn!(
+                "Ignoring result {}, this game already ended {}",
+                result, recorded
+            );
+            return None;
+        }
+        self.ponder.miss(); // Still pondering when the game ended
+        let comment = args[1..].join(" ");
+        let comment = comment.trim_start_matches('{').trim_end_matches('}').trim();
+        self.game.result = Some(result);
+        self.game.comment = (!comment.is_empty()).then(|| comment.to_string());
+        self.game.opponent = self.opponent.as_ref().map(|opponent| opponent.name.clone());
+        info!("Game over {} {}", result, comment);
+        self.time_usage.finish_game();
+        if let Some(training) = &self.training {
+            training.finish_game(Some(result));
+        }
+
+        if let Some(path) = &self.pgn_file {
+            if let Err(e) = self.game.append_pgn(path) {
+                warn!("Couldn't write PGN to {}: {}", path.display(), e);
+            }
+        }
+        if let Some(path) = &self.career_file {
+            let mut stats = CareerStats::load(path);
+            stats.record(result, self.game.our_color);
+            stats.ponder.add(&self.ponder.game);
+            if let Err(e) = stats.save(path) {
+                warn!("Couldn't update career stats in {}: {}", path.display(), e);
+            }
+        }
+        let summary = format!(
+            "info string game over {} {}",
+            result,
+            self.ponder.game.summary()
+        );
+        info!("{}", summary);
+        Some(summary)
+    }
+
+    fn crash_context(&self) -> CrashContext {
+        CrashContext {
+            fen: self.board.to_string(),
+            moves: self.game.moves.iter().map(ChessMove::to_string).collect(),
+            options: OPTIONS
+                .iter()
+                .filter_map(|spec| Some((spec.name.to_string(), self.option_value(spec.name)?)))
+                .collect(),
+        }
+    }
+
+    // Answers to the stats command
+    fn stats_lines(&self) -> Vec<String> {
+        let mut lines = vec![
+            format!("info string stats game {}", self.ponder.game.summary()),
+            format!(
+                "info string stats session {}",
+                self.ponder.session.summary()
+            ),
+        ];
+        if let Some(path) = &self.career_file {
+            let career = CareerStats::load(path);
+            lines.push(format!(
+                "info string stats career games {} wins {} draws {} losses {} {}",
+                career.games,
+                career.wins,
+                career.draws,
+                career.losses,
+                career.ponder.summary()
+            ));
+        }
+        lines
+    }
+
+    fn persist_option(&self, name: &str, value: &str) {
+        let (Some(path), Some(spec)) = (&self.config_path, options::find(name)) else {
+            return;
+        };
+        // Turning persistence off has to stick as well
+        if spec.persist && (self.persist_options || name == "Persist Options") {
+            let value = if value == "<empty>" { "" } else { value };
+            if let Err(e) = options::persist(path, spec, value) {
+                warn!("Couldn't persist {} to {}: {}", name, path.display(), e);
+            }
+        }
+    }
+
+    // A file that can't be loaded is rejected and whatever was loaded before stays
+    fn set_repertoire(&mut self, value: &str) -> bool {
+        self.repertoire = match value {
+            "" | "<empty>" => None,
+            path => match Repertoire::load(Path::new(path)) {
+                Ok(repertoire) => {
+                    info!(
+                        "Loaded repertoire {} ({} positions)",
+                        path,
+                        repertoire.len()
+                    );
+                    Some(repertoire)
+                }
+                Err(e) => {
+                    warn!("{}", e);
+                    return false;
+                }
+            },
+        };
+        self.repertoire_line.reset();
+        true
+    }
+
+    // Same as the repertoire, a bad book leaves the last good one in place
+    fn set_book_file(&mut self, value: &str) -> bool {
+        self.book_file = match value {
+            "" | "<empty>" => None,
+            path => match PolyglotBook::load(Path::new(path)) {
+                Ok(book) => {
+                    info!("Loaded book {} ({} entries)", path, book.len());
+                    Some(book)
+                }
+                Err(e) => {
+                    warn!("{}", e);
+                    return false;
+                }
+            },
+        };
+        true
+    }
+
+    // searchmoves narrows the root, avoidmoves and the Avoid Moves option take moves out of it.
+    // None when the root isn't restricted, plus a notice when the restriction had to be dropped.
+    // Err when nothing's left to search, with what to tell the GUI
+    fn root_moves(
+        &self,
+        go: &GoParams,
+    ) -> Result<(Option<Vec<ChessMove>>, Option<String>), String> {
+        let search = go.searchmoves.as_deref().map(go_moves);
+        let mut avoid = go.avoidmoves.as_deref().map(go_moves).unwrap_or_default();
+        avoid.extend(&self.avoid_moves);
+        if search.is_none() && avoid.is_empty() {
+            return Ok((None, None));
+        }
+        // A searchmoves entry that isn't a legal move here is a GUI bug, say so rather than
+        // quietly searching something else
+        let rejected: Vec<String> = go
+            .searchmoves
+            .iter()
+            .flatten()
+            .filter(|token| {
+                ChessMove::from_str(token)
+                    .ok()
+                    .is_none_or(|chess_move| !self.board.legal(chess_move))
+            })
+            .cloned()
+            .collect();
+        if !rejected.is_empty() {
+            warn!(
+                "searchmoves {} not legal on {}",
+                rejected.join(" "),
+                self.board
+            );
+        }
+        let rejected_notice = (!rejected.is_empty())
+            .then(|| format!("searchmoves {} not legal here, ignored", rejected.join(" ")));
+
+        let allowed: Vec<ChessMove> = MoveGen::new_legal(&self.board)
+            .filter(|m| search.as_ref().is_none_or(|search| search.contains(m)))
+            .filter(|m| !avoid.contains(m))
+            .collect();
+        if allowed.is_empty() {
+            warn!("Every legal move is excluded on {}", self.board);
+            let notice = "every legal move is excluded, nothing to search";
+            return Err(match rejected_notice {
+                Some(rejected) => format!("{}\ninfo string {}", rejected, notice),
+                None => notice.to_string(),
+            });
+        }
+        Ok((Some(allowed), rejected_notice))
+    }
+
+    // How long the engine gets, and anything the GUI should know about it. The engine can't stop
+    // at a depth or count nodes, so go depth and go nodes get a notice and are searched as if the
+    // limit wasn't there.
+    fn time_limit(&self, context: &TimeContext) -> (Duration, Option<String>) {
+        let (time_limit, notice) = self.clock_limit(context);
+        let notices: Vec<String> = [
+            context.depth.map(|depth| {
+                format!(
+                    "depth {} isn't supported, the engine can't stop at a depth",
+                    depth
+                )
+            }),
+            context.nodes.map(|nodes| {
+                format!(
+                    "nodes {} isn't supported, the engine doesn't count nodes",
+                    nodes
+                )
+            }),
+            notice,
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        let notice = (!notices.is_empty()).then(|| notices.join("\ninfo string "));
+        (time_limit, notice)
+    }
+
+    // A movetime is exact, otherwise the engine has a single limit so it gets the manager's soft one
+    fn clock_limit(&self, context: &TimeContext) -> (Duration, Option<String>) {
+        if context.infinite {
+            // Only a worker can hear the stop, inline searches have to end by themselves
+            return match self.worker {
+                Some(_) => (INFINITE_TIME_LIMIT, None),
+                None => (
+                    INLINE_INFINITE_CAP,
+                    Some(format!(
+                        "go infinite can't be stopped here, searching {}ms",
+                        INLINE_INFINITE_CAP.as_millis()
+                    )),
+                ),
+            };
+        }
+        match context.movetime {
+            Some(movetime) => (within_clock(movetime, context), None),
+            None if context.clock.is_none() && context.mate.is_some() => (MATE_TIME_LIMIT, None),
+            None => {
+                let budget = stability_budget(self.time_manager.budget(context), context);
+                (within_clock(budget.soft, context), None)
+            }
+        }
+    }
+
+    // When the worker stops the engine whatever it's doing: the manager's hard limit, for searches
+    // against the clock or a movetime
+    fn hard_limit(&self, context: &TimeContext) -> Option<Duration> {
+        let clocked = context.clock.is_some() || context.movetime.is_some();
+        (clocked && !context.infinite)
+            .then(|| within_clock(self.time_manager.budget(context).hard, context))
+    }
+
+    // Book move for the current position and whether it came from the built-in book
+    fn book_move(&mut self) -> Option<(ChessMove, bool)> {
+        if !self.own_book {
+            return None;
+        }
+        let (book, built_in) = match &self.book_file {
+            Some(book) => (book, false),
+            None => (default_book()?, true),
+        };
+        let candidates = book.candidates(&self.board);
+        if candidates.is_empty() {
+            return None;
+        }
+        self.book_selection
+            .select(&candidates, &mut self.rng)
+            .map(|chess_move| (chess_move, built_in))
+    }
+
+    // The moves a position command adds to the last one and the ply of the first one, if that's
+    // all it does. Not if the board was set some other way since.
+    fn new_moves<'a>(
+        &self,
+        start: &PositionStart,
+        moves: &'a [String],
+    ) -> Option<(&'a [String], usize)> {
+        let (last_start, last_moves, board) = self.last_position.as_ref()?;
+        if *board != self.board || start != last_start || !moves.starts_with(last_moves) {
+            return None;
+        }
+        Some((&moves[last_moves.len()..], last_moves.len()))
+    }
+
+    pub fn search_request(&mut self, board: Board, settings: EngineSettings) -> SearchRequest {
+        self.generation += 1;
+        SearchRequest {
+            board,
+            settings,
+            backend: self.backend.clone(),
+            generation: self.generation,
+            fast: false,
+            deferred_cache: None,
+            capture: self.capture.clone(),
+            root_moves: None,
+            notice: None,
+            training: None,
+            until_stop: false,
+            mate: None,
+            helpers: self.threads - 1,
+            chess960: self.chess960,
+            show_wdl: self.show_wdl,
+            ponder_move: self.ponder_enabled,
+            progress: None,
+            hash_usage: Some(self.hash_usage.clone()),
+            history: Some(Arc::new(self.game.position_hashes())),
+            halfmove_clock: self.game.halfmove_clock(),
+            hard_limit: None,
+            scores: None,
+            time_usage: None,
+        }
+    }
+}
+
+// The halfmove clock and fullmove number a start FEN gives, 0 and 1 where it leaves them out.
+// After a null move both are off, and so are the record's halfmove clock and moves made, since
+// the moves before it aren't in the record any more.
+fn fen_counters(start: &PositionStart) -> (u32, u32) {
+    let PositionStart::Fen(fen) = start else {
+        return (0, 1);
+    };
+    let counter = |at: usize, missing: u32| {
+        fen.split_whitespace()
+            .nth(at)
+            .and_then(|counter| counter.parse().ok())
+            .unwrap_or(missing)
+    };
+    (counter(4, 0), counter(5, 1))
+}
+
+// The answer to a position that can't be used. The last good board is kept, searching a wrong
+// one loses the game quietly.
+fn position_ignored(problem: impl fmt::Display) -> Option<Reply> {
+    let ignored = format!("error position ignored, {}", problem);
+    Some(Reply::Output(UciResponse::info_string(ignored).to_string()))
+}
+
+// The searchmoves or avoidmoves tokens of a go command that are moves at all
+fn go_moves(tokens: &[String]) -> Vec<ChessMove> {
+    tokens
+        .iter()
+        .filter_map(|token| ChessMove::from_str(token).ok())
+        .collect()
+}
+
+fn optional_path(value: &str) -> Option<PathBuf> {
+    match value {
+        "" | "<empty>" => None,
+        path => Some(PathBuf::from(path)),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PositionError {
+    Malformed(String),
+    IllegalMove(String),
+}
+
+impl fmt::Display for PositionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PositionError::Malformed(problem) => write!(f, "{}", problem),
+            PositionError::IllegalMove(problem) => write!(f, "illegal move {}", problem),
+        }
+    }
+}
+
+// The start board of a position command and its moves, each one legal. With `chess960` castling
+// comes as king takes rook and the FEN can use Shredder rights.
+pub fn parse_position(
+    start: &PositionStart,
+    tokens: &[String],
+    chess960: bool,
+) -> Result<(Board, Vec<ChessMove>), PositionError> {
+    let start = match start {
+        PositionStart::StartPos => Board::default(),
+        PositionStart::Fen(fen) => {
+            let mut fields: Vec<&str> = fen.split_whitespace().collect();
+            if fields.len() == 4 {
+                fields.extend(["0", "1"]); // Move counters are optional
+            }
+            let fen = fields.join(" ");
+            match chess960 {
+                true => chess960::parse_fen(&fen),
+                false => Board::from_str(&fen),
+            }
+            .map_err(|e| PositionError::Malformed(format!("bad FEN ({})", e)))?
+        }
+    };
+    let (start, moves, _) = extend_position((start, Vec::new(), start), tokens, 0, chess960)?;
+    Ok((start, moves))
+}
+
+// Plays the move tokens on from a start, the moves since it and the board they give. Plies count
+// from `first_ply`, for errors that match the whole command.
+pub fn extend_position(
+    (mut start, mut moves, mut board): (Board, Vec<ChessMove>, Board),
+    tokens: &[String],
+    first_ply: usize,
+    chess960: bool,
+) -> Result<(Board, Vec<ChessMove>, Board), PositionError> {
+    for (ply, token) in tokens.iter().enumerate() {
+        let ply = first_ply + ply;
+        // A null move, analysis passing the turn. There's no ChessMove for it, so the moves
+        // start again from the board after it.
+        if *token == "0000" {
+            board = board.null_move().ok_or_else(|| {
+                PositionError::IllegalMove(format!("0000 at ply {}, in check", ply + 1))
+            })?;
+            start = board;
+            moves.clear();
+            continue;
+        }
+        let chess_move = ChessMove::from_str(token)
+            .ok()
+            .map(|chess_move| match chess960 {
+                true => chess960::castling_in(&board, chess_move),
+                false => chess_move,
+            })
+            .filter(|chess_move| board.legal(*chess_move))
+            .ok_or_else(|| PositionError::IllegalMove(format!("{} at ply {}", token, ply + 1)))?;
+        board = board.make_move_new(chess_move);
+        moves.push(chess_move);
+    }
+    Ok((start, moves, board))
+}
+
+pub fn play_moves(start: Board, moves: &[ChessMove]) -> Board {
+    moves
+        .iter()
+        .fold(start, |board, chess_move| board.make_move_new(*chess_move))
+}
+
+pub fn load_position(line: &str, board: &mut Board) {
+    let parsed = match parse_command(line) {
+        Ok(UciCommand::Position { start, moves }) => {
+            parse_position(&start, &moves, false).map_err(|e| e.to_string())
+        }
+        Ok(_) => Err("not a position command".to_string()),
+        Err(e) => Err(e.to_string()),
+    };
+    match parsed {
+        Ok((start, moves)) => *board = play_moves(start, &moves),
+        Err(e) => warn!("Ignoring position: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backend::MockBackend;
+
+    #[test]
+    fn test_go_requests_search() {
+        let mut session = UciSession::new(None);
+        session.handle("position startpos moves e2e4");
+        match session.handle("go wtime 600000 btime 600000") {
+            Some(Reply::Search(request)) => {
+                assert_eq!(request.board, session.board);
+                // Opening weight, everything's still on the board
+                assert_eq!(
+                    request.settings.time_limit,
+                    Duration::from_secs(600) / 45 * 4 / 5
+                );
+                assert!(!request.fast);
+            }
+            _ => panic!("go should hand back a search"),
+        }
+        assert_eq!(session.game.ply_count(), 1);
+    }
+
+    #[test]
+    fn test_analysis_gos_dont_count_as_moves() {
+        // An analysis GUI asking about the same position over and over
+        let mut session = UciSession::new(None);
+        for _ in 0..300 {
+            session.handle("position startpos moves e2e4 e7e5");
+            session.handle("go wtime 600000 btime 600000");
+        }
+        assert_eq!(session.game.moves_made(), 1);
+        match session.handle("go wtime 600000 btime 600000") {
+            // Still the opening weight for our second move
+            Some(Reply::Search(request)) => assert_eq!(
+                request.settings.time_limit,
+                Duration::from_secs(600) / 44 * 4 / 5
+            ),
+            _ => panic!("go should hand back a search"),
+        }
+    }
+
+    #[test]
+    fn test_handle_command() {
+        let mut session = UciSession::new(None);
+        session.handle_command(UciCommand::Position {
+            start: PositionStart::StartPos,
+            moves: vec!["e2e4".to_string()],
+        });
+        let mut text = UciSession::new(None);
+        text.handle("position startpos moves e2e4");
+        assert_eq!(session.board, text.board);
+
+        let go = GoParams {
+            wtime: Some(Duration::from_secs(600)),
+            btime: Some(Duration::from_secs(600)),
+            ..Default::default()
+        };
+        match session.handle_command(UciCommand::Go(go)) {
+            Some(Reply::Search(request)) => assert_eq!(request.board, text.board),
+            _ => panic!("go should hand back a search"),
+        }
+    }
+
+    #[test]
+    fn test_warmup_in_the_background() {
+        let mut session = UciSession::new(None);
+        session.backend = Arc::new(MockBackend);
+        session.start_warmup();
+        assert!(session.warming.is_none()); // Off by default
+
+        session.handle("setoption name Startup Warmup value true");
+        assert!(session.warmed_up);
+        assert!(session.warming.is_some());
+        assert!(matches!(
+            session.handle("isready"),
+            Some(Reply::Output(out)) if out == "readyok"
+        ));
+        assert!(session.warming.is_none());
+        session.start_warmup();
+        assert!(session.warming.is_none()); // Once per cache
+    }
+
+    #[test]
+    fn test_repertoire_move_skips_search() {
+        let mut session = UciSession::new(None);
+        session.repertoire = Some(Repertoire::parse("1. e4 e5 2. Nf3").unwrap());
+        session.handle("position startpos moves e2e4 e7e5");
+        assert!(matches!(
+            session.handle("go wtime 600000 btime 600000"),
+            Some(Reply::Output(out)) if out == "bestmove g1f3"
+        ));
+
+        // Out of the line, back to searching
+        session.handle("position startpos moves d2d4");
+        assert!(matches!(
+            session.handle("go wtime 600000 btime 600000"),
+            Some(Reply::Search(_))
+        ));
+    }
+
+    #[test]
+    fn test_book_file_overrides_built_in() {
+        let mut book = PolyglotBook::default();
+        book.insert(&Board::default(), ChessMove::from_str("a2a3").unwrap(), 1);
+        let path = std::env::temp_dir().join("shallow-red-session-book.bin");
+        std::fs::write(&path, book.to_bytes()).unwrap();
+
+        let mut session = UciSession::new(None);
+        session.handle("setoption name OwnBook value true");
+        let reply = session.handle("go wtime 600000 btime 600000");
+        if cfg!(feature = "default-book") {
+            assert!(matches!(
+                reply,
+                Some(Reply::Output(out)) if out.starts_with("info string using built-in book\nbestmove ")
+            ));
+        } else {
+            assert!(matches!(reply, Some(Reply::Search(_))));
+        }
+
+        session.handle(&format!("setoption name BookFile value {}", path.display()));
+        assert!(matches!(
+            session.handle("go wtime 600000 btime 600000"),
+            Some(Reply::Output(out)) if out == "bestmove a2a3"
+        ));
+
+        // An unreadable book is answered, and the loaded one stays
+        let missing = std::env::temp_dir().join("shallow-red-no-such-book.bin");
+        assert!(matches!(
+            session.handle(&format!("setoption name BookFile value {}", missing.display())),
+            Some(Reply::Output(out)) if out.starts_with("info string BookFile can't be set")
+        ));
+        assert!(session.book_file.is_some());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_persisted_option_survives_restart() {
+        let path = std::env::temp_dir().join("shallow-red-persist-test.toml");
+        let _ = std::fs::remove_file(&path);
+
+        let mut session = UciSession::new(None);
+        session.load_config(path.clone());
+        session.handle("setoption name Startup Warmup value true"); // Before persisting was on
+        session.handle("setoption name Persist Options value true");
+        session.handle("setoption name Book Selection value best");
+
+        let mut restarted = UciSession::new(None);
+        restarted.load_config(path.clone());
+        assert_eq!(restarted.book_selection, SelectionPolicy::Best);
+        assert!(!restarted.warmup);
+        match restarted.handle("uci") {
+            Some(Reply::Output(out)) => {
+                assert!(out.contains("option name Book Selection type combo default best var best"))
+            }
+            _ => panic!("uci should answer"),
+        }
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_profile_applied_and_reverted() {
+        let mut session = UciSession::new(None);
+        session.profiles = vec![Profile {
+            name: "engines".to_string(),
+            opponent: Some("fish".to_string()),
+            options: vec![
+                ("OwnBook".to_string(), "true".to_string()),
+                ("Book Selection".to_string(), "best".to_string()),
+            ],
+            ..Default::default()
+        }];
+        session.handle("setoption name Book Selection value uniform");
+
+        session.handle("setoption name UCI_Opponent value none 3500 computer Stockfish");
+        assert!(session.own_book);
+        assert_eq!(session.book_selection, SelectionPolicy::Uniform); // The GUI's choice wins
+
+        session.handle("ucinewgame");
+        session.handle("setoption name UCI_Opponent value none 1500 human Someone");
+        assert!(!session.own_book);
+        assert!(session.active_profile.is_none());
+    }
+
+    #[test]
+    fn test_result_finishes_game() {
+        let dir = std::env::temp_dir();
+        let (pgn, career) = (
+            dir.join("shallow-red-result.pgn"),
+            dir.join("shallow-red-career.toml"),
+        );
+        let _ = std::fs::remove_file(&pgn);
+        let _ = std::fs::remove_file(&career);
+
+        let mut session = UciSession::new(None);
+        session.handle(&format!("setoption name PGN File value {}", pgn.display()));
+        session.handle(&format!(
+            "setoption name Career File value {}",
+            career.display()
+        ));
+        session.handle("position startpos moves e2e4");
+        session.handle("go wtime 600000 btime 600000");
+        session.handle("position startpos moves e2e4 e7e5 d1h5 b8c6 f1c4 g8f6 h5f7");
+        session.handle("result 2-0");
+        assert!(!pgn.exists());
+        session.handle("result 1-0 {White mates}");
+        session.handle("result 0-1"); // Too late, the game is over
+
+        let written = std::fs::read_to_string(&pgn).unwrap();
+        assert!(written.contains("[White \"?\"]\n[Black \"shallow-red\"]\n[Result \"1-0\"]"));
+        assert!(written.contains("4. Qxf7# {White mates} 1-0"));
+        assert_eq!(CareerStats::load(&career).losses, 1);
+
+        session.handle("ucinewgame");
+        session.handle("position startpos moves e2e4");
+        session.handle("go wtime 600000 btime 600000");
+        session.handle("gameresult 1/2-1/2");
+        let stats = CareerStats::load(&career);
+        assert_eq!((stats.games, stats.draws), (2, 1));
+        let _ = std::fs::remove_file(&pgn);
+        let _ = std::fs::remove_file(&career);
+    }
+
+    #[test]
+    fn test_reset_mid_search() {
+        let mut session = UciSession::new(Some(spawn_cache_manager()));
+        session.backend = Arc::new(MockBackend);
+        let (worker, completed) = SearchWorker::spawn();
+        session.worker = Some(worker);
+        let old_cache = session.cache.as_ref().unwrap().cache_ref.clone();
+
+        session.handle("setoption name OwnBook value true");
+        session.handle("position startpos moves e2e4 e7e5 g1f3");
+        session.handle("go wtime 600000 btime 600000"); // Still in flight
+        assert!(matches!(
+            session.handle("reset"),
+            Some(Reply::Output(out)) if out == "info string reset complete"
+        ));
+        let _ = completed.recv_timeout(Duration::from_secs(5)); // The stopped search's bestmove
+
+        assert!(!session.own_book);
+        assert!(session.gui_options.is_empty());
+        assert_eq!(
+            (session.board, session.game.ply_count()),
+            (Board::default(), 0)
+        );
+        let cache = &session.cache.as_ref().unwrap().cache_ref;
+        assert!(!Arc::ptr_eq(cache, &old_cache));
+
+        session.handle("setoption name Full Reset");
+        session.handle("position startpos moves d2d4");
+        session.handle("go wtime 600000 btime 600000");
+        let done = completed.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(done.output.starts_with("bestmove "));
+        assert_eq!(session.game.ply_count(), 1);
+    }
+
+    #[test]
+    fn test_ponder_hit_rate() {
+        let dir = std::env::temp_dir();
+        let (book_path, career) = (
+            dir.join("shallow-red-ponder-book.bin"),
+            dir.join("shallow-red-ponder-career.toml"),
+        );
+        let _ = std::fs::remove_file(&career);
+        let mut book = PolyglotBook::default();
+        let mut booked = Board::default();
+        load_position("position startpos moves e2e4 e7e5 g1f3 g8f6", &mut booked);
+        book.insert(&booked, ChessMove::from_str("f1c4").unwrap(), 1);
+        std::fs::write(&book_path, book.to_bytes()).unwrap();
+
+        let mut session = UciSession::new(None);
+        session.handle("setoption name Ponder value true");
+        session.handle("setoption name OwnBook value true");
+        session.handle(&format!(
+            "setoption name BookFile value {}",
+            book_path.display()
+        ));
+        session.handle(&format!(
+            "setoption name Career File value {}",
+            career.display()
+        ));
+        let clock = "wtime 60000 btime 60000";
+        for line in [
+            "position startpos",
+            "go", // Searched, so the GUI ponders on our PV
+            "position startpos moves e2e4 e7e5",
+            "go ponder",
+            "ponderhit",
+            "position startpos moves e2e4 e7e5 g1f3 b8c6",
+            "go ponder",
+            "stop", // They played something else
+            "position startpos moves e2e4 e7e5 g1f3 g8f6",
+            "go", // Book move
+            "position startpos moves e2e4 e7e5 g1f3 g8f6 f1c4 f8c5",
+            "go ponder",
+            "ponderhit",
+        ] {
+            match line.strip_prefix("go") {
+                Some(args) => session.handle(&format!("go{} {}", args, clock)),
+                None => session.handle(line),
+            };
+        }
+
+        let expected = "ponder started 3 hits 2 misses 1";
+        match session.handle("stats") {
+            Some(Reply::Output(out)) => assert!(out.contains(expected)),
+            _ => panic!("stats should answer"),
+        }
+        match session.handle("result 1-0") {
+            Some(Reply::Output(out)) => {
+                assert!(out.starts_with("info string game over 1-0 "));
+                assert!(out.contains(expected));
+                assert!(out.ends_with("(pv 1/2, book 1/1)"));
+            }
+            _ => panic!("result should answer with a summary"),
+        }
+        let total = CareerStats::load(&career).ponder.total();
+        assert_eq!((total.started, total.hits, total.misses), (3, 2, 1));
+
+        session.handle("ucinewgame");
+        assert_eq!(session.ponder.game.total().started, 0);
+        assert_eq!(session.ponder.session.total().started, 3);
+        let _ = std::fs::remove_file(&career);
+        let _ = std::fs::remove_file(&book_path);
+    }
+
+    #[test]
+    fn test_lazy_cache_on_first_go() {
+        let mut session = UciSession::with_lazy_cache();
+        session.handle("uci");
+        session.handle("isready");
+        session.handle("position startpos moves e2e4");
+        let lazy = session.lazy_cache.clone().unwrap();
+        assert!(!lazy.started());
+
+        match session.handle("go wtime 600000 btime 600000") {
+            Some(Reply::Search(request)) => {
+                let cache = request
+                    .settings
+                    .cache_settings
+                    .expect("first go gets the cache");
+                assert!(Arc::ptr_eq(&cache.cache_ref, &lazy.get().cache_ref));
+            }
+            _ => panic!("go should hand back a search"),
+        }
+
+        session.handle("reset");
+        let lazy = session.lazy_cache.clone().unwrap();
+        session.handle("setoption name Hash value 64");
+        assert!(lazy.started());
+        session.handle("isready");
+        assert!(lazy.is_initialized() && session.cache.is_some());
+    }
+
+    #[test]
+    fn test_avoidmoves() {
+        let mut session = UciSession::new(None);
+        session.backend = Arc::new(MockBackend);
+        let first = MoveGen::new_legal(&Board::default()).next().unwrap();
+        let second = MoveGen::new_legal(&Board::default()).nth(1).unwrap();
+        let go = |session: &mut UciSession, line: &str| match session.handle(line) {
+            Some(Reply::Search(request)) => {
+                let mut out = String::new();
+                request.run(|line| out = line);
+                out
+            }
+            _ => panic!("go should hand back a search"),
+        };
+
+        session.handle("position startpos");
+        let out = go(
+            &mut session,
+            &format!("go avoidmoves {} movetime 100", first),
+        );
+        assert!(out.ends_with(&format!("bestmove {}", second)));
+
+        session.handle(&format!("setoption name Avoid Moves value {}", second));
+        let out = go(
+            &mut session,
+            &format!("go movetime 100 avoidmoves {}", first),
+        );
+        let played = out.lines().last().unwrap();
+        assert!(
+            played != format!("bestmove {}", first) && played != format!("bestmove {}", second)
+        );
+
+        // Nothing left, so there's no search
+        let everything: Vec<String> = MoveGen::new_legal(&Board::default())
+            .map(|m| m.to_string())
+            .collect();
+        let go_everything = format!("go movetime 100 avoidmoves {}", everything.join(" "));
+        let Some(Reply::Output(out)) = session.handle(&go_everything) else {
+            panic!("go should be rejected");
+        };
+        assert_eq!(
+            out,
+            "info string every legal move is excluded, nothing to search\nbestmove 0000"
+        );
+        assert!(!session.set_option("Avoid Moves", "castle"));
+    }
+
+    #[test]
+    fn test_searchmoves() {
+        let mut session = UciSession::new(None);
+        session.backend = Arc::new(MockBackend);
+        session.handle("position startpos moves e2e4");
+        let request = match session.handle("go searchmoves e7e5 e7e4 zz d7d5 depth 3") {
+            Some(Reply::Search(request)) => *request,
+            _ => panic!("go should hand back a search"),
+        };
+        let root_moves = request.root_moves.clone().unwrap();
+        assert_eq!(root_moves.len(), 2);
+        assert!(["d7d5", "e7e5"]
+            .iter()
+            .all(|m| root_moves.contains(&ChessMove::from_str(m).unwrap())));
+        let mut out = String::new();
+        request.run(|output| out = output);
+        assert!(out.starts_with("info string searchmoves e7e4 zz not legal here, ignored\n"));
+        assert!(out.ends_with("bestmove d7d5") || out.ends_with("bestmove e7e5"));
+    }
+
+    #[test]
+    fn test_hash_keeps_cache() {
+        let mut session = UciSession::new(Some(spawn_cache_manager()));
+        let old_cache = session.cache.as_ref().unwrap().cache_ref.clone();
+        assert!(matches!(
+            session.handle("setoption name Hash value 256"),
+            Some(Reply::Output(out)) if out == format!("info string {}", HASH_NOTE)
+        ));
+        assert_eq!(session.hash_mb, 256);
+        // Nothing to size, so there's no reason to throw the table away
+        assert!(Arc::ptr_eq(
+            &session.cache.as_ref().unwrap().cache_ref,
+            &old_cache
+        ));
+        assert!(matches!(
+            session.handle("setoption name Hash value 0"),
+            Some(Reply::Output(_))
+        ));
+        assert_eq!(session.hash_mb, 256);
+    }
+
+    #[test]
+    fn test_null_moves() {
+        use chess::{Color, Piece, Square};
+        let mut session = UciSession::new(None);
+        session.handle("position startpos moves e2e4 0000 d2d4");
+        assert_eq!(session.board.side_to_move(), Color::Black);
+        for square in [Square::E4, Square::D4] {
+            assert_eq!(session.board.piece_on(square), Some(Piece::Pawn));
+            assert_eq!(session.board.color_on(square), Some(Color::White));
+        }
+        assert_eq!(
+            session.game.moves,
+            vec![ChessMove::from_str("d2d4").unwrap()]
+        );
+
+        let checked = "position fen 4k3/8/8/8/8/8/8/4R2K b - - moves 0000";
+        assert!(matches!(
+            session.handle(checked),
+            Some(Reply::Output(out)) if out.ends_with("illegal move 0000 at ply 1, in check")
+        ));
+    }
+
+    #[test]
+    fn test_go_when_game_is_over() {
+        let mut session = UciSession::new(None);
+        session.handle("position startpos moves f2f3 e7e5 g2g4 d8h4");
+        assert!(matches!(
+            session.handle("go wtime 600000 btime 600000"),
+            Some(Reply::Output(out)) if out == "info string no legal moves, checkmate\nbestmove 0000"
+        ));
+        session.handle("position fen 7k/5Q2/6K1/8/8/8/8/8 b - - 0 1");
+        assert!(matches!(
+            session.handle("go infinite"),
+            Some(Reply::Output(out)) if out.ends_with("stalemate\nbestmove 0000")
+        ));
+        assert_eq!(session.game.ply_count(), 1); // Black to move on move 1
+    }
+
+    #[test]
+    fn test_position_only_plays_new_moves() {
+        let mut session = UciSession::new(None);
+        session.handle("position startpos");
+        session.game.start_halfmoves = 7; // Only a full replay resets it
+        session.handle("position startpos moves e2e4");
+        session.handle("position startpos moves e2e4 e7e5");
+        assert_eq!(session.game.start_halfmoves, 7);
+        assert_eq!(session.game.moves.len(), 2);
+        assert!(matches!(
+            session.handle("position startpos moves e2e4 e7e5 e1e3"),
+            Some(Reply::Output(out)) if out.ends_with("illegal move e1e3 at ply 3")
+        ));
+
+        // A takeback or a board set some other way is played from the start
+        session.handle("position startpos moves e2e4");
+        assert_eq!(session.game.start_halfmoves, 0);
+        session.board = Board::default();
+        session.handle("position startpos moves e2e4 e7e5");
+        assert_eq!(
+            session.board,
+            play_moves(Board::default(), &session.game.moves)
+        );
+    }
+
+    #[test]
+    fn test_bad_position_keeps_board() {
+        let mut session = UciSession::new(None);
+        session.handle("position startpos moves e2e4 e7e5");
+        let good = session.board;
+        for (line, problem) in [
+            (
+                "position startpos moves e2e4 e7e5 g1g3",
+                "illegal move g1g3 at ply 3",
+            ),
+            (
+                "position startpos moves e2e4 zz",
+                "illegal move zz at ply 2",
+            ),
+            ("position fen 8/8/8 w - -", "bad FEN"),
+            ("position moves e2e4", "expected startpos or fen"),
+        ] {
+            match session.handle(line) {
+                Some(Reply::Output(out)) => assert!(
+                    out.starts_with(&format!("info string error position ignored, {}", problem)),
+                    "{}",
+                    out
+                ),
+                _ => panic!("{} should be answered", line),
+            }
+            assert_eq!(session.board, good);
+        }
+    }
+
+    #[test]
+    fn test_debug_on() {
+        let mut session = UciSession::new(None);
+        session.backend = Arc::new(MockBackend);
+        assert!(session.handle("frobnicate").is_none());
+
+        session.handle("debug on");
+        assert!(matches!(
+            session.handle("frobnicate"),
+            Some(Reply::Output(out)) if out == "info string unknown command frobnicate"
+        ));
+        match session.handle("go wtime 60000 btime 60000 movestogo 20") {
+            Some(Reply::Search(request)) => assert_eq!(
+                request.notice.as_deref(),
+                Some(
+                    "debug time 2400ms, clock 60000ms inc 0ms movestogo 20, manager classic, \
+                     cache none"
+                )
+            ),
+            _ => panic!("go should hand back a search"),
+        }
+
+        session.handle("debug off");
+        assert!(session.handle("frobnicate").is_none());
+    }
+
+    #[test]
+    fn test_chess960_castling() {
+        let mut session = UciSession::new(None);
+        session.backend = Arc::new(MockBackend);
+        let fen = "position fen r3k2r/pppppppp/8/8/8/8/PPPPPPPP/R3K2R w HAha - 0 1";
+        session.handle(&format!("{} moves e1h1", fen));
+        assert_eq!(session.board, Board::default()); // Shredder rights need the option
+
+        session.handle("setoption name UCI_Chess960 value true");
+        session.handle(&format!("{} moves e1h1", fen));
+        assert_eq!(
+            session.board.to_string(),
+            "r3k2r/pppppppp/8/8/8/8/PPPPPPPP/R4RK1 b kq - 0 1"
+        );
+    }
+
+    #[test]
+    fn test_ponder_option_off() {
+        let mut session = UciSession::new(None);
+        session.backend = Arc::new(MockBackend);
+        let (worker, _completed) = SearchWorker::spawn();
+        session.worker = Some(worker);
+        session.handle("position startpos moves e2e4");
+        session.handle("go ponder wtime 60000 btime 60000");
+        assert_eq!(session.ponder_budget, None); // Searched as a normal move
+        assert_eq!(session.ponder.game.total().started, 0);
+
+        session.handle("setoption name Ponder value true");
+        session.handle("go ponder wtime 60000 btime 60000");
+        assert!(session.ponder_budget.is_some());
+        assert_eq!(session.ponder.game.total().started, 1);
+        session.handle("stop");
+    }
+
+    #[test]
+    fn test_clear_hash() {
+        let mut session = UciSession::new(Some(spawn_cache_manager()));
+        let cache = session.cache.as_ref().unwrap().cache_ref.clone();
+        session.warmed_up = true;
+        assert!(matches!(
+            session.handle("setoption name Clear Hash"),
+            Some(Reply::Output(out)) if out == "info string hash cleared"
+        ));
+        assert!(Arc::ptr_eq(
+            &session.cache.as_ref().unwrap().cache_ref,
+            &cache
+        ));
+        assert!(!session.warmed_up);
+    }
+
+    #[test]
+    fn test_blank_input_ignored() {
+        let mut session = UciSession::new(None);
+        for blank in ["", "   ", "\t\r\n"] {
+            assert!(session.handle(blank).is_none());
+        }
+        assert!(session.handle("result").is_none());
+        assert!(matches!(
+            session.handle("setoption"),
+            Some(Reply::Output(out)) if out.starts_with("info string setoption takes")
+        ));
+        assert_eq!(session.board, Board::default());
+    }
+
+    #[test]
+    fn test_newgame_clears_hash() {
+        let mut session = UciSession::new(Some(spawn_cache_manager()));
+        let cache = session.cache.as_ref().unwrap().cache_ref.clone();
+        session.handle("position startpos moves e2e4");
+        session.warmed_up = true;
+        session.last_move_source = PredictionSource::Book;
+        session.handle("ucinewgame");
+        assert!(Arc::ptr_eq(
+            &session.cache.as_ref().unwrap().cache_ref,
+            &cache
+        ));
+        assert!(!session.warmed_up);
+        assert_eq!(session.last_move_source, PredictionSource::Pv);
+        assert_eq!(session.board, Board::default());
+    }
+
+    #[test]
+    fn test_setoption_answers() {
+        let mut session = UciSession::new(None);
+        assert!(session
+            .handle("setoption name bullet fast path value TRUE")
+            .is_none());
+        assert!(session.fast_path);
+        assert!(session.gui_options.contains("Bullet Fast Path"));
+
+        let answer = |session: &mut UciSession, line: &str| match session.handle(line) {
+            Some(Reply::Output(out)) => out,
+            _ => panic!("{} should be answered", line),
+        };
+        assert_eq!(
+            answer(&mut session, "setoption name Frobnicate value 3"),
+            "info string unknown option Frobnicate"
+        );
+        assert_eq!(
+            answer(&mut session, "setoption name OwnBook value maybe"),
+            "info string OwnBook is true or false"
+        );
+        assert_eq!(
+            answer(&mut session, "setoption name Time Manager value psychic"),
+            "info string Time Manager can't be set to psychic"
+        );
+        assert!(
+            answer(&mut session, "setoption value 3").starts_with("info string setoption takes")
+        );
+    }
+
+    #[test]
+    fn test_go_movetime() {
+        let mut session = UciSession::new(None);
+        session.handle("position startpos moves e2e4");
+        for manager in ["classic", "increment"] {
+            session.handle(&format!("setoption name Time Manager value {}", manager));
+            match session.handle("go movetime 1000 wtime 5000 btime 90000") {
+                Some(Reply::Search(request)) => {
+                    assert_eq!(request.settings.time_limit, Duration::from_secs(1))
+                }
+                _ => panic!("go should hand back a search"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_go_depth_unsupported() {
+        let go = |line: &str| {
+            let mut session = UciSession::new(None);
+            session.backend = Arc::new(MockBackend);
+            match session.handle(line) {
+                Some(Reply::Search(request)) => *request,
+                _ => panic!("go should hand back a search"),
+            }
+        };
+        let clock_only = go("go wtime 60000 btime 60000");
+        let request = go("go depth 8 wtime 60000 btime 60000");
+        // Searched on the clock, the depth is only mentioned
+        assert_eq!(request.settings.time_limit, clock_only.settings.time_limit);
+        assert_eq!(request.hard_limit, clock_only.hard_limit);
+        let mut out = String::new();
+        request.run(|output| out = output);
+        assert!(out.starts_with("info string depth 8 isn't supported"));
+        assert!(out.lines().last().unwrap().starts_with("bestmove "));
+    }
+
+    #[test]
+    fn test_command_line_defaults() {
+        let mut session = UciSession::new(None);
+        assert_eq!(session.set_default_option("threads", "4"), Ok(()));
+        assert_eq!(session.threads, 4);
+        assert!(session.set_default_option("Threads", "0").is_err());
+        assert!(session.set_default_option("Threadz", "2").is_err());
+        match session.handle("uci") {
+            Some(Reply::Output(out)) => {
+                assert!(out.contains("option name Threads type spin default 4 min 1 max 64"))
+            }
+            _ => panic!("uci should answer"),
+        }
+    }
+
+    #[test]
+    fn test_engine_identity() {
+        let mut session = UciSession::new(None);
+        session.engine_name = "Shallow Red Tweaked".to_string();
+        session.engine_author = "someone".to_string();
+        match session.handle("uci") {
+            Some(Reply::Output(out)) => {
+                assert!(out.starts_with("id name Shallow Red Tweaked\nid author someone\n"))
+            }
+            _ => panic!("uci should answer"),
+        }
+    }
+
+    #[test]
+    fn test_nodestime_unsupported() {
+        // The engine can't count nodes, so there's no clock in nodes to offer
+        let mut session = UciSession::new(None);
+        assert!(matches!(
+            session.handle("setoption name nodestime value 1000"),
+            Some(Reply::Output(out)) if out == "info string unknown option nodestime"
+        ));
+        session.handle("position startpos moves e2e4");
+        match session.handle("go wtime 60000 btime 60000 movestogo 20") {
+            Some(Reply::Search(request)) => {
+                // The opening's weight on 60s over 20 moves
+                assert_eq!(request.notice, None);
+                assert_eq!(request.settings.time_limit, Duration::from_millis(2400));
+            }
+            _ => panic!("go should hand back a search"),
+        }
+    }
+
+    #[test]
+    fn test_go_nodes_unsupported() {
+        let go = |line: &str| {
+            let mut session = UciSession::new(None);
+            session.backend = Arc::new(MockBackend);
+            match session.handle(line) {
+                Some(Reply::Search(request)) => *request,
+                _ => panic!("go should hand back a search"),
+            }
+        };
+        let clock_only = go("go wtime 60000 btime 60000");
+        let request = go("go nodes 500000 wtime 60000 btime 60000");
+        assert_eq!(request.settings.time_limit, clock_only.settings.time_limit);
+        assert_eq!(request.hard_limit, clock_only.hard_limit);
+        let mut out = String::new();
+        request.run(|output| out = output);
+        assert!(out.starts_with("info string nodes 500000 isn't supported"));
+        assert!(out.lines().last().unwrap().starts_with("bestmove "));
+
+        let both = go("go depth 8 nodes 500000 movetime 100");
+        assert_eq!(
+            both.notice.as_deref(),
+            Some(
+                "depth 8 isn't supported, the engine can't stop at a depth\n\
+                 info string nodes 500000 isn't supported, the engine doesn't count nodes"
+            )
+        );
+        assert_eq!(both.settings.time_limit, Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_answered_go_waits_for_the_last_search() {
+        let mut session = UciSession::new(None);
+        session.backend = Arc::new(MockBackend);
+        let (worker, completed) = SearchWorker::spawn();
+        session.worker = Some(worker);
+        assert!(session.handle("go infinite").is_none()); // Held until stop
+
+        // Fool's mate, go is answered at once
+        session.handle("position startpos moves f2f3 e7e5 g2g4 d8h4");
+        assert!(matches!(
+            session.handle("go wtime 600000 btime 600000"),
+            Some(Reply::Output(out)) if out.ends_with("bestmove 0000")
+        ));
+        // The infinite search's bestmove was already out by then
+        let sent: Vec<String> = completed.try_iter().map(|done| done.output).collect();
+        assert!(
+            sent.iter().any(|out| out.starts_with("bestmove ")),
+            "{:?}",
+            sent
+        );
+    }
+
+    #[test]
+    fn test_go_infinite_limits() {
+        let mut session = UciSession::new(None);
+        session.backend = Arc::new(MockBackend);
+        match session.handle("go infinite") {
+            Some(Reply::Search(request)) => {
+                // Inline, nothing could stop it
+                assert_eq!(request.settings.time_limit, INLINE_INFINITE_CAP);
+                assert!(!request.until_stop);
+                assert!(request.notice.unwrap().contains("can't be stopped here"));
+            }
+            _ => panic!("go should hand back a search"),
+        }
+        let (worker, _completed) = SearchWorker::spawn();
+        session.worker = Some(worker);
+        let go = GoParams {
+            infinite: true,
+            ..GoParams::default()
+        };
+        let context = TimeContext::from_go(&go, &session.board, 0);
+        assert_eq!(session.time_limit(&context), (INFINITE_TIME_LIMIT, None));
+    }
+
+    #[test]
+    fn test_go_mate() {
+        let mut session = UciSession::new(None);
+        session.backend = Arc::new(MockBackend);
+        session.handle("position fen 6k1/5ppp/8/8/8/8/5PPP/3R2K1 w - - 0 1");
+        let request = match session.handle("go mate 2") {
+            Some(Reply::Search(request)) => *request,
+            _ => panic!("go should hand back a search"),
+        };
+        assert_eq!(request.settings.time_limit, MATE_TIME_LIMIT);
+        let mut out = String::new();
+        request.run(|output| out = output);
+        assert_eq!(out, "info depth 1 score mate 1 pv d1d8\nbestmove d1d8");
+
+        // No mate to be had, the engine's move as usual
+        session.handle("position startpos");
+        match session.handle("go mate 1 movetime 50") {
+            Some(Reply::Search(request)) => request.run(|output| out = output),
+            _ => panic!("go should hand back a search"),
+        }
+        assert!(out.starts_with("bestmove "));
+    }
+
+    #[test]
+    fn test_position_fen() {
+        let mut session = UciSession::new(None);
+        session.handle(
+            "position fen r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1 \
+             moves e1g1 a8b8",
+        );
+        assert_eq!(
+            session.board,
+            Board::from_str("1r2k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R4RK1 w k - 2 2")
+                .unwrap()
+        );
+        assert_eq!(session.game.moves.len(), 2);
+
+        // Counters left off, and bad input leaves the board alone
+        session.handle("position fen 8/8/8/8/8/8/k7/4K2R w K -");
+        let board = session.board;
+        assert_eq!(
+            board,
+            Board::from_str("8/8/8/8/8/8/k7/4K2R w K - 0 1").unwrap()
+        );
+        session.handle("position fen 8/8/8/8/8/8/k7/4K2R w K - 0 1 moves e1e3");
+        session.handle("position fen not/a/fen w - - 0 1");
+        assert_eq!(session.board, board);
+    }
+}
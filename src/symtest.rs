@@ -0,0 +1,263 @@
+// Evaluation symmetry check: a position and its color flipped mirror should score the same for
+// the side to move. Anything else points at an asymmetric eval.
+use chess::Board;
+use shallow_red_engine::utils::engine_interface::EngineSettings;
+use std::{fs, path::Path, str::FromStr, time::Duration};
+
+use crate::{backend::SearchBackend, display::Score};
+
+const TOLERANCE_CP: i32 = 10;
+const SYMTEST_TIME: Duration = Duration::from_millis(100); // Short search per position
+
+const DEFAULT_POSITIONS: [&str; 6] = [
+    "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+    "r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3",
+    "r1bqk2r/pppp1ppp/2n2n2/2b1p3/2B1P3/3P1N2/PPP2PPP/RNBQK2R w KQkq - 1 5",
+    "rnbqkbnr/ppp1pppp/8/8/3pP3/8/PPPP1PPP/RNBQKBNR b Kq e3 0 3",
+    "8/5pk1/6p1/8/3R4/6P1/5PK1/2r5 w - - 0 40",
+    "6k1/5ppp/8/8/8/8/1q3PPP/3Q2K1 b - - 0 30",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Asymmetry {
+    pub board: Board,
+    pub score: Score,
+    pub mirrored: Score,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SymReport {
+    pub positions: usize,
+    pub asymmetric: Vec<Asymmetry>,
+    pub unscored: usize, // Backend didn't report a score for one side or both
+}
+
+impl SymReport {
+    pub fn passed(&self) -> bool {
+        self.asymmetric.is_empty()
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines: Vec<String> = self
+            .asymmetric
+            .iter()
+            .map(|a| {
+                format!(
+                    "info string symtest {} scores {:?}, mirrored {:?}",
+                    a.board, a.score, a.mirrored
+                )
+            })
+            .collect();
+        lines.push(format!(
+            "info string symtest {} positions, {} asymmetric, {} unscored",
+            self.positions,
+            self.asymmetric.len(),
+            self.unscored
+        ));
+        lines
+    }
+}
+
+// Flip ranks and swap colors, side to move, castling rights and the en passant rank
+pub fn mirror(board: &Board) -> Board {
+    let fen = board.to_string();
+    let fields: Vec<&str> = fen.split_whitespace().collect();
+
+    let placement = fields[0]
+        .split('/')
+        .rev()
+        .map(swap_case)
+        .collect::<Vec<String>>()
+        .join("/");
+    let side = if fields[1] == "w" { "b" } else { "w" };
+    let castling = match fields[2] {
+        "-" => "-".to_string(),
+        rights => {
+            let swapped = swap_case(rights);
+            "KQkq".chars().filter(|c| swapped.contains(*c)).collect()
+        }
+    };
+    let en_passant = match fields[3] {
+        "-" => "-".to_string(),
+        square => {
+            let (file, rank) = square.split_at(1);
+            format!(
+                "{}{}",
+                file,
+                9 - rank.parse::<u8>().expect("en passant rank")
+            )
+        }
+    };
+
+    let mirrored = [&placement, side, &castling, &en_passant]
+        .into_iter()
+        .chain(fields[4..].iter().copied())
+        .collect::<Vec<&str>>()
+        .join(" ");
+    Board::from_str(&mirrored).expect("Mirrored position should be valid")
+}
+
+fn swap_case(s: &str) -> String {
+    s.chars()
+        .map(|c| {
+            if c.is_ascii_uppercase() {
+                c.to_ascii_lowercase()
+            } else {
+                c.to_ascii_uppercase()
+            }
+        })
+        .collect()
+}
+
+// Built in positions, plus the ones in `file` (EPD or FEN, one per line)
+pub fn load_positions(file: Option<&Path>) -> Result<Vec<Board>, String> {
+    let mut positions: Vec<Board> = DEFAULT_POSITIONS
+        .iter()
+        .map(|fen| Board::from_str(fen).expect("Built in position should be valid"))
+        .collect();
+    if let Some(file) = file {
+        let text = fs::read_to_string(file)
+            .map_err(|e| format!("can't read {}: {}", file.display(), e))?;
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let fields: Vec<&str> = line.split_whitespace().take(4).collect();
+            let fen = fields.join(" ") + " 0 1";
+            positions.push(Board::from_str(&fen).map_err(|_| format!("bad position {}", line))?);
+        }
+    }
+    Ok(positions)
+}
+
+fn centipawns(score: Score) -> i32 {
+    match score {
+        Score::Centipawns(cp) => cp,
+        Score::Mate(moves) => moves.signum() * 100_000 - moves, // Nearer mates score further out
+    }
+}
+
+pub fn symtest(backend: &dyn SearchBackend, positions: &[Board]) -> SymReport {
+    let score = |board: Board| {
+        let settings = EngineSettings {
+            verbose: false,
+            time_limit: SYMTEST_TIME,
+            ..Default::default()
+        };
+        backend.search(board, settings).score
+    };
+
+    let mut report = SymReport {
+        positions: positions.len(),
+        ..Default::default()
+    };
+    for board in positions {
+        match (score(*board), score(mirror(board))) {
+            (Some(original), Some(mirrored))
+                if (centipawns(original) - centipawns(mirrored)).abs() > TOLERANCE_CP =>
+            {
+                report.asymmetric.push(Asymmetry {
+                    board: *board,
+                    score: original,
+                    mirrored,
+                })
+            }
+            (Some(_), Some(_)) => {}
+            _ => report.unscored += 1,
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::SearchOutcome;
+    use chess::{Color, MoveGen, Piece, ALL_SQUARES};
+
+    fn board(fen: &str) -> Board {
+        Board::from_str(fen).unwrap()
+    }
+
+    #[test]
+    fn test_mirror_startpos() {
+        let mirrored = mirror(&Board::default());
+        assert_eq!(mirrored.side_to_move(), Color::Black);
+        assert_eq!(mirrored, Board::default().null_move().unwrap());
+    }
+
+    #[test]
+    fn test_mirror_castling_and_en_passant() {
+        let original = board("rnbqkbnr/ppp1pppp/8/8/3pP3/8/PPPP1PPP/RNBQKBNR b Kq e3 0 3");
+        let expected = board("rnbqkbnr/pppp1ppp/8/3Pp3/8/8/PPP1PPPP/RNBQKBNR w Qk e6 0 3");
+        assert_eq!(mirror(&original), expected);
+        assert_eq!(mirror(&expected), original);
+    }
+
+    #[test]
+    fn test_mirror_round_trip() {
+        for fen in DEFAULT_POSITIONS {
+            let original = board(fen);
+            assert_eq!(mirror(&mirror(&original)), original);
+            // Same number of legal moves for the side to move on both sides
+            assert_eq!(
+                MoveGen::new_legal(&original).len(),
+                MoveGen::new_legal(&mirror(&original)).len()
+            );
+        }
+    }
+
+    // Material count for the side to move, plus a bonus for white that a buggy eval might have
+    struct MaterialBackend {
+        white_bonus: i32,
+    }
+
+    impl SearchBackend for MaterialBackend {
+        fn search(&self, board: Board, _settings: EngineSettings) -> SearchOutcome {
+            let value = |piece| match piece {
+                Piece::Pawn => 100,
+                Piece::Knight | Piece::Bishop => 300,
+                Piece::Rook => 500,
+                Piece::Queen => 900,
+                Piece::King => 0,
+            };
+            let mut white = self.white_bonus;
+            for square in ALL_SQUARES {
+                if let (Some(piece), Some(color)) = (board.piece_on(square), board.color_on(square))
+                {
+                    white += if color == Color::White {
+                        value(piece)
+                    } else {
+                        -value(piece)
+                    };
+                }
+            }
+            let cp = if board.side_to_move() == Color::White {
+                white
+            } else {
+                -white
+            };
+            SearchOutcome {
+                best_move: MoveGen::new_legal(&board).next().unwrap_or_default(),
+                score: Some(Score::Centipawns(cp)),
+                nodes: None,
+            }
+        }
+    }
+
+    #[test]
+    fn test_symtest_flags_asymmetric_eval() {
+        let positions = load_positions(None).unwrap();
+        let fair = symtest(&MaterialBackend { white_bonus: 0 }, &positions);
+        assert!(fair.passed());
+        assert_eq!(fair.unscored, 0);
+
+        let biased = symtest(&MaterialBackend { white_bonus: 50 }, &positions);
+        assert_eq!(biased.asymmetric.len(), positions.len());
+        assert!(biased.lines().last().unwrap().contains("6 asymmetric"));
+    }
+
+    #[test]
+    fn test_unscored_backend() {
+        let report = symtest(&crate::backend::MockBackend, &load_positions(None).unwrap());
+        assert!(report.passed());
+        assert_eq!(report.unscored, report.positions);
+    }
+}
@@ -0,0 +1,396 @@
+// Endgame tablebase probing behind a trait, so `tbprobe` and the root move can be tested
+// against a mocked prober. Scores are always from the side to move's point of view.
+use chess::{Board, CastleRights, ChessMove, Color, MoveGen, Piece, ALL_PIECES};
+use std::fmt;
+
+// Worst to best
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Wdl {
+    Loss,
+    BlessedLoss, // Lost, but drawn under the 50 move rule
+    Draw,
+    CursedWin, // Won, but drawn under the 50 move rule
+    Win,
+}
+
+impl Wdl {
+    // Same result from the other side
+    pub fn flip(self) -> Wdl {
+        match self {
+            Wdl::Loss => Wdl::Win,
+            Wdl::BlessedLoss => Wdl::CursedWin,
+            Wdl::Draw => Wdl::Draw,
+            Wdl::CursedWin => Wdl::BlessedLoss,
+            Wdl::Win => Wdl::Loss,
+        }
+    }
+}
+
+impl fmt::Display for Wdl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Wdl::Loss => write!(f, "loss"),
+            Wdl::BlessedLoss => write!(f, "blessed loss"),
+            Wdl::Draw => write!(f, "draw"),
+            Wdl::CursedWin => write!(f, "cursed win"),
+            Wdl::Win => write!(f, "win"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProbeError {
+    NotConfigured,
+    MissingTable(String), // Material key of the table, e.g. KRvK
+    TooManyPieces { pieces: u32, max: u32 },
+    CastlingRights,
+    Failed(String), // The tables are there but the probe went wrong
+}
+
+impl fmt::Display for ProbeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProbeError::NotConfigured => write!(f, "no tablebases configured (set SyzygyPath)"),
+            ProbeError::MissingTable(key) => write!(f, "missing table file for {}", key),
+            ProbeError::TooManyPieces { pieces, max } => {
+                write!(
+                    f,
+                    "{} pieces on the board, tables cover at most {}",
+                    pieces, max
+                )
+            }
+            ProbeError::CastlingRights => {
+                write!(
+                    f,
+                    "castling rights present, tablebases don't cover castling"
+                )
+            }
+            ProbeError::Failed(e) => write!(f, "probe failed: {}", e),
+        }
+    }
+}
+
+pub trait Prober: Send + Sync {
+    fn max_pieces(&self) -> u32;
+    fn wdl(&self, board: &Board) -> Result<Wdl, ProbeError>;
+    fn dtz(&self, board: &Board) -> Result<Option<i32>, ProbeError>; // None when the tables have no DTZ
+}
+
+// Used until tablebases are configured
+pub struct NoTablebases;
+
+impl Prober for NoTablebases {
+    fn max_pieces(&self) -> u32 {
+        0
+    }
+
+    fn wdl(&self, _board: &Board) -> Result<Wdl, ProbeError> {
+        Err(ProbeError::NotConfigured)
+    }
+
+    fn dtz(&self, _board: &Board) -> Result<Option<i32>, ProbeError> {
+        Err(ProbeError::NotConfigured)
+    }
+}
+
+// Syzygy files from the SyzygyPath directories, read through shakmaty-syzygy
+#[cfg(not(target_arch = "wasm32"))]
+pub struct SyzygyTables {
+    tables: shakmaty_syzygy::Tablebase<shakmaty::Chess>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl SyzygyTables {
+    // Directories separated like PATH entries, fails if none of them had any tables
+    pub fn open(path: &str) -> Result<SyzygyTables, String> {
+        let mut tables = shakmaty_syzygy::Tablebase::new();
+        let mut found = 0;
+        for dir in std::env::split_paths(path) {
+            match tables.add_directory(&dir) {
+                Ok(count) => found += count,
+                Err(e) => log::warn!("Couldn't read tablebases in {}: {}", dir.display(), e),
+            }
+        }
+        if found == 0 {
+            return Err(format!("no tablebase files in {}", path));
+        }
+        log::info!("Loaded {} tablebase files from {}", found, path);
+        Ok(SyzygyTables { tables })
+    }
+
+    fn position(board: &Board) -> Result<shakmaty::Chess, ProbeError> {
+        let fen: shakmaty::fen::Fen = board.to_string().parse().expect("Board FEN should parse");
+        fen.into_position(shakmaty::CastlingMode::Standard)
+            .map_err(|e| ProbeError::Failed(e.to_string()))
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Prober for SyzygyTables {
+    fn max_pieces(&self) -> u32 {
+        self.tables.max_pieces() as u32
+    }
+
+    fn wdl(&self, board: &Board) -> Result<Wdl, ProbeError> {
+        use shakmaty_syzygy::Wdl as Syzygy;
+        // The chess crate doesn't keep the halfmove clock, so every position is treated as fresh
+        let wdl = self
+            .tables
+            .probe_wdl_after_zeroing(&Self::position(board)?)
+            .map_err(|e| syzygy_error(board, e))?;
+        Ok(match wdl {
+            Syzygy::Loss => Wdl::Loss,
+            Syzygy::BlessedLoss => Wdl::BlessedLoss,
+            Syzygy::Draw => Wdl::Draw,
+            Syzygy::CursedWin => Wdl::CursedWin,
+            Syzygy::Win => Wdl::Win,
+        })
+    }
+
+    fn dtz(&self, board: &Board) -> Result<Option<i32>, ProbeError> {
+        use shakmaty_syzygy::{Metric, SyzygyError};
+        match self.tables.probe_dtz(&Self::position(board)?) {
+            Ok(dtz) => Ok(Some(dtz.ignore_rounding().0)),
+            Err(SyzygyError::MissingTable {
+                metric: Metric::Dtz,
+                ..
+            }) => Ok(None),
+            Err(e) => Err(syzygy_error(board, e)),
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn syzygy_error(board: &Board, e: shakmaty_syzygy::SyzygyError) -> ProbeError {
+    match e {
+        shakmaty_syzygy::SyzygyError::MissingTable { .. } => {
+            ProbeError::MissingTable(material_key(board))
+        }
+        e => ProbeError::Failed(e.to_string()),
+    }
+}
+
+// Whether the position can be in a table at all, checked before going near the prober
+pub fn probeable(prober: &dyn Prober, board: &Board) -> Result<(), ProbeError> {
+    let max = prober.max_pieces();
+    if max == 0 {
+        return Err(ProbeError::NotConfigured);
+    }
+    let pieces = board.combined().popcnt();
+    if pieces > max {
+        return Err(ProbeError::TooManyPieces { pieces, max });
+    }
+    if [Color::White, Color::Black]
+        .iter()
+        .any(|color| board.castle_rights(*color) != CastleRights::NoRights)
+    {
+        return Err(ProbeError::CastlingRights);
+    }
+    Ok(())
+}
+
+// Table name for the material on the board, stronger side first: KQvK, KRPvKR...
+pub fn material_key(board: &Board) -> String {
+    let side = |color: Color| -> String {
+        ALL_PIECES
+            .iter()
+            .rev()
+            .map(|piece| {
+                let count = (board.pieces(*piece) & board.color_combined(color)).popcnt();
+                piece_letter(*piece).to_string().repeat(count as usize)
+            })
+            .collect()
+    };
+    let (white, black) = (side(Color::White), side(Color::Black));
+    if black.len() > white.len() {
+        format!("{}v{}", black, white)
+    } else {
+        format!("{}v{}", white, black)
+    }
+}
+
+fn piece_letter(piece: Piece) -> char {
+    match piece {
+        Piece::Pawn => 'P',
+        Piece::Knight => 'N',
+        Piece::Bishop => 'B',
+        Piece::Rook => 'R',
+        Piece::Queen => 'Q',
+        Piece::King => 'K',
+    }
+}
+
+// The tbprobe debug command: WDL and DTZ of the position, then what every legal move leads to
+pub fn tbprobe(prober: &dyn Prober, board: &Board) -> Vec<String> {
+    let probed = probeable(prober, board).and_then(|_| {
+        let wdl = prober.wdl(board)?;
+        let dtz = prober.dtz(board)?;
+        Ok((wdl, dtz))
+    });
+    let (wdl, dtz) = match probed {
+        Ok(probed) => probed,
+        Err(e) => return vec![format!("info string tbprobe {}", e)],
+    };
+
+    let mut lines = vec![match dtz {
+        Some(dtz) => format!(
+            "info string tbprobe {} wdl {} dtz {}",
+            material_key(board),
+            wdl,
+            dtz
+        ),
+        None => format!(
+            "info string tbprobe {} wdl {} dtz unavailable",
+            material_key(board),
+            wdl
+        ),
+    }];
+    for chess_move in MoveGen::new_legal(board) {
+        let outcome = match prober.wdl(&board.make_move_new(chess_move)) {
+            Ok(wdl) => wdl.flip().to_string(),
+            Err(e) => e.to_string(),
+        };
+        lines.push(format!("info string tbprobe {} {}", chess_move, outcome));
+    }
+    lines
+}
+
+// The move to play straight from the tables: the best result, then for a win the quickest way to
+// a zeroing move, for a loss the slowest. None when the position or any move isn't covered.
+pub fn root_move(
+    prober: &dyn Prober,
+    board: &Board,
+    allowed: Option<&[ChessMove]>,
+) -> Option<(ChessMove, Wdl)> {
+    probeable(prober, board).ok()?;
+    let mut best: Option<((Wdl, i32, i32), ChessMove)> = None;
+    for chess_move in MoveGen::new_legal(board) {
+        if allowed.is_some_and(|allowed| !allowed.contains(&chess_move)) {
+            continue;
+        }
+        let after = board.make_move_new(chess_move);
+        let wdl = prober.wdl(&after).ok()?.flip();
+        let dtz = prober.dtz(&after).ok()?.unwrap_or(0).abs();
+        let zeroing = board.piece_on(chess_move.get_source()) == Some(Piece::Pawn)
+            || board.piece_on(chess_move.get_dest()).is_some();
+        let progress = match wdl {
+            Wdl::Win | Wdl::CursedWin => (zeroing as i32, -dtz),
+            Wdl::Loss | Wdl::BlessedLoss => (-(zeroing as i32), dtz),
+            Wdl::Draw => (0, 0),
+        };
+        let key = (wdl, progress.0, progress.1);
+        if best.is_none_or(|(best_key, _)| key > best_key) {
+            best = Some((key, chess_move));
+        }
+    }
+    best.map(|((wdl, _, _), chess_move)| (chess_move, wdl))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    // Up to 4 pieces, KQvK only: whoever has the queen wins, bare kings draw, the rest is missing
+    struct MockProber;
+
+    impl Prober for MockProber {
+        fn max_pieces(&self) -> u32 {
+            4
+        }
+
+        fn wdl(&self, board: &Board) -> Result<Wdl, ProbeError> {
+            let queens =
+                |color| (board.pieces(Piece::Queen) & board.color_combined(color)).popcnt();
+            match material_key(board).as_str() {
+                "KQvK" if queens(board.side_to_move()) == 1 => Ok(Wdl::Win),
+                "KQvK" => Ok(Wdl::Loss),
+                "KvK" => Ok(Wdl::Draw),
+                key => Err(ProbeError::MissingTable(key.to_string())),
+            }
+        }
+
+        fn dtz(&self, board: &Board) -> Result<Option<i32>, ProbeError> {
+            self.wdl(board).map(|wdl| match wdl {
+                Wdl::Draw => Some(0),
+                _ => None,
+            })
+        }
+    }
+
+    fn board(fen: &str) -> Board {
+        Board::from_str(fen).unwrap()
+    }
+
+    #[test]
+    fn test_probe_position_and_moves() {
+        // The queen on b2 can be taken by the king on a1
+        let lines = tbprobe(&MockProber, &board("8/8/8/8/8/8/1q6/K6k w - - 0 1"));
+        assert_eq!(
+            lines[0],
+            "info string tbprobe KQvK wdl loss dtz unavailable"
+        );
+        assert!(lines.contains(&"info string tbprobe a1b2 draw".to_string()));
+        assert_eq!(
+            lines.len(),
+            1 + MoveGen::new_legal(&board("8/8/8/8/8/8/1q6/K6k w - - 0 1")).len()
+        );
+    }
+
+    #[test]
+    fn test_distinct_failures() {
+        let missing = tbprobe(&MockProber, &board("8/8/8/8/8/8/1r6/K6k w - - 0 1"));
+        assert_eq!(
+            missing,
+            vec!["info string tbprobe missing table file for KRvK"]
+        );
+
+        let crowded = tbprobe(&MockProber, &board("8/8/8/8/8/8/PPPPPPPP/K6k w - - 0 1"));
+        assert_eq!(
+            crowded,
+            vec!["info string tbprobe 10 pieces on the board, tables cover at most 4"]
+        );
+
+        let castling = tbprobe(&MockProber, &board("4k3/8/8/8/8/8/8/R3K3 w Q - 0 1"));
+        assert_eq!(
+            castling,
+            vec!["info string tbprobe castling rights present, tablebases don't cover castling"]
+        );
+
+        let unconfigured = tbprobe(&NoTablebases, &board("8/8/8/8/8/8/1q6/K6k w - - 0 1"));
+        assert_eq!(
+            unconfigured,
+            vec!["info string tbprobe no tablebases configured (set SyzygyPath)"]
+        );
+    }
+
+    #[test]
+    fn test_root_move() {
+        // Taking the queen draws, the king's other move loses
+        let checked = board("8/8/8/8/8/1q6/K7/7k w - - 0 1");
+        let (chess_move, wdl) = root_move(&MockProber, &checked, None).unwrap();
+        assert_eq!((chess_move.to_string().as_str(), wdl), ("a2b3", Wdl::Draw));
+
+        let a2a1 = ChessMove::from_str("a2a1").unwrap();
+        let lost = root_move(&MockProber, &checked, Some(&[a2a1]));
+        assert_eq!(lost, Some((a2a1, Wdl::Loss)));
+
+        // KRvK isn't in the mock, so that's left to the search
+        assert_eq!(
+            root_move(&MockProber, &board("8/8/8/8/8/1r6/K7/7k w - - 0 1"), None),
+            None
+        );
+    }
+
+    #[test]
+    fn test_material_key() {
+        assert_eq!(
+            material_key(&board("8/8/8/8/8/8/1q6/K6k w - - 0 1")),
+            "KQvK"
+        );
+        assert_eq!(
+            material_key(&board("4k3/4r3/8/8/8/8/4PP2/4K2R w - - 0 1")),
+            "KRPPvKR"
+        );
+    }
+}
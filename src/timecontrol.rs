@@ -1,22 +1,579 @@
+// How long to think. The go handler fills in a TimeContext and asks whichever TimeManager the
+// Time Manager option picked for a budget.
+use chess::{Board, Color, MoveGen};
+use parking_lot::Mutex;
 use std::time::Duration;
 
-pub(crate) fn thinking_time(moves_played: u8, time_remaining: Duration) -> Duration {
-    let game_moves_expected: u8 = 45; // Expect ~40 moves per game
+use crate::{capture::centipawns, command::GoParams, display::Score};
 
-    let moves_left = std::cmp::max(game_moves_expected - moves_played, 10); // Always assume we have 10 moves left 
+pub const TIME_MANAGERS: [&str; 2] = ["classic", "increment"];
+pub const DEFAULT_SAFETY_BUFFER_MS: i64 = 100;
+pub const DEFAULT_MAX_MOVE_SHARE: i64 = 20; // Percent
+pub const DEFAULT_SLOW_MOVER: i64 = 100; // Percent
+pub const DEFAULT_MIN_THINKING_MS: i64 = 1000; // Classic's floor, before Move Overhead
+const CLOCK_RESERVE: u32 = 20; // A twentieth of the clock is never spent either
+const MIN_THINKING_TIME: Duration = Duration::from_millis(1); // After Move Overhead, for huge overheads
+const HARD_LIMIT_FACTOR: u32 = 2; // Classic's hard limit, times the soft one
+const EMERGENCY_CLOCK: Duration = Duration::from_secs(5); // Under this left, move almost at once
+const EMERGENCY_SHARE: u32 = 40; // Of what's left, per move in an emergency
+const FORCED_MOVE_TIME: Duration = Duration::from_millis(10); // Only one legal move, nothing to think about
+const OPENING_MOVES: u32 = 10; // Our moves, with close to everything still on the board
+const OPENING_PIECES: u32 = 28;
+const MIDDLEGAME_PIECES: u32 = 20; // Kings included
+const ENDGAME_PIECES: u32 = 10;
+const STABLE_CP: i32 = 15; // Eval moving less than this between our moves, the game looks settled
+const UNSTABLE_CP: i32 = 60; // And more than this, unclear
 
-    // Take the expected time left OR 1 second, whichever is greater
-    std::cmp::max(time_remaining/(moves_left as u32), Duration::from_secs(1))
+// Everything a strategy might want to know about the move it's budgeting for
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TimeContext {
+    pub clock: Option<Duration>, // Our time left
+    pub opponent_clock: Option<Duration>,
+    pub increment: Duration,
+    pub opponent_increment: Duration,
+    pub moves_to_go: Option<u32>,
+    pub movetime: Option<Duration>,
+    pub depth: Option<u8>, // go depth, the engine can't stop at one so the clock still counts
+    pub nodes: Option<u64>, // go nodes, nor can it count them
+    pub infinite: bool,    // go infinite, searching until stop
+    pub mate: Option<u8>,  // go mate, moves to find a mate in
+    pub move_number: u32,  // Our moves so far this game
+    pub pieces: u32,       // Pieces left on the board, a rough game phase
+    pub legal_moves: u32,  // 1 and there's no choice to make
+    pub last_score: Option<Score>, // Our eval after the previous move, when the engine gave one
+    pub previous_score: Option<Score>, // And after the one before that
+    pub move_overhead: Duration, // Move Overhead option, lost to the GUI and network every move
+    pub safety_buffer: Duration, // Safety Buffer option, kept back from the clock whatever the manager says
+    pub max_move_share: u32, // Max Move Share option, percent of the clock one move can have. 0 for no cap.
+    pub slow_mover: u32, // SlowMover option, percent the managers' budgets are scaled by. 0 leaves them.
+    pub min_thinking_time: Option<Duration>, // Minimum Thinking Time option, the default without it
+}
+
+impl TimeContext {
+    // From the arguments of a go command, anything missing is left for the manager to deal with
+    pub fn from_go(go: &GoParams, board: &Board, move_number: u32) -> TimeContext {
+        let (ours, theirs) = match board.side_to_move() {
+            Color::White => ((go.wtime, go.winc), (go.btime, go.binc)),
+            Color::Black => ((go.btime, go.binc), (go.wtime, go.winc)),
+        };
+
+        TimeContext {
+            clock: ours.0,
+            opponent_clock: theirs.0,
+            increment: ours.1.unwrap_or_default(),
+            opponent_increment: theirs.1.unwrap_or_default(),
+            moves_to_go: go.movestogo,
+            movetime: go.movetime,
+            depth: go.depth,
+            nodes: go.nodes,
+            infinite: go.infinite,
+            mate: go.mate,
+            move_number,
+            pieces: board.combined().popcnt(),
+            legal_moves: MoveGen::new_legal(board).len() as u32,
+            last_score: None,
+            previous_score: None,
+            move_overhead: Duration::ZERO,
+            safety_buffer: Duration::ZERO,
+            max_move_share: 0,
+            slow_mover: 0,
+            min_thinking_time: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeBudget {
+    pub soft: Duration, // What we aim to use
+    pub hard: Duration, // Never more than this
+}
+
+pub trait TimeManager: Send + Sync {
+    fn budget(&self, context: &TimeContext) -> TimeBudget;
+}
+
+pub fn time_manager(name: &str) -> Option<Box<dyn TimeManager>> {
+    match name {
+        "classic" => Some(Box::new(Classic)),
+        "increment" => Some(Box::new(Increment)),
+        _ => None,
+    }
+}
+
+// An even share of the clock over the moves to the next time control (movestogo, or a guess
+// without it) plus most of the increment, weighted by the game phase and never under the Minimum
+// Thinking Time. The hard limit leaves room for twice that, within a third of the clock. A
+// movetime is used as given.
+pub struct Classic;
+
+impl TimeManager for Classic {
+    fn budget(&self, context: &TimeContext) -> TimeBudget {
+        if let Some(movetime) = context.movetime {
+            return TimeBudget {
+                soft: movetime,
+                hard: movetime,
+            };
+        }
+        if let Some(short) = short_budget(context) {
+            return short;
+        }
+        let time = thinking_time(
+            context.move_number,
+            context.clock.unwrap_or_default(),
+            context.increment,
+            context.moves_to_go,
+            context.move_overhead,
+            phase_weight(context),
+            context
+                .min_thinking_time
+                .unwrap_or(Duration::from_millis(DEFAULT_MIN_THINKING_MS as u64)),
+        );
+        let clock = context.clock.unwrap_or_default();
+        let hard = (time * HARD_LIMIT_FACTOR)
+            .min(clock.saturating_sub(context.move_overhead) / 3)
+            .max(time);
+        slow_mover(TimeBudget { soft: time, hard }, context)
+    }
+}
+
+// Splits the clock over movestogo when the GUI sends it, and spends most of the increment on
+// top, weighted by the game phase. A movetime is used as given.
+pub struct Increment;
+
+impl TimeManager for Increment {
+    fn budget(&self, context: &TimeContext) -> TimeBudget {
+        if let Some(movetime) = context.movetime {
+            return TimeBudget {
+                soft: movetime,
+                hard: movetime,
+            };
+        }
+        if let Some(short) = short_budget(context) {
+            return short;
+        }
+        let clock = context.clock.unwrap_or_default();
+        let moves_left = context
+            .moves_to_go
+            .unwrap_or_else(|| expected_moves_left(context.move_number))
+            .max(1);
+        let (more, less) = phase_weight(context);
+        let soft = (clock / moves_left + context.increment * 3 / 4) * more / less;
+        let hard = (clock / 3).max(soft.min(clock));
+        let overhead = |time: Duration| {
+            time.saturating_sub(context.move_overhead)
+                .max(MIN_THINKING_TIME)
+        };
+        slow_mover(
+            TimeBudget {
+                soft: overhead(soft.min(hard)),
+                hard: overhead(hard),
+            },
+            context,
+        )
+    }
+}
+
+// The SlowMover option: more or less time for every move planned off the clock. Not the short
+// budgets, those are as long as a move can safely take. The hard limit keeps up if it has to.
+fn slow_mover(budget: TimeBudget, context: &TimeContext) -> TimeBudget {
+    let soft = match context.slow_mover {
+        0 => budget.soft,
+        percent => (budget.soft * percent / 100).max(MIN_THINKING_TIME),
+    };
+    TimeBudget {
+        soft,
+        hard: budget.hard.max(soft),
+    }
+}
+
+// Moves that hardly need a budget, a forced one or any once the clock is nearly gone
+fn short_budget(context: &TimeContext) -> Option<TimeBudget> {
+    if context.legal_moves == 1 {
+        return Some(TimeBudget {
+            soft: FORCED_MOVE_TIME,
+            hard: FORCED_MOVE_TIME,
+        });
+    }
+    emergency_budget(context)
+}
+
+// More for the middlegame, where the game is decided. Less while the opening is still on the
+// board and once it's down to an endgame, there's less to get wrong. As a fraction.
+fn phase_weight(context: &TimeContext) -> (u32, u32) {
+    match context.pieces {
+        pieces if pieces >= OPENING_PIECES && context.move_number < OPENING_MOVES => (4, 5),
+        pieces if pieces >= MIDDLEGAME_PIECES => (5, 4),
+        pieces if pieces <= ENDGAME_PIECES => (3, 4),
+        _ => (1, 1),
+    }
+}
+
+// Nearly out of time: a sliver of the clock and half the increment, never a tenth of what's left,
+// and no one second floor. Short enough for the fast path, the book and the cache do the work.
+fn emergency_budget(context: &TimeContext) -> Option<TimeBudget> {
+    let clock = context.clock.filter(|clock| *clock < EMERGENCY_CLOCK)?;
+    let left = clock.saturating_sub(context.move_overhead);
+    let soft = (left / EMERGENCY_SHARE + context.increment / 2)
+        .min(left / 10)
+        .max(MIN_THINKING_TIME);
+    Some(TimeBudget {
+        soft,
+        hard: (soft * 2).min(left / 5).max(soft),
+    })
+}
+
+// No more than what's left of our clock once Move Overhead, the Safety Buffer and a twentieth of
+// it are kept back, so slow output can't lose on time. Nor more than the Max Move Share of it, so
+// one long think can't leave the rest of the game short. As it is without a clock.
+pub fn within_clock(time: Duration, context: &TimeContext) -> Duration {
+    let Some(clock) = context.clock else {
+        return time;
+    };
+    let kept = context.move_overhead + context.safety_buffer + clock / CLOCK_RESERVE;
+    let share = match context.max_move_share {
+        0 => clock,
+        percent => clock * percent.min(100) / 100,
+    };
+    time.min(share)
+        .min(clock.saturating_sub(kept))
+        .max(MIN_THINKING_TIME)
+}
+
+// Our evals after our last two searches, oldest first. The engine doesn't report its iterations,
+// so how much the eval moves from one of our moves to the next is what there is to go on.
+#[derive(Default)]
+pub struct ScoreLog(Mutex<(Option<Score>, Option<Score>)>);
+
+impl ScoreLog {
+    pub fn record(&self, score: Option<Score>) {
+        let mut scores = self.0.lock();
+        *scores = (scores.1, score);
+    }
+
+    pub fn last_two(&self) -> (Option<Score>, Option<Score>) {
+        *self.0.lock()
+    }
+
+    pub fn clear(&self) {
+        *self.0.lock() = (None, None);
+    }
+}
+
+// Less time when the eval has settled, more (up to the hard limit) when it's swinging.
+// Without two evals to compare the budget stays as it is, which is the usual case for now since the
+// engine's searches don't come back with one.
+pub fn stability_budget(budget: TimeBudget, context: &TimeContext) -> TimeBudget {
+    let (Some(previous), Some(last)) = (context.previous_score, context.last_score) else {
+        return budget;
+    };
+    let swing = (centipawns(last) - centipawns(previous)).abs();
+    let soft = match swing {
+        swing if swing < STABLE_CP => budget.soft * 3 / 4,
+        swing if swing > UNSTABLE_CP => budget
+            .soft
+            .mul_f64((swing as f64 / UNSTABLE_CP as f64).min(2.0)),
+        _ => budget.soft,
+    };
+    TimeBudget {
+        soft: soft.min(budget.hard).max(MIN_THINKING_TIME),
+        hard: budget.hard,
+    }
+}
+
+fn expected_moves_left(moves_played: u32) -> u32 {
+    let game_moves_expected: u32 = 45; // Expect ~40 moves per game
+    std::cmp::max(game_moves_expected.saturating_sub(moves_played), 10) // Always assume we have 10 moves left
+}
+
+pub fn thinking_time(
+    moves_played: u32,
+    time_remaining: Duration,
+    increment: Duration,
+    moves_to_go: Option<u32>,
+    overhead: Duration,
+    (more, less): (u32, u32), // Game phase weight on the share of the clock
+    floor: Duration,
+) -> Duration {
+    let share = match moves_to_go {
+        // Even on the last move before the control keep a little back
+        Some(moves_to_go) => (time_remaining / moves_to_go.max(1)).min(time_remaining * 3 / 4),
+        None => time_remaining / expected_moves_left(moves_played),
+    };
+
+    // The increment comes back after the move, but only spend it if it's on the clock already
+    let from_increment = (increment * 3 / 4).min(time_remaining / 2);
+    let share = (share * more / less).min(time_remaining * 3 / 4);
+    let time = share + from_increment;
+
+    // Take the expected time left OR the floor, whichever is greater, less what the GUI loses
+    std::cmp::max(time, floor)
+        .saturating_sub(overhead)
+        .max(MIN_THINKING_TIME)
 }
 
 #[cfg(test)]
-mod tests{
-    use super::thinking_time;
-    use std::time::Duration;
+mod tests {
+    use super::*;
+    use crate::command::parse_go;
+    use std::str::FromStr;
+
+    fn clock(move_number: u32, clock: Duration) -> TimeContext {
+        TimeContext {
+            clock: Some(clock),
+            move_number,
+            pieces: 16, // Between the middlegame and the endgame, the phase doesn't count
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_thinking_time() {
+        let classic = time_manager("classic").unwrap();
+        assert_eq!(
+            classic.budget(&clock(5, Duration::from_secs(10))).soft,
+            Duration::from_secs(1)
+        ); // Minimum 1s
+        let budget = classic.budget(&clock(30, Duration::from_secs(30)));
+        assert_eq!(budget.soft, Duration::from_secs(2)); // 2sec per move
+        assert_eq!(budget.hard, Duration::from_secs(4));
+        let short = classic.budget(&clock(30, Duration::from_secs(3)));
+        assert_eq!(short.hard, short.soft); // Never under the soft limit
+        assert_eq!(
+            classic
+                .budget(&TimeContext {
+                    increment: Duration::from_secs(2),
+                    ..clock(30, Duration::from_secs(30))
+                })
+                .soft,
+            Duration::from_millis(3500)
+        ); // Plus 3/4 of the increment
+        assert_eq!(
+            thinking_time(
+                30,
+                Duration::from_secs(3),
+                Duration::from_secs(10),
+                None,
+                Duration::ZERO,
+                (1, 1),
+                Duration::from_secs(1)
+            ),
+            Duration::from_millis(1700)
+        ); // Not more of the increment than half the clock
+    }
 
     #[test]
-    fn test_thinking_time(){
-        assert_eq!(thinking_time(5, Duration::from_secs(0)), Duration::from_secs(1)); // Minimum 1s
-        assert_eq!(thinking_time(30, Duration::from_secs(30)), Duration::from_secs(2)); // 2sec per move
+    fn test_min_thinking_time() {
+        let classic = time_manager("classic").unwrap();
+        let bullet = |floor| {
+            let context = TimeContext {
+                min_thinking_time: Some(Duration::from_millis(floor)),
+                ..clock(30, Duration::from_secs(6))
+            };
+            classic.budget(&context).soft
+        };
+        assert_eq!(bullet(1000), Duration::from_secs(1));
+        assert_eq!(bullet(100), Duration::from_millis(400)); // 6s / 15
+        assert_eq!(bullet(0), Duration::from_millis(400));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_move_overhead() {
+        let overhead = |manager: &str, overhead| {
+            let context = TimeContext {
+                move_overhead: Duration::from_millis(overhead),
+                ..clock(30, Duration::from_secs(30))
+            };
+            time_manager(manager).unwrap().budget(&context)
+        };
+        assert_eq!(overhead("classic", 300).soft, Duration::from_millis(1700));
+        assert_eq!(overhead("classic", 5000).soft, MIN_THINKING_TIME);
+        let increment = overhead("increment", 300);
+        assert_eq!(increment.hard, Duration::from_millis(9700));
+        assert!(increment.soft <= increment.hard);
+    }
+
+    #[test]
+    fn test_emergency_time() {
+        for manager in TIME_MANAGERS {
+            let manager = time_manager(manager).unwrap();
+            let budget = manager.budget(&clock(30, Duration::from_secs(4)));
+            assert_eq!(budget.soft, Duration::from_millis(100));
+            assert_eq!(budget.hard, Duration::from_millis(200));
+            let with_increment = manager.budget(&TimeContext {
+                increment: Duration::from_secs(2),
+                ..clock(30, Duration::from_secs(4))
+            });
+            assert_eq!(with_increment.soft, Duration::from_millis(400));
+            let flagging = manager.budget(&clock(30, Duration::ZERO));
+            assert_eq!(
+                (flagging.soft, flagging.hard),
+                (MIN_THINKING_TIME, MIN_THINKING_TIME)
+            );
+        }
+    }
+
+    #[test]
+    fn test_phase_weight() {
+        let classic = time_manager("classic").unwrap();
+        let phase = |move_number, pieces| {
+            let context = TimeContext {
+                pieces,
+                ..clock(move_number, Duration::from_secs(60))
+            };
+            classic.budget(&context).soft
+        };
+        assert_eq!(phase(25, 16), Duration::from_secs(3)); // 60s / 20
+        assert_eq!(phase(5, 32), Duration::from_millis(1200));
+        assert_eq!(phase(25, 24), Duration::from_millis(3750));
+        assert_eq!(phase(25, 8), Duration::from_millis(2250));
+
+        let forced = TimeContext {
+            legal_moves: 1,
+            ..clock(25, Duration::from_secs(60))
+        };
+        assert_eq!(classic.budget(&forced).soft, FORCED_MOVE_TIME);
+        let board = Board::from_str("7k/8/8/8/8/8/1r6/K1r5 w - - 0 1").unwrap();
+        let go = GoParams::default();
+        assert_eq!(TimeContext::from_go(&go, &board, 0).legal_moves, 1);
+    }
+
+    #[test]
+    fn test_slow_mover() {
+        for manager in TIME_MANAGERS {
+            let manager = time_manager(manager).unwrap();
+            let context = clock(30, Duration::from_secs(60));
+            let normal = manager.budget(&context);
+            let slower = manager.budget(&TimeContext {
+                slow_mover: 200,
+                ..context
+            });
+            assert_eq!(slower.soft, normal.soft * 2);
+            assert!(slower.hard >= slower.soft);
+            let faster = manager.budget(&TimeContext {
+                slow_mover: 50,
+                ..context
+            });
+            assert_eq!((faster.soft, faster.hard), (normal.soft / 2, normal.hard));
+        }
+    }
+
+    #[test]
+    fn test_within_clock() {
+        let context = TimeContext {
+            move_overhead: Duration::from_millis(50),
+            safety_buffer: Duration::from_millis(100),
+            ..clock(30, Duration::from_secs(2))
+        };
+        let second = Duration::from_secs(1);
+        assert_eq!(within_clock(second, &context), second);
+        assert_eq!(
+            within_clock(second * 3, &context),
+            Duration::from_millis(1750) // 2000 less 50, 100 and 100
+        );
+        let flagged = TimeContext {
+            clock: Some(Duration::from_millis(120)),
+            ..context
+        };
+        assert_eq!(within_clock(second, &flagged), MIN_THINKING_TIME);
+        assert_eq!(
+            within_clock(second * 9, &TimeContext::default()),
+            second * 9
+        );
+
+        let capped = TimeContext {
+            max_move_share: 20,
+            ..context
+        };
+        assert_eq!(within_clock(second, &capped), Duration::from_millis(400));
+    }
+
+    #[test]
+    fn test_stability_budget() {
+        let budget = TimeBudget {
+            soft: Duration::from_secs(2),
+            hard: Duration::from_secs(3),
+        };
+        let swing = |previous, last| {
+            let context = TimeContext {
+                previous_score: Some(Score::Centipawns(previous)),
+                last_score: Some(Score::Centipawns(last)),
+                ..Default::default()
+            };
+            stability_budget(budget, &context).soft
+        };
+        assert_eq!(swing(30, 35), Duration::from_millis(1500));
+        assert_eq!(swing(30, 60), Duration::from_secs(2));
+        assert_eq!(swing(30, -60), Duration::from_secs(3)); // Capped at the hard limit
+        assert_eq!(stability_budget(budget, &TimeContext::default()), budget);
+
+        let scores = ScoreLog::default();
+        scores.record(Some(Score::Centipawns(10)));
+        scores.record(None);
+        scores.record(Some(Score::Mate(3)));
+        assert_eq!(scores.last_two(), (None, Some(Score::Mate(3))));
+    }
+
+    #[test]
+    fn test_classic_moves_to_go() {
+        let classic = time_manager("classic").unwrap();
+        let with_moves_to_go = |moves_to_go| {
+            let context = TimeContext {
+                moves_to_go: Some(moves_to_go),
+                ..clock(5, Duration::from_secs(120))
+            };
+            classic.budget(&context).soft
+        };
+        assert_eq!(with_moves_to_go(12), Duration::from_secs(10)); // Not 120s / 40
+        assert_eq!(with_moves_to_go(1), Duration::from_secs(90));
+        assert_eq!(with_moves_to_go(0), Duration::from_secs(90));
+    }
+
+    #[test]
+    fn test_increment_budget() {
+        let increment = time_manager("increment").unwrap();
+        let context = TimeContext {
+            increment: Duration::from_secs(2),
+            moves_to_go: Some(10),
+            ..clock(10, Duration::from_secs(60))
+        };
+        let budget = increment.budget(&context);
+        assert_eq!(budget.soft, Duration::from_millis(7500));
+        assert_eq!(budget.hard, Duration::from_secs(20));
+
+        // Nearly flagging, never plan past the clock
+        let budget = increment.budget(&TimeContext {
+            moves_to_go: Some(1),
+            ..context
+        });
+        assert!(budget.soft <= budget.hard);
+        assert!(budget.hard <= Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_context_from_go() {
+        let board = Board::default().null_move().unwrap(); // Black to move
+        let go = |line: &str| {
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            TimeContext::from_go(&parse_go(&tokens), &board, 3)
+        };
+        let context = go("go wtime 1000 btime 2000 winc 10 binc 20 movestogo 5");
+        assert_eq!(context.clock, Some(Duration::from_millis(2000)));
+        assert_eq!(context.increment, Duration::from_millis(20));
+        assert_eq!(context.opponent_clock, Some(Duration::from_millis(1000)));
+        assert_eq!(context.moves_to_go, Some(5));
+        assert_eq!(context.pieces, 32);
+        assert_eq!(context.depth, None);
+        let depth = go("go depth 8 wtime 100");
+        assert_eq!(depth.depth, Some(8));
+        let nodes = go("go nodes 500000");
+        assert_eq!((nodes.nodes, nodes.depth), (Some(500000), None));
+        let infinite = go("go infinite");
+        assert_eq!((infinite.clock, infinite.movetime), (None, None));
+        assert!(infinite.infinite && !context.infinite);
+        assert_eq!(go("go mate 3").mate, Some(3));
+        assert_eq!(
+            time_manager("classic").unwrap().budget(&infinite).soft,
+            Duration::from_secs(1)
+        );
+    }
+}
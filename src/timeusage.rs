@@ -0,0 +1,133 @@
+// What each of our moves was given against what it took, for tuning the time managers and working
+// out why a game flagged. Moves go to the log as they're played, the totals once the game's over.
+use log::{info, warn};
+use parking_lot::Mutex;
+use std::time::Duration;
+
+const OVERRUN_SLACK: Duration = Duration::from_millis(10); // Over budget by more than this is an overrun
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct MoveTime {
+    generation: u64, // The search's, from SearchRequest
+    allocated: Duration,
+    clock: Option<Duration>, // Ours when the go came in
+    used: Option<Duration>,  // Until the bestmove, None while searching
+}
+
+#[derive(Default)]
+pub struct TimeUsage(Mutex<Vec<MoveTime>>);
+
+impl TimeUsage {
+    pub fn start(&self, generation: u64, allocated: Duration, clock: Option<Duration>) {
+        self.0.lock().push(MoveTime {
+            generation,
+            allocated,
+            clock,
+            used: None,
+        });
+    }
+
+    // A search finding its move, nothing if it was started before the game was finished
+    pub fn finish(&self, generation: u64, used: Duration) {
+        let mut moves = self.0.lock();
+        let Some((number, played)) = moves
+            .iter_mut()
+            .enumerate()
+            .find(|(_, played)| played.generation == generation)
+        else {
+            return;
+        };
+        played.used = Some(used);
+        let left = match played.clock {
+            Some(clock) => format!("{}ms", clock.saturating_sub(used).as_millis()),
+            None => "unknown".to_string(),
+        };
+        info!(
+            "Time for move {}: allocated {}ms, used {}ms, clock about {} left",
+            number + 1,
+            played.allocated.as_millis(),
+            used.as_millis(),
+            left
+        );
+        if used > played.allocated + OVERRUN_SLACK {
+            warn!(
+                "Move {} overran its {}ms by {}ms",
+                number + 1,
+                played.allocated.as_millis(),
+                (used - played.allocated).as_millis()
+            );
+        }
+    }
+
+    pub fn summary(&self) -> Option<String> {
+        let moves = self.0.lock();
+        let played: Vec<(&MoveTime, Duration)> = moves
+            .iter()
+            .filter_map(|played| played.used.map(|used| (played, used)))
+            .collect();
+        if played.is_empty() {
+            return None;
+        }
+        let allocated: Duration = played.iter().map(|(played, _)| played.allocated).sum();
+        let used: Duration = played.iter().map(|(_, used)| *used).sum();
+        let overruns: Vec<Duration> = played
+            .iter()
+            .filter(|(played, used)| *used > played.allocated + OVERRUN_SLACK)
+            .map(|(played, used)| *used - played.allocated)
+            .collect();
+        let mut summary = format!(
+            "Time usage over {} moves: allocated {}ms, used {}ms ({}%), {} overruns",
+            played.len(),
+            allocated.as_millis(),
+            used.as_millis(),
+            (used.as_millis() * 100)
+                .checked_div(allocated.as_millis())
+                .unwrap_or(0),
+            overruns.len()
+        );
+        if let Some(worst) = overruns.iter().max() {
+            summary += &format!(" (worst {}ms over)", worst.as_millis());
+        }
+        if let Some(lowest) = played
+            .iter()
+            .filter_map(|(played, used)| Some(played.clock?.saturating_sub(*used)))
+            .min()
+        {
+            summary += &format!(", lowest clock about {}ms", lowest.as_millis());
+        }
+        Some(summary)
+    }
+
+    // The game's over: log the totals and start again
+    pub fn finish_game(&self) {
+        if let Some(summary) = self.summary() {
+            info!("{}", summary);
+        }
+        self.0.lock().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_time_usage_summary() {
+        let usage = TimeUsage::default();
+        assert_eq!(usage.summary(), None);
+        let second = Duration::from_secs(1);
+        usage.start(1, second, Some(second * 30));
+        usage.finish(1, Duration::from_millis(900));
+        usage.start(2, second, Some(second * 20));
+        usage.finish(2, Duration::from_millis(1300));
+        usage.start(3, second, None); // Still searching, doesn't count
+        usage.finish(7, second); // Not one of ours
+        assert_eq!(
+            usage.summary().unwrap(),
+            "Time usage over 2 moves: allocated 2000ms, used 2200ms (110%), 1 overruns \
+             (worst 300ms over), lowest clock about 18700ms"
+        );
+        usage.finish_game();
+        assert_eq!(usage.summary(), None);
+    }
+}
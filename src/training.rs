@@ -0,0 +1,233 @@
+// --export-training <file>: a JSON line per completed search, for training an evaluation.
+// Lines are held until the game ends so each one can carry the game's result, then written
+// out in one go. Searches whose move had to be substituted are kept but flagged.
+use chess::{Board, ChessMove, Color};
+use log::{info, warn};
+use parking_lot::Mutex;
+use std::{fs::OpenOptions, io::Write, path::PathBuf};
+
+use crate::{capture::centipawns, display::Score, game::GameResult};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrainingSample {
+    pub fen: String,
+    pub side: Color,
+    pub ply: u32,
+    pub depth: Option<u8>,
+    pub score_cp: Option<i32>, // Side to move's point of view, mates as ±10000
+    pub bestmove: ChessMove,
+    pub fallback: bool,
+}
+
+#[derive(Debug, Default)]
+struct ExportState {
+    game: u32,
+    pending: Vec<TrainingSample>, // This game's searches, written at game end
+}
+
+pub struct TrainingExport {
+    path: PathBuf,
+    run: u64, // Game ids are "<run>-<game>", so runs appending to the same file don't collide
+    state: Mutex<ExportState>,
+}
+
+impl TrainingExport {
+    pub fn new(path: PathBuf, run: u64) -> TrainingExport {
+        TrainingExport {
+            path,
+            run,
+            state: Mutex::new(ExportState {
+                game: 1,
+                ..Default::default()
+            }),
+        }
+    }
+
+    pub fn after_search(
+        &self,
+        board: &Board,
+        ply: u32,
+        chess_move: ChessMove,
+        score: Option<Score>,
+        fallback: bool,
+    ) {
+        self.state.lock().pending.push(TrainingSample {
+            fen: board.to_string(),
+            side: board.side_to_move(),
+            ply,
+            depth: None, // The engine doesn't report it
+            score_cp: score.map(centipawns),
+            bestmove: chess_move,
+            fallback,
+        });
+    }
+
+    // Writes out the game's searches with its result (null when it never came) and starts the next
+    pub fn finish_game(&self, result: Option<GameResult>) {
+        let mut state = self.state.lock();
+        let samples = std::mem::take(&mut state.pending);
+        let game_id = format!("{}-{}", self.run, state.game);
+        state.game += 1;
+        if samples.is_empty() {
+            return;
+        }
+
+        let result = result
+            .filter(|result| *result != GameResult::Unfinished)
+            .map_or("null".to_string(), |result| format!("\"{}\"", result));
+        let lines: String = samples
+            .iter()
+            .map(|sample| json_line(sample, &game_id, &result) + "\n")
+            .collect();
+        let written = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| {
+                file.write_all(lines.as_bytes())?;
+                file.sync_data()
+            });
+        match written {
+            Ok(()) => info!(
+                "Exported {} training positions from game {}",
+                samples.len(),
+                game_id
+            ),
+            Err(e) => warn!(
+                "Couldn't export training data to {}: {}",
+                self.path.display(),
+                e
+            ),
+        }
+    }
+}
+
+// Whatever was still pending when the session ends goes out without a result
+impl Drop for TrainingExport {
+    fn drop(&mut self) {
+        self.finish_game(None);
+    }
+}
+
+fn json_line(sample: &TrainingSample, game_id: &str, result: &str) -> String {
+    let optional = |value: Option<String>| value.unwrap_or("null".to_string());
+    format!(
+        "{{\"fen\":\"{}\",\"side\":\"{}\",\"game\":\"{}\",\"ply\":{},\"depth\":{},\"score_cp\":{},\
+         \"bestmove\":\"{}\",\"fallback\":{},\"result\":{}}}",
+        sample.fen,
+        match sample.side {
+            Color::White => "w",
+            Color::Black => "b",
+        },
+        game_id,
+        sample.ply,
+        optional(sample.depth.map(|depth| depth.to_string())),
+        optional(sample.score_cp.map(|cp| cp.to_string())),
+        sample.bestmove,
+        sample.fallback,
+        result
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        backend::{SearchBackend, SearchOutcome},
+        session::{Reply, UciSession},
+    };
+    use chess::MoveGen;
+    use shallow_red_engine::utils::engine_interface::EngineSettings;
+    use std::{fs, str::FromStr, sync::Arc};
+
+    // First legal move, scored +40 when white is to move and -25 when black is
+    struct SidedBackend;
+
+    impl SearchBackend for SidedBackend {
+        fn search(&self, board: Board, _settings: EngineSettings) -> SearchOutcome {
+            SearchOutcome {
+                best_move: MoveGen::new_legal(&board).next().unwrap(),
+                score: Some(Score::Centipawns(match board.side_to_move() {
+                    Color::White => 40,
+                    Color::Black => -25,
+                })),
+                nodes: None,
+            }
+        }
+    }
+
+    fn export_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("shallow-red-training-{}.jsonl", name));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn play_go(session: &mut UciSession) {
+        match session.handle("go wtime 600000 btime 600000") {
+            Some(Reply::Search(request)) => request.run(|_| {}),
+            _ => panic!("go should hand back a search"),
+        }
+    }
+
+    #[test]
+    fn test_scripted_game_export() {
+        let path = export_path("game");
+        let mut session = UciSession::new(None);
+        session.backend = Arc::new(SidedBackend);
+        session.training = Some(Arc::new(TrainingExport::new(path.clone(), 7)));
+
+        session.handle("position startpos");
+        play_go(&mut session);
+        let first = MoveGen::new_legal(&Board::default()).next().unwrap();
+        session.handle(&format!("position startpos moves {}", first));
+        play_go(&mut session);
+        assert!(!path.exists()); // Nothing written mid game
+        session.handle("result 0-1 {White resigns}");
+
+        let exported = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = exported.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                format!(
+                    "{{\"fen\":\"{}\",\"side\":\"w\",\"game\":\"7-1\",\"ply\":0,\"depth\":null,\
+                     \"score_cp\":40,\"bestmove\":\"{}\",\"fallback\":false,\"result\":\"0-1\"}}",
+                    Board::default(),
+                    first
+                ),
+                format!(
+                    "{{\"fen\":\"{}\",\"side\":\"b\",\"game\":\"7-1\",\"ply\":1,\"depth\":null,\
+                     \"score_cp\":-25,\"bestmove\":\"{}\",\"fallback\":false,\"result\":\"0-1\"}}",
+                    session.board,
+                    MoveGen::new_legal(&session.board).next().unwrap()
+                ),
+            ]
+        );
+
+        // The next game gets its own id, and leaves its result null when it never ends
+        session.handle("ucinewgame");
+        session.handle("position startpos");
+        play_go(&mut session);
+        drop(session);
+        let exported = fs::read_to_string(&path).unwrap();
+        let last = exported.lines().last().unwrap();
+        assert!(last.contains("\"game\":\"7-2\",\"ply\":0,"));
+        assert!(last.ends_with("\"result\":null}"));
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_fallback_flagged() {
+        let path = export_path("fallback");
+        let export = TrainingExport::new(path.clone(), 1);
+        let board = Board::default();
+        let substitute = ChessMove::from_str("a2a3").unwrap();
+        export.after_search(&board, 0, substitute, Some(Score::Mate(-2)), true);
+        export.finish_game(Some(GameResult::Draw));
+
+        let exported = fs::read_to_string(&path).unwrap();
+        assert!(exported.contains("\"score_cp\":-10000,\"bestmove\":\"a2a3\",\"fallback\":true,"));
+        assert!(exported.ends_with("\"result\":\"1/2-1/2\"}\n"));
+        let _ = fs::remove_file(&path);
+    }
+}
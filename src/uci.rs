@@ -0,0 +1,594 @@
+// The UCI frontend over stdin and stdout that the binary runs: commands are read a line at a
+// time and handed to the session, and whatever comes back from it or the search worker is printed.
+// Input and search output are waited on together, so neither holds up the other. Anything that
+// reads the input or takes the output another way (a test, a fuzzer, another frontend) can drive
+// the same loop through `run`.
+use chess::Board;
+use log::{info, warn};
+use std::{
+    io::{self, Write},
+    str::FromStr,
+    sync::{
+        mpsc::{channel, Sender},
+        Arc, OnceLock,
+    },
+    thread,
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, Lines},
+    select,
+    sync::{
+        mpsc::{self, UnboundedReceiver},
+        watch,
+    },
+    task,
+};
+
+use crate::{
+    crash,
+    search::{join_within, SearchCompleted, SearchWorker},
+    session::{Reply, UciSession},
+};
+
+const MAX_UNREADABLE: u32 = 10; // Failed reads in a row before the input counts as gone
+
+// Until quit comes or the GUI goes away, with a worker started for the searches. A GUI that
+// crashes or gets killed closes our stdin, and usually stdout with it: either is taken as quit.
+// So is `shutdown` turning true, for whoever else wants us gone.
+pub async fn run(
+    mut session: UciSession,
+    input: impl AsyncBufRead + Unpin,
+    output: &Writer,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let mut input = input.lines();
+    let mut completed = start_worker(&mut session);
+    let mut unreadable = 0;
+
+    loop {
+        let uci_input = select! {
+            line = read_command(&mut input) => line,
+            Ok(()) = shutdown.changed() => {
+                if *shutdown.borrow() {
+                    info!("Shutdown requested, quitting");
+                    break;
+                }
+                continue;
+            }
+            Some(done) = completed.recv() => {
+                if !send_completed(&done, output) {
+                    info!("stdout closed, quitting");
+                    break;
+                }
+                continue;
+            }
+        };
+        let uci_input = match uci_input {
+            Ok(line) => line,
+            Err(InputError::Closed) => {
+                info!("stdin closed, quitting");
+                break;
+            }
+            Err(e) => {
+                // A bad line is worth reporting, a read that keeps failing is a pipe that's gone
+                warn!("{}", e);
+                unreadable += 1;
+                if unreadable >= MAX_UNREADABLE {
+                    info!("{} unreadable reads in a row, quitting", unreadable);
+                    break;
+                }
+                if !output.send(&format!("info string error {}", e)) {
+                    info!("stdout closed, quitting");
+                    break;
+                }
+                continue;
+            }
+        };
+        unreadable = 0;
+        info!("Received << {}", uci_input);
+
+        let uci_output: Option<String> =
+            parse_input(uci_input, &mut session, &mut input, output).await;
+        info!("Sent >> {:#?}", uci_output);
+
+        // Only print out if we have a message. Whatever the worker finished first goes out first.
+        if let Some(out) = uci_output {
+            while let Ok(done) = completed.try_recv() {
+                send_completed(&done, output);
+            }
+            if out == "quit" {
+                break;
+            } else if !output.send(&out) {
+                info!("stdout closed, quitting");
+                break;
+            }
+        };
+    }
+    shut_down(session, &mut completed, output);
+}
+
+enum Outgoing {
+    Line(String),
+    Flush(Sender<()>), // Answered once everything queued before it is written
+}
+
+// Everything for the GUI goes through one writer thread, each reply as a single write and flushed
+// straight after, so a bestmove from the worker can't land in the middle of another reply. The
+// thread quits on the first failed write, from then on nothing can be queued.
+#[derive(Clone)]
+pub struct Writer {
+    queue: Sender<Outgoing>,
+}
+
+impl Writer {
+    pub fn spawn(mut sink: impl Write + Send + 'static) -> Writer {
+        let (queue, queued) = channel();
+        thread::spawn(move || {
+            for outgoing in queued {
+                match outgoing {
+                    Outgoing::Line(out) => {
+                        let written = sink
+                            .write_all(format!("{}\n", out).as_bytes())
+                            .and_then(|_| sink.flush());
+                        if written.is_err() {
+                            return;
+                        }
+                    }
+                    Outgoing::Flush(done) => {
+                        let _ = done.send(());
+                    }
+                }
+            }
+        });
+        Writer { queue }
+    }
+
+    // One or more lines to the GUI, false once it's stopped listening. println! would panic there.
+    pub fn send(&self, out: &str) -> bool {
+        self.queue.send(Outgoing::Line(out.to_string())).is_ok()
+    }
+
+    // Until everything sent so far is out, or `within`
+    pub fn flush(&self, within: Duration) -> bool {
+        let (done, flushed) = channel();
+        self.queue.send(Outgoing::Flush(done)).is_ok() && flushed.recv_timeout(within).is_ok()
+    }
+}
+
+static STDOUT: OnceLock<Writer> = OnceLock::new();
+
+// The process's one writer to stdout
+pub fn stdout() -> &'static Writer {
+    STDOUT.get_or_init(|| Writer::spawn(io::stdout()))
+}
+
+// SIGINT or SIGTERM (Ctrl-C on Windows) as quit, so a supervisor stopping us (systemd, docker
+// stop) gets the same clean shutdown as a GUI would, not a kill halfway through a line
+pub fn quit_on_signals(shutdown: watch::Sender<bool>) {
+    tokio::spawn(async move {
+        let signal = next_signal().await;
+        info!("{} received, quitting", signal);
+        let _ = shutdown.send(true);
+    });
+}
+
+#[cfg(unix)]
+async fn next_signal() -> &'static str {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut terminate = match signal(SignalKind::terminate()) {
+        Ok(terminate) => Some(terminate),
+        Err(e) => {
+            warn!("Can't handle SIGTERM, {}", e);
+            None
+        }
+    };
+    let sigterm = async {
+        match &mut terminate {
+            Some(terminate) => terminate.recv().await,
+            None => std::future::pending().await,
+        }
+    };
+    select! {
+        Ok(()) = tokio::signal::ctrl_c() => "SIGINT",
+        _ = sigterm => "SIGTERM",
+    }
+}
+
+#[cfg(not(unix))]
+async fn next_signal() -> &'static str {
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        warn!("Can't handle Ctrl-C, {}", e);
+        std::future::pending::<()>().await;
+    }
+    "Ctrl-C"
+}
+
+// What can go wrong reading a command from the GUI
+#[derive(Debug)]
+pub enum InputError {
+    Closed,                // stdin is gone, same as quit
+    Unreadable(io::Error), // Not UTF-8, or the read itself failed
+}
+
+impl std::fmt::Display for InputError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            InputError::Closed => write!(f, "input closed"),
+            InputError::Unreadable(e) => write!(f, "unreadable input, {}", e),
+        }
+    }
+}
+
+// One line of input without its line ending
+pub async fn read_command<R: AsyncBufRead + Unpin>(
+    input: &mut Lines<R>,
+) -> Result<String, InputError> {
+    match input.next_line().await {
+        Ok(Some(line)) => Ok(line),
+        Ok(None) => Err(InputError::Closed),
+        Err(e) => Err(InputError::Unreadable(e)),
+    }
+}
+
+pub async fn parse_input<R: AsyncBufRead + Unpin>(
+    uci_input: String,
+    session: &mut UciSession,
+    input: &mut Lines<R>,
+    output: &Writer,
+) -> Option<String> {
+    // Reads the next input line, so it stays out of the shared session
+    if uci_input.split_whitespace().next() == Some("debuginternal") {
+        let debug_board = match read_command(input).await {
+            Ok(line) => Board::from_str(line.trim()).map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+        return match debug_board {
+            Ok(board) => {
+                session.board = board;
+                None
+            }
+            Err(e) => Some(format!("info string error debuginternal board, {}", e)),
+        };
+    }
+
+    match session.handle(&uci_input)? {
+        Reply::Output(out) => Some(out),
+        Reply::Quit => Some("quit".to_string()),
+        Reply::Search(request) => {
+            // Only sessions without a worker get here
+            let output = output.clone();
+            task::spawn_blocking(move || {
+                request.run(|out| {
+                    output.send(&out);
+                })
+            });
+            None
+        }
+    }
+}
+
+// Searches run on the session's worker, which sends what they produce straight into a tokio
+// channel so the loop can wait on it next to the input. The channel closes once the worker's gone.
+pub fn start_worker(session: &mut UciSession) -> UnboundedReceiver<SearchCompleted> {
+    let (done_tx, completed) = mpsc::unbounded_channel();
+    session.worker = Some(SearchWorker::spawn_with(Arc::new(move |done| {
+        let _ = done_tx.send(done); // Nobody listening once the loop's gone
+    })));
+    completed
+}
+
+fn send_completed(done: &SearchCompleted, output: &Writer) -> bool {
+    info!("Sent >> {}", done.output);
+    crash::record_sent(&done.output);
+    output.send(&done.output)
+}
+
+const QUIT_GRACE: Duration = Duration::from_millis(500);
+
+// However the loop ended: stop the search and let the worker finish, print the bestmove that's
+// owed, drop the session and with it the cache senders so the cache manager thread quits, then
+// see the output and the log out
+pub fn shut_down(
+    mut session: UciSession,
+    completed: &mut UnboundedReceiver<SearchCompleted>,
+    output: &Writer,
+) {
+    // The worker may still hold a clone, so don't wait for the drop
+    if let Some(training) = &session.training {
+        training.finish_game(None);
+    }
+    session.time_usage.finish_game();
+    let finished = session
+        .worker
+        .take()
+        .is_none_or(|worker| worker.shut_down(QUIT_GRACE));
+    if !finished {
+        warn!("Search still running at quit, exiting anyway");
+    }
+    // Nowhere to go if stdout is what closed, that's fine
+    while let Ok(done) = completed.try_recv() {
+        send_completed(&done, output);
+    }
+    let cache_manager = session
+        .lazy_cache
+        .as_ref()
+        .and_then(|lazy| lazy.take_manager());
+    drop(session);
+    if cache_manager.is_some_and(|manager| !join_within(manager, QUIT_GRACE)) {
+        warn!("Cache manager still running at quit");
+    }
+    if !output.flush(QUIT_GRACE) {
+        warn!("Output not written by quit");
+    }
+    log::logger().flush();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{backend::MockBackend, version::VERSION};
+    use chess::{ChessMove, MoveGen, Square};
+    use parking_lot::Mutex;
+    use std::thread::JoinHandle;
+    use tokio::io::BufReader;
+
+    fn input(text: &'static [u8]) -> Lines<BufReader<&'static [u8]>> {
+        BufReader::new(text).lines()
+    }
+
+    fn no_input() -> Lines<BufReader<&'static [u8]>> {
+        input(b"")
+    }
+
+    fn mock_session() -> UciSession {
+        let mut session = UciSession::new(None);
+        session.backend = Arc::new(MockBackend);
+        session
+    }
+
+    // A writer whose output the test can read back
+    fn captured() -> (Writer, Shared) {
+        let written = Shared::default();
+        (Writer::spawn(written.clone()), written)
+    }
+
+    async fn handle(session: &mut UciSession, line: &str) -> Option<String> {
+        let (output, _) = captured();
+        parse_input(line.to_string(), session, &mut no_input(), &output).await
+    }
+
+    // The worker's output up to and including the bestmove
+    async fn bestmove(completed: &mut UnboundedReceiver<SearchCompleted>) -> String {
+        let waiting = async {
+            loop {
+                let done = completed.recv().await.expect("worker gone");
+                if done.output.contains("bestmove") {
+                    return done.output;
+                }
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), waiting)
+            .await
+            .expect("no bestmove")
+    }
+
+    #[tokio::test]
+    async fn test_uciok() {
+        let output = handle(&mut mock_session(), "uci").await.unwrap();
+        assert_eq!(
+            output,
+            format!(
+                "id name Shallow Red {}\n\
+             id author 15jgme\n\
+             option name Startup Warmup type check default false\n\
+             option name Bullet Fast Path type check default false\n\
+             option name Repertoire File type string default <empty>\n\
+             option name Capture File type string default <empty>\n\
+             option name OwnBook type check default false\n\
+             option name BookFile type string default <empty>\n\
+             option name Book Selection type combo default weighted var best var weighted var uniform var min-weight 10\n\
+             option name Persist Options type check default false\n\
+             option name UCI_Opponent type string default <empty>\n\
+             option name PGN File type string default <empty>\n\
+             option name Career File type string default <empty>\n\
+             option name Time Manager type combo default classic var classic var increment\n\
+             option name Avoid Moves type string default <empty>\n\
+             option name Full Reset type button\n\
+             option name Hash type spin default 16 min 1 max 65536\n\
+             option name Clear Hash type button\n\
+             option name Threads type spin default 1 min 1 max 64\n\
+             option name Ponder type check default false\n\
+             option name UCI_Chess960 type check default false\n\
+             option name UCI_ShowWDL type check default false\n\
+             option name Move Overhead type spin default 0 min 0 max 5000\n\
+             option name Safety Buffer type spin default 100 min 0 max 5000\n\
+             option name Max Move Share type spin default 20 min 1 max 100\n\
+             option name SlowMover type spin default 100 min 10 max 1000\n\
+             option name Minimum Thinking Time type spin default 1000 min 0 max 5000\n\
+             option name SyzygyPath type string default <empty>\n\
+             option name UCI_LimitStrength type check default false\n\
+             option name UCI_Elo type spin default 1500 min 800 max 2200\n\
+             uciok",
+                VERSION
+            )
+        )
+    }
+
+    #[tokio::test]
+    async fn test_read_command() {
+        let mut input = input(b"uci\r\nisready\n\xff\n");
+        assert_eq!(read_command(&mut input).await.unwrap(), "uci");
+        assert_eq!(read_command(&mut input).await.unwrap(), "isready");
+        assert!(matches!(
+            read_command(&mut input).await,
+            Err(InputError::Unreadable(_))
+        ));
+        assert!(matches!(
+            read_command(&mut input).await,
+            Err(InputError::Closed)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_debuginternal_reads_the_next_line() {
+        let mut session = mock_session();
+        let fen = "8/8/8/8/8/8/6k1/4K2R w K - 0 1";
+        let mut input = input(b"8/8/8/8/8/8/6k1/4K2R w K - 0 1\n");
+        let (output, _) = captured();
+        let output = parse_input(
+            "debuginternal".to_string(),
+            &mut session,
+            &mut input,
+            &output,
+        )
+        .await;
+        assert_eq!(output, None);
+        assert_eq!(session.board, Board::from_str(fen).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_readyok() {
+        let output = handle(&mut mock_session(), "isready").await.unwrap();
+        assert_eq!(output, "readyok")
+    }
+
+    #[tokio::test]
+    async fn test_warmup_readyok() {
+        let mut session = mock_session();
+        handle(&mut session, "setoption name Startup Warmup value true").await;
+        let output = handle(&mut session, "isready").await;
+        assert_eq!(output.unwrap(), "readyok"); // Only readyok, and only once the search is done
+        assert!(session.warmed_up);
+        assert!(session.warming.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_newgame() {
+        let output = handle(&mut mock_session(), "ucinewgame").await;
+        assert_eq!(output, None)
+    }
+
+    #[tokio::test]
+    async fn test_position() {
+        let mut session = mock_session();
+        handle(&mut session, "position startpos moves e2e4").await;
+        let board_e2e4 =
+            Board::default().make_move_new(ChessMove::new(Square::E2, Square::E4, None));
+        assert_eq!(session.board, board_e2e4);
+    }
+
+    #[tokio::test]
+    async fn test_go() {
+        let mut session = mock_session();
+        let mut completed = start_worker(&mut session);
+        handle(&mut session, "position startpos moves e2e4").await;
+        handle(&mut session, "go wtime 600000 btime 600000").await;
+        let expected = MoveGen::new_legal(&session.board).next().unwrap();
+        let out = bestmove(&mut completed).await;
+        assert!(out.ends_with(&format!("bestmove {}", expected)));
+    }
+
+    #[tokio::test]
+    async fn test_blunder() {
+        let mut session = mock_session();
+        let mut completed = start_worker(&mut session);
+        session.board =
+            Board::from_str("r3r1k1/ppp3pp/4p3/1P6/4p3/b3P3/qBQ2PPP/3R1RK1 w - - 0 1").unwrap();
+        handle(&mut session, "go wtime 600000 btime 600000").await;
+        let out = bestmove(&mut completed).await;
+        let played = out.lines().last().unwrap().trim_start_matches("bestmove ");
+        let played = ChessMove::from_str(played).unwrap();
+        assert!(MoveGen::new_legal(&session.board).any(|legal| legal == played));
+    }
+
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.0.lock().extend_from_slice(bytes);
+            Ok(bytes.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    struct Closed;
+
+    impl Write for Closed {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_writer_keeps_lines_whole() {
+        let (output, written) = captured();
+        let senders: Vec<JoinHandle<()>> = (0..4)
+            .map(|_| {
+                let output = output.clone();
+                thread::spawn(move || {
+                    for _ in 0..50 {
+                        output.send("info depth 1 score cp 30 pv e2e4\nbestmove e2e4");
+                    }
+                })
+            })
+            .collect();
+        for sender in senders {
+            sender.join().unwrap();
+        }
+        assert!(output.flush(Duration::from_secs(5)));
+        let written = String::from_utf8(written.0.lock().clone()).unwrap();
+        let lines: Vec<&str> = written.lines().collect();
+        assert_eq!(lines.len(), 400);
+        for pair in lines.chunks(2) {
+            assert_eq!(pair, ["info depth 1 score cp 30 pv e2e4", "bestmove e2e4"]);
+        }
+    }
+
+    #[test]
+    fn test_writer_stops_when_closed() {
+        let output = Writer::spawn(Closed);
+        output.send("readyok");
+        // The writer's gone once the flush can't be answered
+        assert!(!output.flush(Duration::from_secs(5)));
+        assert!(!output.send("readyok"));
+    }
+
+    #[tokio::test]
+    async fn test_eof_quits_mid_search() {
+        // The GUI went away during an infinite search, nothing will ever send stop
+        let input = BufReader::new(&b"position startpos\ngo infinite\n"[..]);
+        let (_shutdown, shutdown_rx) = watch::channel(false);
+        let (output, written) = captured();
+        let running = run(mock_session(), input, &output, shutdown_rx);
+        let ran = tokio::time::timeout(Duration::from_secs(5), running);
+        assert!(ran.await.is_ok());
+        // Quit still owes the bestmove, and it's written before run returns
+        let written = String::from_utf8(written.0.lock().clone()).unwrap();
+        let expected = MoveGen::new_legal(&Board::default()).next().unwrap();
+        assert_eq!(
+            written.lines().last(),
+            Some(&*format!("bestmove {}", expected))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_signal_quits() {
+        // stdin stays open, only the signal can end it
+        let (input, _gui) = tokio::io::duplex(64);
+        let (shutdown, shutdown_rx) = watch::channel(false);
+        shutdown.send(true).unwrap(); // Sent before the loop looks, it still sees it
+        let (output, _) = captured();
+        let running = run(mock_session(), BufReader::new(input), &output, shutdown_rx);
+        let ran = tokio::time::timeout(Duration::from_secs(5), running);
+        assert!(ran.await.is_ok());
+    }
+}
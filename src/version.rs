@@ -0,0 +1,4 @@
+// Which build this is, for --version, the id name line and crash reports, so a bug report can be
+// tied to the commit it came from. build.rs works both out.
+pub const VERSION: &str = env!("SHALLOW_RED_VERSION"); // 0.1.0+1a2b3c4, no hash outside a git checkout
+pub const BUILD: &str = env!("SHALLOW_RED_BUILD"); // x86_64-unknown-linux-gnu release build, features: http
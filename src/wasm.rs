@@ -0,0 +1,191 @@
+// Browser frontend: stdin/stdout are replaced by `post_command` and an output callback.
+// There are no threads on wasm32, so searches run to completion inside `post_command`
+// and the cache writes queued during a search are applied once it returns.
+use log::{info, LevelFilter, Log, Metadata, Record};
+use parking_lot::{Mutex, RwLock};
+use shallow_red_engine::managers::cache_manager::Cache;
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    sync::{Arc, Once},
+};
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    search::{deferred_cache, SearchRequest},
+    session::{Reply, UciSession},
+};
+
+const LOG_CAPACITY: usize = 1000; // Lines kept in the in-memory log
+
+thread_local! {
+    static SESSION: RefCell<UciSession> = RefCell::new(UciSession::new(None));
+    static CACHE: Arc<RwLock<Cache>> = Arc::new(RwLock::new(Cache::default()));
+    static OUTPUT: RefCell<Option<js_sys::Function>> = const { RefCell::new(None) };
+}
+
+static LOG: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+static LOGGER: RingLogger = RingLogger;
+static LOGGER_INIT: Once = Once::new();
+
+// Replaces the log file, keeps the last LOG_CAPACITY lines around for `get_log`
+struct RingLogger;
+
+impl Log for RingLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= LevelFilter::Info
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let mut log = LOG.lock();
+        if log.len() == LOG_CAPACITY {
+            log.pop_front();
+        }
+        log.push_back(format!("[{}] {}", record.level(), record.args()));
+    }
+
+    fn flush(&self) {}
+}
+
+fn init_logging() {
+    LOGGER_INIT.call_once(|| {
+        if log::set_logger(&LOGGER).is_ok() {
+            log::set_max_level(LevelFilter::Info);
+        }
+    });
+}
+
+#[wasm_bindgen]
+pub fn set_output_callback(callback: js_sys::Function) {
+    OUTPUT.with(|output| *output.borrow_mut() = Some(callback));
+}
+
+#[wasm_bindgen]
+pub fn post_command(line: &str) {
+    init_logging();
+    info!("Received << {}", line);
+
+    let reply = SESSION.with(|session| session.borrow_mut().handle(line));
+    match reply {
+        Some(Reply::Output(out)) => send(&out),
+        Some(Reply::Search(request)) => run_search(*request, |out| send(&out)),
+        Some(Reply::Quit) | None => {}
+    }
+}
+
+#[wasm_bindgen]
+pub fn get_log() -> String {
+    let log = LOG.lock();
+    log.iter().cloned().collect::<Vec<String>>().join("\n")
+}
+
+fn run_search(mut request: SearchRequest, output: impl FnOnce(String)) {
+    // No cache thread here, writes queued during the search are applied once it has returned
+    let (cache_settings, apply_writes) = CACHE.with(deferred_cache);
+    request.settings.cache_settings = Some(cache_settings);
+    request.deferred_cache = Some(apply_writes);
+    request.run(output);
+}
+
+fn send(out: &str) {
+    info!("Sent >> {}", out);
+    OUTPUT.with(|output| {
+        if let Some(callback) = output.borrow().as_ref() {
+            for line in out.lines() {
+                let _ = callback.call1(&JsValue::NULL, &JsValue::from_str(line));
+            }
+        }
+    });
+}
+
+#[cfg(all(test, target_arch = "wasm32"))]
+mod test {
+    use super::*;
+    use crate::version::VERSION;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    thread_local! {
+        static RECEIVED: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+    }
+
+    // Starts collecting output from here on
+    fn collect_output() {
+        RECEIVED.with(|received| received.borrow_mut().clear());
+        let callback = Closure::<dyn Fn(String)>::new(|line: String| {
+            RECEIVED.with(|received| received.borrow_mut().push(line))
+        });
+        set_output_callback(
+            callback
+                .as_ref()
+                .unchecked_ref::<js_sys::Function>()
+                .clone(),
+        );
+        callback.forget();
+    }
+
+    #[wasm_bindgen_test]
+    fn test_uci_position_go() {
+        collect_output();
+        post_command("uci");
+        post_command("isready");
+        post_command("position startpos moves e2e4");
+        post_command("go movetime 50");
+
+        let received = RECEIVED.with(|received| received.borrow().clone());
+        assert_eq!(received[0], format!("id name Shallow Red {}", VERSION));
+        assert_eq!(
+            received[1..32],
+            [
+                "id author 15jgme",
+                "option name Startup Warmup type check default false",
+                "option name Bullet Fast Path type check default false",
+                "option name Repertoire File type string default <empty>",
+                "option name Capture File type string default <empty>",
+                "option name OwnBook type check default false",
+                "option name BookFile type string default <empty>",
+                "option name Book Selection type combo default weighted var best var weighted var uniform var min-weight 10",
+                "option name Persist Options type check default false",
+                "option name UCI_Opponent type string default <empty>",
+                "option name PGN File type string default <empty>",
+                "option name Career File type string default <empty>",
+                "option name Time Manager type combo default classic var classic var increment",
+                "option name Avoid Moves type string default <empty>",
+                "option name Full Reset type button",
+                "option name Hash type spin default 16 min 1 max 65536",
+                "option name Clear Hash type button",
+                "option name Threads type spin default 1 min 1 max 64",
+                "option name Ponder type check default false",
+                "option name UCI_Chess960 type check default false",
+                "option name UCI_ShowWDL type check default false",
+                "option name Move Overhead type spin default 0 min 0 max 5000",
+                "option name Safety Buffer type spin default 100 min 0 max 5000",
+                "option name Max Move Share type spin default 20 min 1 max 100",
+                "option name SlowMover type spin default 100 min 10 max 1000",
+                "option name Minimum Thinking Time type spin default 1000 min 0 max 5000",
+                "option name SyzygyPath type string default <empty>",
+                "option name UCI_LimitStrength type check default false",
+                "option name UCI_Elo type spin default 1500 min 800 max 2200",
+                "uciok",
+                "readyok"
+            ]
+        );
+        assert!(received[32].starts_with("bestmove "));
+        assert!(get_log().contains("Received << go movetime 50"));
+    }
+
+    // The clock goes through the time manager, which reads the time like everything else does
+    #[wasm_bindgen_test]
+    fn test_go_on_the_clock() {
+        collect_output();
+        post_command("ucinewgame");
+        post_command("position startpos moves e2e4 e7e5");
+        post_command("go wtime 2000 btime 2000 winc 10 binc 10");
+
+        let received = RECEIVED.with(|received| received.borrow().clone());
+        let bestmove = received.last().expect("no output");
+        assert!(bestmove.starts_with("bestmove "), "{:?}", received);
+    }
+}